@@ -21,6 +21,8 @@ p2_table:
 p3_fb_table:           
 	resb 4096
 
+; no guard pages around the IST stacks: the kernel image is mapped with
+; 2 MiB pages
 align 16
 ist0_stack:
     resb 4096
@@ -30,6 +32,11 @@ ist1_stack:
     resb 4096
 ist1_stack_top:
 
+ist2_stack:
+    ; page faults of user processes write a core file on this stack
+    resb 16384
+ist2_stack_top:
+
 tss64:
     resb 104
    
@@ -54,8 +61,10 @@ global _start
 
 global ist0_stack
 global ist1_stack
+global ist2_stack
 global ist0_stack_top
 global ist1_stack_top
+global ist2_stack_top
 
 global tss64
 global dma
@@ -141,12 +150,12 @@ gdt_start:
     ; Kernel data segment (offset 0x10)
     dq 0x00AF92000000FFFF
 
-    ; User code segment (offset 0x18)
-    dq 0x00affa000000ffff
-
-    ; User data segment (offset 0x20)
+    ; User data segment (offset 0x18); SYSRET wants it right below user code
     dq 0x00aff2000000ffff
 
+    ; User code segment (offset 0x20)
+    dq 0x00affa000000ffff
+
 gdt_tss_descriptor:
     dw 0x0067                 ; limit
     dw 0                      ; base low 16 (will patch)
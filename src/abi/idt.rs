@@ -1,103 +1,212 @@
+use core::arch::naked_asm;
+
 use x86_64::{
     registers::control::Cr2,
-    structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode},
+    structures::idt::{InterruptDescriptorTable, InterruptStackFrame},
     VirtAddr,
 };
 
 use crate::{
     abi::syscall::{syscall_80h, syscall_handler},
-    task::scheduler,
+    backtrace, gdb,
+    mem::{pages, vm},
+    net::serial,
+    task::{
+        context::TrapFrame,
+        coredump, poll, process, scheduler,
+        signal::{self, SIGILL, SIGSEGV, SIGTRAP},
+    },
 };
 
 #[link_section = ".idt"]
 static mut IDT: InterruptDescriptorTable = InterruptDescriptorTable::new();
 
-#[no_mangle]
-#[link_section = ".text"]
-extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
-    error_code: PageFaultErrorCode,
-) {
-    error!("EXCEPTION: PAGE FAULT");
-    warn!("\nAccessed Address: ");
+/// Entry stub of an exception that is not resumed from.  It saves the
+/// registers as a TrapFrame and hands it to `fault_handler`, which does not
+/// return.  Exceptions without an error code push a 0 in its place.
+macro_rules! fault_stub {
+    ($name:ident, $vector:literal $(, $no_error:literal)?) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() -> ! {
+            naked_asm!(
+                $($no_error,)?
+                "push {vector}",
+                "push r15",
+                "push r14",
+                "push r13",
+                "push r12",
+                "push r11",
+                "push r10",
+                "push r9",
+                "push r8",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push rdx",
+                "push rcx",
+                "push rbx",
+                "push rax",
+                "cld",
+                // 22 quadwords pushed since the aligned interrupt entry, so
+                // RSP is 16-byte aligned for the call.
+                "mov rdi, rsp",
+                "call {handler}",
+                "ud2",
+                vector = const $vector,
+                handler = sym fault_handler,
+            );
+        }
+    };
+}
 
-    rprint!("EXCEPTION: PAGE FAULT\n");
+fault_stub!(invalid_opcode_stub, 6, "push 0");
+fault_stub!(general_protection_fault_stub, 13);
 
-    match Cr2::read() {
-        Ok(addr) => {
-            printx!(addr.as_u64());
-        }
-        Err(_) => {
-            warn!("Cannot read CR2");
+/// Entry stub of an exception that may be resumed from: #DB and #BP, and #PF
+/// for pages mapped on demand.  It saves the registers as a TrapFrame like
+/// `fault_stub`, and restores them from it once the handler returns, so a
+/// debugger can change them.
+macro_rules! trap_stub {
+    ($name:ident, $vector:literal, $handler:ident $(, $no_error:literal)?) => {
+        #[unsafe(naked)]
+        extern "C" fn $name() {
+            naked_asm!(
+                $($no_error,)?
+                "push {vector}",
+                "push r15",
+                "push r14",
+                "push r13",
+                "push r12",
+                "push r11",
+                "push r10",
+                "push r9",
+                "push r8",
+                "push rsi",
+                "push rdi",
+                "push rbp",
+                "push rdx",
+                "push rcx",
+                "push rbx",
+                "push rax",
+                "cld",
+                "mov rdi, rsp",
+                "call {handler}",
+                "pop rax",
+                "pop rbx",
+                "pop rcx",
+                "pop rdx",
+                "pop rbp",
+                "pop rdi",
+                "pop rsi",
+                "pop r8",
+                "pop r9",
+                "pop r10",
+                "pop r11",
+                "pop r12",
+                "pop r13",
+                "pop r14",
+                "pop r15",
+                // Vector and error code
+                "add rsp, 16",
+                "iretq",
+                vector = const $vector,
+                handler = sym $handler,
+            );
         }
+    };
+}
+
+trap_stub!(debug_stub, 1, trap_handler, "push 0");
+trap_stub!(breakpoint_stub, 3, trap_handler, "push 0");
+trap_stub!(page_fault_stub, 14, page_fault_handler);
+
+extern "C" fn trap_handler(frame: &mut TrapFrame) {
+    if gdb::trap(frame) {
+        return;
     }
 
-    warn!("\nError Code: ");
-    printn!(error_code.bits());
-    warn!("\nStack frame SP: ");
-    printx!(stack_frame.stack_pointer.as_u64());
+    // With no debugger attached, nothing expects the trap.
+    if frame.vector == 3 {
+        error!("EXCEPTION: BREAKPOINT");
+    } else {
+        error!("EXCEPTION: DEBUG");
+    }
+
+    warn!("\nRIP: ");
+    printx!(frame.rip);
     print!("\n\n");
 
-    unsafe {
-        scheduler::crash(0xff);
-        scheduler::wake(scheduler::get_shell_pid());
+    unsafe { fault_exit(frame, SIGTRAP) }
+}
 
-        // Re-enable interrupts so the PIT timer fires and the scheduler can
-        // switch to the shell.
-        core::arch::asm!("sti");
-        loop {
-            core::arch::asm!("hlt");
-        }
+/// Map the page a process touched for the first time, or end it if it has
+/// no business there.
+extern "C" fn page_fault_handler(frame: &mut TrapFrame) {
+    if unsafe { vm::handle_fault(Cr2::read_raw(), frame.error_code) } {
+        return;
     }
+
+    fault_handler(frame)
 }
 
-#[no_mangle]
-#[link_section = ".text"]
-extern "x86-interrupt" fn general_protection_fault_handler(
-    frame: InterruptStackFrame,
-    error_code: u64,
-) {
-    error!("EXCEPTION: GENERAL PROTECTION FAULT");
+extern "C" fn fault_handler(frame: &TrapFrame) -> ! {
+    let sig = match frame.vector {
+        14 => {
+            let addr = Cr2::read_raw();
 
-    warn!("\nError code: ");
-    printn!(error_code);
-    warn!("\nRIP: ");
-    printx!(frame.instruction_pointer.as_u64());
-    warn!("\nStack frame SP: ");
-    printx!(frame.stack_pointer.as_u64());
-    print!("\n\n");
+            // The kernel ran off its stack: nothing it was doing can be
+            // trusted, but the page-fault stack is still there to panic on.
+            if frame.cs & 3 == 0 && process::is_kernel_stack_guard(addr) {
+                match unsafe { scheduler::get_current_pid() } {
+                    0xff => panic!("kernel stack overflow (PID unknown), RIP {:#x}", frame.rip),
+                    pid => panic!("kernel stack overflow in PID {}, RIP {:#x}", pid, frame.rip),
+                }
+            }
 
-    unsafe {
-        scheduler::crash(0xff);
-        scheduler::wake(scheduler::get_shell_pid());
+            if is_user_fault(frame) && vm::is_stack_guard(addr) {
+                error!("STACK OVERFLOW");
+            } else if is_user_fault(frame) {
+                error!("SEGMENTATION FAULT");
+            } else {
+                error!("EXCEPTION: PAGE FAULT");
+            }
+            warn!("\nAccessed Address: ");
 
-        core::arch::asm!("sti");
-        loop {
-            core::arch::asm!("hlt");
+            rprint!("EXCEPTION: PAGE FAULT\n");
+
+            match Cr2::read() {
+                Ok(addr) => {
+                    printx!(addr.as_u64());
+                }
+                Err(_) => {
+                    warn!("Cannot read CR2");
+                }
+            }
+
+            warn!("\nError Code: ");
+            printn!(frame.error_code);
+            SIGSEGV
         }
-    }
-}
+        13 => {
+            error!("EXCEPTION: GENERAL PROTECTION FAULT");
 
-#[no_mangle]
-#[link_section = ".text"]
-extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFrame) {
-    error!("EXCEPTION: INVALID OPCODE");
+            warn!("\nError code: ");
+            printn!(frame.error_code);
+            SIGSEGV
+        }
+        _ => {
+            error!("EXCEPTION: INVALID OPCODE");
+            SIGILL
+        }
+    };
 
     warn!("\nRIP: ");
-    printx!(stack_frame.instruction_pointer.as_u64());
+    printx!(frame.rip);
     warn!("\nStack frame SP: ");
-    printx!(stack_frame.stack_pointer.as_u64());
+    printx!(frame.rsp);
     print!("\n\n");
 
-    unsafe {
-        scheduler::crash(0xff);
-        scheduler::wake(scheduler::get_shell_pid());
-
-        core::arch::asm!("sti");
-        loop {
-            core::arch::asm!("hlt");
-        }
-    }
+    unsafe { fault_exit(frame, sig) }
 }
 
 #[no_mangle]
@@ -118,16 +227,77 @@ extern "x86-interrupt" fn double_fault_handler(
     );*/
     print!("\n\n");
 
+    // The general purpose registers are lost by the time a double fault is
+    // raised; only the CPU's frame is known.
+    let frame = TrapFrame {
+        vector: 8,
+        error_code,
+        rip: stack_frame.instruction_pointer.as_u64(),
+        cs: stack_frame.code_segment.0 as u64,
+        rflags: stack_frame.cpu_flags.bits(),
+        rsp: stack_frame.stack_pointer.as_u64(),
+        ss: stack_frame.stack_segment.0 as u64,
+        ..TrapFrame::ZERO
+    };
+
     //keyboard_loop();
-    unsafe {
-        scheduler::crash(0xff);
-        scheduler::wake(scheduler::get_shell_pid());
+    unsafe { fault_exit(&frame, SIGSEGV) }
+}
+
+/// Deal with an exception that cannot be resumed from.  Raised by user code,
+/// or by a syscall following a bad user pointer, it ends the process that was
+/// running, reporting `sig` as a POSIX shell would, after writing its core
+/// file.  It then halts until the next PIT tick switches away from the
+/// process for good; the scheduler reaps it like any other exit.  Otherwise
+/// raised in ring 0, it leaves the kernel in an unknown state, so the system
+/// is halted.  An attached gdb is shown the fault first.
+unsafe fn fault_exit(frame: &TrapFrame, sig: u8) -> ! {
+    gdb::report_fault(frame, sig);
+
+    if !is_user_fault(frame) {
+        error!("Fault in kernel mode, system halted\n");
+
+        for b in b"Fault in kernel mode, system halted\n" {
+            serial::write(*b);
+        }
+
+        backtrace::print(frame.rip, frame.rbp);
 
-        core::arch::asm!("sti");
         loop {
-            core::arch::asm!("hlt");
+            core::arch::asm!("cli", "hlt");
         }
     }
+
+    let pid = scheduler::get_current_pid();
+
+    warn!("Process ");
+    printn!(pid as u64);
+
+    // Interrupts stay off while the process memory is dumped, so none of its
+    // threads runs again.  A bad pointer handed to a syscall leaves no user
+    // registers to dump.
+    if frame.cs & 3 == 3 && coredump::write(frame, Cr2::read_raw(), sig) {
+        warn!(" killed, core dumped to /mnt/fat/CORE\n");
+    } else {
+        warn!(" killed\n");
+    }
+
+    scheduler::exit_process(pid, signal::exit_code(sig));
+
+    core::arch::asm!("sti");
+    loop {
+        core::arch::asm!("hlt");
+    }
+}
+
+/// True if the process that was running is to blame for the fault: it was
+/// raised in user mode, or by the kernel touching a user address that is not
+/// part of the process on behalf of a syscall.
+fn is_user_fault(frame: &TrapFrame) -> bool {
+    frame.cs & 3 == 3
+        || (frame.vector == 14
+            && vm::in_user_window(Cr2::read_raw())
+            && unsafe { pages::read_cr3() as u64 != pages::KERNEL_CR3 })
 }
 
 pub fn load_idt() {
@@ -157,10 +327,32 @@ extern "x86-interrupt" fn keyboard_handler(_stack: InterruptStackFrame) {
         }
     }
 
+    // Keyboard standard input is polled through a file descriptor.
+    scheduler::wake_pollers(poll::kind_bit(poll::POLL_KEYBOARD) | poll::kind_bit(poll::POLL_FD));
+
     // Acknowledge the PIC
     crate::input::port::write(0x20, 0x20);
 }
 
+#[no_mangle]
+#[link_section = ".text"]
+extern "x86-interrupt" fn mouse_handler(_stack: InterruptStackFrame) {
+    let status = crate::input::port::read_u8(0x64);
+
+    // Always drain the data port, or the 8042 stops raising IRQs.
+    let data = crate::input::port::read_u8(0x60);
+
+    // Bit 5 marks auxiliary device data; anything else is not for us.
+    if status & 0x20 != 0 {
+        crate::input::mouse::push_byte(data);
+        scheduler::wake_pollers(poll::kind_bit(poll::POLL_MOUSE));
+    }
+
+    // Acknowledge both PICs
+    crate::input::port::write(0xA0, 0x20);
+    crate::input::port::write(0x20, 0x20);
+}
+
 #[no_mangle]
 #[link_section = ".text"]
 extern "x86-interrupt" fn floppy_drive_handler(_stack: InterruptStackFrame) {
@@ -171,19 +363,33 @@ extern "x86-interrupt" fn floppy_drive_handler(_stack: InterruptStackFrame) {
 #[expect(static_mut_refs)]
 /// https://phrack.org/issues/59/4
 pub fn install_isrs() {
-    unsafe { IDT.invalid_opcode.set_handler_fn(invalid_opcode_handler) };
+    unsafe {
+        IDT.debug
+            .set_handler_addr(VirtAddr::new(debug_stub as *const () as u64))
+    };
+    // int3 is allowed in user code, for breakpoints set by gdb.
+    unsafe {
+        IDT.breakpoint
+            .set_handler_addr(VirtAddr::new(breakpoint_stub as *const () as u64))
+            .set_privilege_level(x86_64::PrivilegeLevel::Ring3)
+    };
+    unsafe {
+        IDT.invalid_opcode
+            .set_handler_addr(VirtAddr::new(invalid_opcode_stub as *const () as u64))
+    };
     unsafe {
         IDT.double_fault
             .set_handler_fn(double_fault_handler)
             .set_stack_index(1)
     };
     unsafe {
-        IDT.general_protection_fault
-            .set_handler_fn(general_protection_fault_handler)
+        IDT.general_protection_fault.set_handler_addr(VirtAddr::new(
+            general_protection_fault_stub as *const () as u64,
+        ))
     };
     unsafe {
         IDT.page_fault
-            .set_handler_fn(page_fault_handler)
+            .set_handler_addr(VirtAddr::new(page_fault_stub as *const () as u64))
             .set_stack_index(2)
     };
 
@@ -191,6 +397,7 @@ pub fn install_isrs() {
     unsafe { IDT[0x20].set_handler_addr(VirtAddr::new(timer_interrupt_stub as *const () as u64)) };
     unsafe { IDT[0x21].set_handler_fn(keyboard_handler) };
     unsafe { IDT[0x26].set_handler_fn(floppy_drive_handler) };
+    unsafe { IDT[0x2C].set_handler_fn(mouse_handler) };
     /*unsafe {
         IDT[0x7f]
             .set_handler_fn(syscall_handler)
@@ -1,2 +1,3 @@
 pub mod idt;
+pub mod strace;
 pub mod syscall;
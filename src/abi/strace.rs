@@ -0,0 +1,227 @@
+//! Syscall tracing for the `strace` shell command.  A traced process gets one
+//! line per syscall it makes, with the decoded name, the arguments and the
+//! return code, on the console or appended to `STRACE.TXT` in the FAT12 root.
+
+use core::fmt::{self, Write};
+
+use crate::{
+    abi::syscall::SyscallReturnCode,
+    fs::fat12::{block::Floppy, fs::Filesystem},
+    video::vga::{self, Color},
+};
+
+/// Where the trace lines of a process go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trace {
+    Console,
+    File,
+}
+
+/// Trace file in the FAT12 root directory.
+pub const TRACE_FILE: [u8; 11] = *b"STRACE  TXT";
+
+/// Name and argument count of each syscall; unknown numbers are shown by
+/// number with all six arguments.
+const SYSCALLS: &[(u64, &str, usize)] = &[
+    (0x00, "exit", 2),
+    (0x01, "sysinfo", 2),
+    (0x02, "rtc_time", 2),
+    (0x03, "input_pipe", 2),
+    (0x04, "ticks", 2),
+    (0x05, "sleep", 2),
+    (0x06, "set_priority", 2),
+    (0x07, "wait", 2),
+    (0x08, "spawn", 2),
+    (0x0a, "malloc", 2),
+    (0x0b, "realloc", 2),
+    (0x0f, "free", 2),
+    (0x10, "print", 2),
+    (0x11, "clear_screen", 2),
+    (0x12, "put_pixel", 2),
+    (0x13, "render_mode13h", 2),
+    (0x14, "map_vga", 2),
+    (0x15, "set_video_mode", 2),
+    (0x16, "vesa_info", 2),
+    (0x17, "vesa_blit", 2),
+    (0x18, "get_font", 2),
+    (0x19, "read_stdin", 2),
+    (0x1a, "play_freq", 2),
+    (0x1b, "play_audio", 2),
+    (0x1f, "stop_speaker", 2),
+    (0x20, "read_file", 2),
+    (0x21, "write_file", 2),
+    (0x22, "rename", 2),
+    (0x23, "delete", 2),
+    (0x24, "read_fat", 2),
+    (0x25, "write_fat", 2),
+    (0x26, "insert_entry", 2),
+    (0x27, "mkdir", 2),
+    (0x28, "list_dir", 2),
+    (0x29, "run_bin", 2),
+    (0x2a, "run_elf", 2),
+    (0x2b, "fsck", 2),
+    (0x2c, "list_mounts", 2),
+    (0x2d, "list_path", 2),
+    (0x2e, "chdir", 2),
+    (0x2f, "list_tasks", 2),
+    (0x30, "port_out", 2),
+    (0x31, "port_in", 2),
+    (0x32, "serial", 2),
+    (0x33, "create_packet", 2),
+    (0x34, "send_packet", 2),
+    (0x35, "net_receive", 3),
+    (0x36, "net_send", 3),
+    (0x37, "net_bind", 2),
+    (0x38, "net_status", 2),
+    (0x40, "sigaction", 2),
+    (0x41, "sigreturn", 2),
+    (0x42, "kill", 2),
+    (0x43, "alarm", 2),
+    (0x44, "thread_create", 2),
+    (0x45, "thread_exit", 2),
+    (0x46, "thread_join", 2),
+    (0x47, "futex_wait", 2),
+    (0x48, "futex_wake", 2),
+    (0x49, "port_create", 2),
+    (0x4a, "port_destroy", 2),
+    (0x4b, "port_send", 2),
+    (0x4c, "port_receive", 2),
+    (0x4d, "port_call", 2),
+    (0x4e, "port_reply", 2),
+    (0x4f, "service_register", 2),
+    (0x50, "service_lookup", 2),
+    (0x51, "service_unregister", 2),
+    (0x52, "shm_create", 3),
+    (0x53, "shm_open", 2),
+    (0x54, "shm_map", 2),
+    (0x55, "shm_unmap", 2),
+    (0x56, "open", 2),
+    (0x57, "read", 2),
+    (0x58, "write", 2),
+    (0x59, "seek", 2),
+    (0x5a, "stat", 2),
+    (0x5b, "close", 2),
+    (0x5c, "poll", 2),
+    (0x5d, "brk", 1),
+    (0x5e, "sbrk", 1),
+    (0x5f, "mmap", 4),
+    (0x60, "munmap", 2),
+];
+
+/// Syscalls that do not return to the caller when they succeed; they are
+/// logged before they run, with `?` for the return code.
+pub fn returns(syscall_no: u64) -> bool {
+    !matches!(syscall_no, 0x00 | 0x45)
+}
+
+/// A trace line, cut short if it does not fit.
+struct Line {
+    buf: [u8; 160],
+    len: usize,
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+
+        Ok(())
+    }
+}
+
+/// Log syscall `syscall_no` of `pid` with its argument registers and the
+/// value it returned, None if it does not return.
+pub fn log(trace: Trace, pid: usize, syscall_no: u64, args: &[u64; 6], ret: Option<u64>) {
+    let mut line = Line {
+        buf: [0; 160],
+        len: 0,
+    };
+
+    let _ = format(&mut line, pid, syscall_no, args, ret);
+
+    // Keep the newline even if the line was cut.
+    if line.len == line.buf.len() {
+        line.len -= 1;
+    }
+    line.buf[line.len] = b'\n';
+    line.len += 1;
+
+    let text = core::str::from_utf8(&line.buf[..line.len]).unwrap_or("?\n");
+
+    match trace {
+        Trace::Console => {
+            if let Some(mut writer) = vga::get_writer() {
+                writer.set_color_num(Color::DarkCyan as u8, Color::Black as u8);
+                writer.write_str_raw(text);
+            }
+        }
+        Trace::File => append(text.as_bytes()),
+    }
+}
+
+fn format(
+    w: &mut Line,
+    pid: usize,
+    syscall_no: u64,
+    args: &[u64; 6],
+    ret: Option<u64>,
+) -> fmt::Result {
+    write!(w, "[{}] ", pid)?;
+
+    let count = match SYSCALLS.iter().find(|(no, _, _)| *no == syscall_no) {
+        Some((_, name, count)) => {
+            w.write_str(name)?;
+            *count
+        }
+        None => {
+            write!(w, "syscall_{:#x}", syscall_no)?;
+            args.len()
+        }
+    };
+
+    w.write_str("(")?;
+    for (i, arg) in args.iter().take(count).enumerate() {
+        if i > 0 {
+            w.write_str(", ")?;
+        }
+        write!(w, "{:#x}", arg)?;
+    }
+    w.write_str(") = ")?;
+
+    match ret {
+        Some(ret) => match SyscallReturnCode::name(ret) {
+            Some(name) => write!(w, "{:#x} {}", ret, name),
+            None => write!(w, "{:#x}", ret),
+        },
+        None => w.write_str("?"),
+    }
+}
+
+/// Empty the trace file, creating it if needed.  Returns false if the FAT12
+/// filesystem cannot be read.
+pub fn open_file() -> bool {
+    let floppy = Floppy::init();
+    let Ok(fs) = Filesystem::new(&floppy) else {
+        return false;
+    };
+
+    if !fs.truncate_file(0, &TRACE_FILE) {
+        fs.create_file(0, &TRACE_FILE);
+    }
+
+    fs.find_entry(0, &TRACE_FILE).is_some()
+}
+
+/// Append to the trace file; lines are lost if it is gone.
+fn append(data: &[u8]) {
+    let floppy = Floppy::init();
+    let Ok(fs) = Filesystem::new(&floppy) else {
+        return;
+    };
+
+    if let Some(entry) = fs.find_entry(0, &TRACE_FILE) {
+        fs.write_at(0, &TRACE_FILE, entry.file_size, data);
+    }
+}
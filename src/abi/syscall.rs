@@ -1,38 +1,73 @@
+use alloc::vec::Vec;
 use core::{arch::naked_asm, ptr::copy_nonoverlapping};
 
 use x86_64::structures::idt::InterruptStackFrame;
 
 use crate::{
+    abi::strace,
     fs::{
         block::BlockDevice,
-        fat12::{
-            block::Floppy,
-            check,
-            fs::{fat83, Filesystem},
-        },
+        fat12::{check, fs::fat83},
+        file::{self, File, FileError, Node},
         iso9660::Iso9660,
         vfs,
     },
     init::config::SYSTEM_CONFIG,
-    input::{elf, irq},
-    mem::uheap,
+    input::{elf, irq, keyboard, mouse},
+    mem::{pages, shm, uheap, vm},
     net::{icmp, ipv4, serial, tcp},
     task::{
-        queue::Message,
-        scheduler::{self},
+        context::InterruptFrame,
+        poll::{self, PollSource, MAX_POLL_SOURCES},
+        process::{DEFAULT_PRIORITY, PRIORITY_LEVELS},
+        queue::{Message, MAX_MSG_SIZE},
+        scheduler::{self, FutexWait, IpcError},
+        service::{self, NAME_LEN},
+        signal::{SigAction, SIG_IGN},
     },
     time::rtc,
 };
 
-const USERLAND_START: u64 = 0x600_000;
-// Must match USERLAND_END in src/input/elf.rs.  Stacks are placed at
-// 0x840_000–0x8C0_000, so any pointer from a user stack lives above the
-// old 0x800_000 ceiling and was incorrectly rejected by every syscall.
-const USERLAND_END: u64 = 0xA00_000;
+/// Arg1 of syscall 0x06 naming the calling process.  Not a valid PID, so
+/// that every process, PID 0 included, can be named.
+const PID_SELF: u64 = u64::MAX;
+
+/// Flag in Arg2 of syscall 0x5C: return at once if no source is ready.
+const POLL_NOWAIT: u64 = 1 << 16;
+
+/// Flag in Arg2 of syscall 0x2F selecting entries with CPU accounting.
+const TASK_LIST_STATS: u64 = 1 << 32;
+
+/// Flag in Arg2 of syscall 0x50 to wait until the name is registered.
+const LOOKUP_WAIT: u64 = 1;
+
+/// Flag in Arg2 of syscall 0x54 to map a region writable, and in Arg3 of
+/// syscall 0x52 to let processes that open it do so.
+const SHM_WRITE: u64 = 1;
+
+/// Flag in Arg2 of syscall 0x5F to map memory writable.
+const MAP_WRITE: u64 = 1;
+
+/// Flag in Arg2 of syscall 0x5F to map a file instead of zeroed memory.
+const MAP_FILE: u64 = 2;
+
+/// Buffer size of syscalls 0x35 and 0x36 when Arg3 is 0, as the callers
+/// written before it was an argument assume.
+const SOCKET_BUF_LEGACY: usize = 512;
+
+/// Most bytes syscall 0x19 returns at once, so that a byte count is never
+/// mistaken for a return code.
+const STDIN_READ_MAX: usize = 0x80;
 
 #[repr(u64)]
-enum SyscallReturnCode {
+pub(crate) enum SyscallReturnCode {
     Ok = 0x00,
+    Interrupted = 0xf5,
+    BrokenPipe = 0xf6,
+    TryAgain = 0xf7,
+    TimedOut = 0xf8,
+    OutOfMemory = 0xf9,
+    InvalidExecutable = 0xfa,
     NotImplemented = 0xfb,
     InvalidInput = 0xfc,
     FilesystemError = 0xfd,
@@ -40,67 +75,231 @@ enum SyscallReturnCode {
     InvalidSyscall = 0xff,
 }
 
-static mut MSG_BUF: [[u8; 512]; 10] = [
-    [0; 512], [0; 512], [0; 512], [0; 512], [0; 512], [0; 512], [0; 512], [0; 512], [0; 512],
-    [0; 512],
-];
+impl SyscallReturnCode {
+    /// Name of the error code `value`, or None if it is not one.
+    pub(crate) fn name(value: u64) -> Option<&'static str> {
+        let name = match value {
+            0xf5 => "Interrupted",
+            0xf6 => "BrokenPipe",
+            0xf7 => "TryAgain",
+            0xf8 => "TimedOut",
+            0xf9 => "OutOfMemory",
+            0xfa => "InvalidExecutable",
+            0xfb => "NotImplemented",
+            0xfc => "InvalidInput",
+            0xfd => "FilesystemError",
+            0xfe => "FileNotFound",
+            0xff => "InvalidSyscall",
+            _ => return None,
+        };
+
+        Some(name)
+    }
+}
+
+/// Scratch slot for the user RSP between the `syscall` instruction and the
+/// push onto the kernel stack.  Interrupts stay masked in between (see the
+/// SFMASK in init::cpu), and there is one CPU, so one slot is enough.
+static mut SYSCALL_USER_RSP: u64 = 0;
 
 /// This function is the syscall ABI dispatching routine. It is called exclusively from the ISR
-/// for interrupt 0x7f.
+/// for interrupt 0x7f, the compatibility path next to `syscall_entry`.
 #[unsafe(naked)]
 pub extern "x86-interrupt" fn syscall_handler(_: InterruptStackFrame) -> ! {
     naked_asm!(
-        "mov rcx, rdx",
         "cld",
 
-        "push rax",
-        "push rbx",
-        "push rcx",
-        "push rdx",
+        // Same order as timer_interrupt_stub, so the saved registers form an
+        // InterruptFrame.  The interrupt entry leaves RSP 16-byte aligned
+        // after the 15 pushes, as the call below needs.
+        "push r15",
+        "push r14",
+        "push r13",
+        "push r12",
+        "push r11",
+        "push r10",
+        "push r9",
+        "push r8",
         "push rsi",
         "push rdi",
         "push rbp",
-        "push r8",
-        //"push r9",
-        "push r10",
+        "push rdx",
+        "push rcx",
+        "push rbx",
+        "push rax",
+
+        "mov rdi, rsp",
+
+        "call {syscall_inner}",
+
+        // Return the result in the caller's RAX.
+        "mov [rsp], rax",
+
+        // Enter a signal handler instead if one is due.
+        "cli",
+        "mov rdi, rsp",
+        "call {deliver_signal}",
+
+        "pop rax",
+        "pop rbx",
+        "pop rcx",
+        "pop rdx",
+        "pop rbp",
+        "pop rdi",
+        "pop rsi",
+        "pop r8",
+        "pop r9",
+        "pop r10",
+        "pop r11",
+        "pop r12",
+        "pop r13",
+        "pop r14",
+        "pop r15",
+
+        "iretq",
+        syscall_inner = sym syscall_inner,
+        deliver_signal = sym scheduler::deliver_signal,
+    );
+}
+
+/// Entry point of the `syscall` instruction, set in IA32_LSTAR by init::cpu.
+/// The CPU leaves the return RIP in RCX and RFLAGS in R11 and does not switch
+/// stacks, so the user RSP is swapped for the kernel stack of the process
+/// (TSS RSP0) and an iretq frame is built there by hand.  From then on it is
+/// the same InterruptFrame as behind `int 0x7f`.
+///
+/// Returns with `sysretq` unless the frame no longer looks like the one built
+/// here, e.g. after a signal handler was entered or `sigreturn` restored an
+/// interrupted context: SYSRET can only restore RIP from RCX and RFLAGS from
+/// R11, so anything else goes out through `iretq`.
+#[unsafe(naked)]
+pub unsafe extern "C" fn syscall_entry() -> ! {
+    naked_asm!(
+        "mov [rip + {user_rsp}], rsp",
+        "mov rsp, [rip + {tss} + 4]",
+
+        "push {ss}",
+        "push qword ptr [rip + {user_rsp}]",
         "push r11",
-        "push r12",
-        "push r13",
-        "push r14",
+        "push {cs}",
+        "push rcx",
+
+        "cld",
+
         "push r15",
+        "push r14",
+        "push r13",
+        "push r12",
+        "push r11",
+        "push r10",
+        "push r9",
+        "push r8",
+        "push rsi",
+        "push rdi",
+        "push rbp",
+        "push rdx",
+        "push rcx",
+        "push rbx",
+        "push rax",
 
-        "sub rsp, 8",
-        "mov rdx, rax",
+        "mov rdi, rsp",
 
         "call {syscall_inner}",
 
-        "mov r9, rax",
-        "add rsp, 8",
+        "mov [rsp], rax",
+
+        "cli",
+        "mov rdi, rsp",
+        "call {deliver_signal}",
+
+        // SYSRET is fine if the saved RCX equals the RIP and R11 the RFLAGS
+        // to return to, CS is the user one and the RIP a canonical user
+        // address: SYSRET to anything else faults in ring 0, on the user
+        // stack.  ZF is set then, and the pops below keep it.
+        "mov rax, [rsp + 120]",
+        "mov rcx, rax",
+        "shr rcx, 47",
+        "xor rax, [rsp + 16]",
+        "or rax, rcx",
+        "mov rcx, [rsp + 136]",
+        "xor rcx, [rsp + 80]",
+        "or rax, rcx",
+        "mov rcx, [rsp + 128]",
+        "xor rcx, {cs}",
+        "or rax, rcx",
 
-        "pop r15",
-        "pop r14",
-        "pop r13",
-        "pop r12",
-        "pop r11",
-        "pop r10",
-        //"pop r9",
-        "pop r8",
+        "pop rax",
+        "pop rbx",
+        "pop rcx",
+        "pop rdx",
         "pop rbp",
         "pop rdi",
         "pop rsi",
-        "pop rdx",
-        "pop rcx",
-        "pop rbx",
-        "pop rax",
+        "pop r8",
+        "pop r9",
+        "pop r10",
+        "pop r11",
+        "pop r12",
+        "pop r13",
+        "pop r14",
+        "pop r15",
 
-        "mov rax, r9",
+        "jnz 2f",
+        "mov rsp, [rsp + 24]",
+        "sysretq",
 
+        "2:",
         "iretq",
+        user_rsp = sym SYSCALL_USER_RSP,
+        tss = sym TSS64,
+        ss = const scheduler::USER_SS,
+        cs = const scheduler::USER_CS,
         syscall_inner = sym syscall_inner,
+        deliver_signal = sym scheduler::deliver_signal,
     );
 }
 
-extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
+extern "C" {
+    /// The TSS of boot.asm; RSP0 is its u64 at offset 4, kept by the scheduler
+    /// at the top of the running process's kernel stack.
+    #[link_name = "tss64"]
+    static TSS64: crate::init::idt::Tss64;
+}
+
+/// `frame` holds the registers of the caller, restored when the syscall
+/// returns.  The syscall number is in RAX and the arguments in RDI, RSI, RDX,
+/// R10, R8 and R9, whether it came through `syscall` or `int 0x7f`.
+extern "C" fn syscall_inner(frame: *mut InterruptFrame) -> u64 {
+    let Some(trace) = (unsafe { scheduler::trace() }) else {
+        return dispatch(frame);
+    };
+
+    let (pid, syscall_no, args) = unsafe {
+        let f = &*frame;
+        (
+            scheduler::get_current_pid(),
+            f.rax,
+            [f.rdi, f.rsi, f.rdx, f.r10, f.r8, f.r9],
+        )
+    };
+
+    if !strace::returns(syscall_no) {
+        strace::log(trace, pid, syscall_no, &args, None);
+    }
+
+    let ret = dispatch(frame);
+
+    strace::log(trace, pid, syscall_no, &args, Some(ret));
+
+    ret
+}
+
+fn dispatch(frame: *mut InterruptFrame) -> u64 {
+    let (syscall_no, arg1, arg2, arg3, arg4, arg5, arg6) = unsafe {
+        let f = &*frame;
+        (f.rax, f.rdi, f.rsi, f.rdx, f.r10, f.r8, f.r9)
+    };
+
     // Re-enable interrupts so the PIT timer can preempt long-running syscalls.
     // The scheduler uses try_lock, so a timer tick during a scheduler operation
     // will simply fail to acquire the lock and return the old RSP unchanged.
@@ -114,6 +313,14 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
     debugn!(arg1);
     debug!(", arg2: ");
     debugn!(arg2);
+    debug!(", arg3: ");
+    debugn!(arg3);
+    debug!(", arg4: ");
+    debugn!(arg4);
+    debug!(", arg5: ");
+    debugn!(arg5);
+    debug!(", arg6: ");
+    debugn!(arg6);
     debug!("\n");
 
     rprint!("syscall_handler: called: ");
@@ -130,6 +337,8 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *
          *  Arg1: any
          *  Arg2: program return code
+         *
+         *  Ends every thread of the calling process.
          */
         0x00 => {
             unsafe {
@@ -142,8 +351,8 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                 rprintn!(arg2);
                 rprint!("\n");
 
-                scheduler::kill(pid);
-                scheduler::wake(scheduler::get_shell_pid());
+                // The parent is woken once the process has been reaped.
+                scheduler::exit_process(pid, arg2);
 
                 core::arch::asm!("sti");
                 loop {
@@ -160,7 +369,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to system info struct (*mut SysInfo)
          */
         0x01 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -215,7 +424,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to RTC structu (*mut RTC)
          */
         0x02 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -238,11 +447,12 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
         /*
          *  Syscall 0x03 --- Pipe subscription handling
          *
-         *  Arg1: op type
+         *  Arg1: op type (0x01-0x03 keyboard subscribe, unsubscribe, drain;
+         *        0x04-0x06 mouse subscribe, drain, unsubscribe)
          *  Arg2: pointer to circular buffer (*const u8)
          */
         0x03 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -269,6 +479,19 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                     }
                 }
 
+                0x04 => {
+                    mouse::mouse_subscribe(arg2);
+                }
+
+                0x05 => {
+                    let pid = unsafe { scheduler::get_current_pid() };
+                    mouse::mouse_drain(pid, arg2 as *mut u8, 15);
+                }
+
+                0x06 => {
+                    mouse::mouse_unsubscribe();
+                }
+
                 _ => {}
             }
         }
@@ -296,8 +519,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          */
         0x05 => {
             if arg1 > 0 {
-                let ticks = (arg1 + 9) / 10; // ceil(ms / 10)
-                let wake_tick = crate::time::acpi::get_tick_count() + ticks;
+                let wake_tick = crate::time::acpi::get_tick_count() + ms_to_ticks(arg1);
                 unsafe {
                     scheduler::sleep_current(wake_tick);
                 }
@@ -305,12 +527,129 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
         }
 
         /*
-         *  Syscall 0x0a --- Allocate memory from the userland heap
+         *  Syscall 0x06 --- Set process priority
+         *
+         *  Arg1: PID (PID_SELF = calling process)
+         *  Arg2: priority, 0 (highest) to 7 (lowest)
+         *
+         *  The process restarts at the given feedback queue level.  Read back
+         *  priorities through syscall 0x2F.
+         */
+        0x06 => {
+            if arg2 >= PRIORITY_LEVELS as u64 {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            unsafe {
+                let pid = if arg1 == PID_SELF {
+                    scheduler::get_current_pid()
+                } else {
+                    arg1 as usize
+                };
+
+                if !scheduler::set_priority(pid, arg2 as u8) {
+                    return SyscallReturnCode::InvalidInput as u64;
+                }
+            }
+        }
+
+        /*
+         *  Syscall 0x07 --- Wait for a child process to exit
+         *
+         *  Arg1: child PID (0x00 = any child)
+         *  Arg2: pointer to exit code (*mut u64), or 0x00 if not needed
+         *  Returns: PID of the exited child, or 0 if the caller has no such child
+         *
+         *  Blocks the calling process until the child is reaped.  A child
+         *  killed by a fault is reported with exit code 139 (page or
+         *  protection fault) or 132 (invalid opcode).
+         */
+        0x07 => {
+            if arg2 != 0 && !is_user_ptr(arg2) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let child = if arg1 == 0 { None } else { Some(arg1 as usize) };
+
+            unsafe {
+                let Some((pid, code)) = scheduler::wait(child) else {
+                    return 0;
+                };
+
+                if arg2 != 0 {
+                    *(arg2 as *mut u64) = code;
+                }
+
+                return pid as u64;
+            }
+        }
+
+        /*
+         *  Syscall 0x08 --- Spawn a process
+         *
+         *  Arg1: pointer to spawn request (*const SpawnRequest)
+         *  Arg2: pointer to PID of the new process (*mut u64)
+         *  Returns: 0x00 on success, or an error code
+         *
+         *  The path is a full VFS path (or relative to the current FAT12
+         *  directory).  argv and envp are NULL-terminated arrays of string
+         *  pointers; a NULL argv runs the program with argv[0] = path.  The
+         *  child inherits the caller's priority and is reported to it by 0x07.
+         */
+        0x08 => {
+            if !is_user_ptr(arg1) || !is_user_ptr(arg2) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let req = unsafe { (arg1 as *const SpawnRequest).read_unaligned() };
+            let (path_ptr, argv_ptr, envp_ptr) = (req.path, req.argv, req.envp);
+
+            if !is_user_ptr(path_ptr) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let path = unsafe { nul_terminated_slice(path_ptr as *const u8, 128) };
+
+            let (Some(mut argv), Some(envp)) =
+                (unsafe { user_str_array(argv_ptr) }, unsafe { user_str_array(envp_ptr) })
+            else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            if argv.is_empty() {
+                argv.push(path);
+            }
+
+            let priority = unsafe { scheduler::get_priority(scheduler::get_current_pid()) }
+                .unwrap_or(DEFAULT_PRIORITY);
+
+            match elf::spawn(path, &argv, &envp, priority) {
+                Ok(pid) => unsafe {
+                    *(arg2 as *mut u64) = pid as u64;
+                },
+                Err(e) => {
+                    return match e {
+                        elf::SpawnError::NotFound => SyscallReturnCode::FileNotFound,
+                        elf::SpawnError::Filesystem => SyscallReturnCode::FilesystemError,
+                        elf::SpawnError::InvalidExecutable => SyscallReturnCode::InvalidExecutable,
+                        elf::SpawnError::OutOfMemory => SyscallReturnCode::OutOfMemory,
+                        elf::SpawnError::IsDirectory | elf::SpawnError::TooManyArgs => {
+                            SyscallReturnCode::InvalidInput
+                        }
+                    } as u64;
+                }
+            }
+        }
+
+        /*
+         *  Syscall 0x0a --- Allocate memory from the process's heap
          *
          *  Arg1: size in bytes to allocate
          *  Arg2: unused (0x00)
-         *  Returns: virtual address of the allocated block (in 0xC00_000–0xFFF_FFF),
-         *           or 0x00 on failure.  The block is zeroed.
+         *  Returns: virtual address of the allocated block, or 0x00 on
+         *           failure.  The block is zeroed.
+         *
+         *  The heap grows by moving the program break (see 0x5E).
          */
         0x0a => {
             return uheap::malloc(arg1 as usize);
@@ -330,7 +669,8 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
         /*
          *  Syscall 0x0f --- Free a heap block
          *
-         *  Arg1: pointer to the block to free (must be in 0xC00_000–0xFFF_FFF)
+         *  Arg1: pointer to the block to free (anything that is not a block
+         *        of the caller's heap is ignored)
          *  Arg2: 0x00
          */
         0x0f => {
@@ -342,9 +682,14 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *
          *  Arg1: pointer to data (&[u8])
          *  Arg2: length in bytes to print
+         *  Returns: 0x00, or BrokenPipe if standard output is a pipe nobody
+         *  reads any more
+         *
+         *  Output stops at the first NUL byte.  When standard output is a
+         *  pipe, the call blocks while the pipe is full.
          */
         0x10 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -352,13 +697,9 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             let len = arg2 as usize;
             let slice = unsafe { core::slice::from_raw_parts(ptr, len) };
 
-            for &b in slice.iter() {
-                if b == b'\0' {
-                    break;
-                }
+            let end = slice.iter().position(|&b| b == b'\0').unwrap_or(len);
 
-                printb!(&[b]);
-            }
+            return unsafe { write_stdout(&slice[..end]) };
         }
 
         /*
@@ -401,7 +742,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: userland pointer to 768-byte palette (256 × RGB triplets), or 0 for default
          */
         0x13 => unsafe {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -415,7 +756,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             let pitch_px = fb.pitch / 4;
 
             /* Use caller-supplied palette if valid, else fall back to default VGA palette */
-            let use_custom = (USERLAND_START..=USERLAND_END).contains(&arg2);
+            let use_custom = is_user_ptr(arg2);
             let pal_ptr = if use_custom {
                 arg2 as *const u8
             } else {
@@ -451,7 +792,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  On success the virtual base (0xA00_000) is written to *arg2.
          */
         0x14 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -504,7 +845,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Returns: 0 on success, 1 if no framebuffer is available
          */
         0x16 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
             unsafe {
@@ -530,7 +871,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  hardware framebuffer.  One call per frame replaces per-pixel writes.
          */
         0x17 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
             unsafe {
@@ -538,6 +879,17 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                 if (*fb).addr == 0 || (*fb).width == 0 || (*fb).height == 0 {
                     return SyscallReturnCode::Ok as u64;
                 }
+
+                // The whole source image, at the size the mode reads it.
+                let src_len = if arg2 == 0 {
+                    (*fb).width as u64 * (*fb).height as u64 * 4
+                } else {
+                    ((arg2 >> 16) & 0xFFFF) * (arg2 & 0xFFFF) * 4
+                };
+                if src_len != 0 && !is_user_buffer(arg1, src_len) {
+                    return SyscallReturnCode::InvalidInput as u64;
+                }
+
                 let src_ptr = arg1 as *const u32;
                 let dst_ptr = (*fb).addr as *mut u32;
                 let pitch_px = ((*fb).pitch / 4) as usize;
@@ -584,7 +936,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Rows are 1 byte each; bit 7 (MSB) is the leftmost pixel (8px wide).
          */
         0x18 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
             let font = crate::init::font::PSF_FONT;
@@ -594,7 +946,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             let char_size = font[3] as usize;
             let glyph_data = &font[4..];
             let copy_len = (arg2 as usize).min(glyph_data.len());
-            if copy_len == 0 || char_size == 0 {
+            if copy_len == 0 || char_size == 0 || !is_user_buffer(arg1, copy_len as u64) {
                 return 0;
             }
             unsafe {
@@ -603,6 +955,34 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             return char_size as u64;
         }
 
+        /*
+         *  Syscall 0x19 --- Read from standard input
+         *
+         *  Arg1: pointer to buffer (*mut u8)
+         *  Arg2: buffer capacity in bytes
+         *  Returns: number of bytes read (at most STDIN_READ_MAX), 0 at end of
+         *  input
+         *
+         *  Blocks until input is available.  From a pipe, whatever is
+         *  buffered is returned, and end of input is reached once the writers
+         *  have exited.  From the keyboard, one line is read and echoed,
+         *  ending with '\n'; Ctrl+D on an empty line ends input.
+         */
+        0x19 => {
+            let cap = (arg2 as usize).min(STDIN_READ_MAX);
+
+            if cap == 0 || !is_user_buffer(arg1, cap as u64) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let buf = unsafe { core::slice::from_raw_parts_mut(arg1 as *mut u8, cap) };
+
+            return match unsafe { read_stdin(buf) } {
+                Ok(count) => count as u64,
+                Err(err) => ipc_error(err),
+            };
+        }
+
         /*
          *  Syscall 0x1a --- Play a frequency
          *
@@ -626,7 +1006,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to NUL-terminated file name (*const u8)
          */
         0x1b => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -637,14 +1017,14 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
 
             match arg1 {
                 0x01 => {
-                    let floppy = Floppy::init();
-                    match Filesystem::new(&floppy) {
-                        Ok(fs) => match fs.find_entry(base, &name83) {
-                            None => return SyscallReturnCode::FileNotFound as u64,
-                            Some(entry) => {
-                                fs.read_file(entry.start_cluster, &mut buf);
-                            }
-                        },
+                    let found = file::with_fat12(|fs| {
+                        fs.find_entry(base, &name83)
+                            .map(|entry| fs.read_file(entry.start_cluster, &mut buf))
+                    });
+
+                    match found {
+                        Ok(Some(_)) => {}
+                        Ok(None) => return SyscallReturnCode::FileNotFound as u64,
                         Err(_e) => {
                             rprint!(e);
                             rprint!("\n");
@@ -682,12 +1062,10 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Syscall 0x20 --- Read a file
          *
          *  Arg1: pointer to filename byte slice (&[u8])
-         *  Arg2: pointer to buffer (*mut [u8; 512])
+         *  Arg2: pointer to buffer (*mut u8), at least the file size long
          */
         0x20 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1)
-                || !(USERLAND_START..=USERLAND_END).contains(&arg2)
-            {
+            if !is_user_ptr(arg1) || !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -701,6 +1079,9 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                         None => return SyscallReturnCode::FileNotFound as u64,
                         Some(e) if e.is_dir => return SyscallReturnCode::InvalidInput as u64,
                         Some(e) => {
+                            if !is_user_buffer(arg2, e.size as u64) {
+                                return SyscallReturnCode::InvalidInput as u64;
+                            }
                             let buf_ptr = arg2 as *mut u8;
                             let buf = unsafe {
                                 core::slice::from_raw_parts_mut(buf_ptr, e.size as usize)
@@ -715,30 +1096,44 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             let (rel, base) = vfs_resolve_fat12(name_slice);
             let name83 = fat83(rel);
             let buf_ptr = arg2 as *mut u8;
-            let floppy = Floppy::init();
 
-            match Filesystem::new(&floppy) {
-                Ok(fs) => {
-                    let entry = match fs.find_entry(base, &name83) {
-                        Some(e) if e.attr & 0x10 == 0 => e,
-                        _ => return SyscallReturnCode::FileNotFound as u64,
-                    };
-                    let mut cluster = entry.start_cluster;
-                    let mut offset = 0u32;
-                    while offset < entry.file_size {
-                        let lba = fs.cluster_to_lba(cluster);
-                        let mut sector = [0u8; 512];
-                        fs.device.read_sector(lba, &mut sector);
-                        unsafe {
-                            copy_nonoverlapping(sector.as_ptr(), buf_ptr.add(offset as usize), 512);
-                        }
-                        cluster = fs.read_fat12_entry(cluster);
-                        if cluster >= 0xFF8 || cluster == 0 {
-                            break;
-                        }
-                        offset += 512;
+            // The sectors are gathered first: the user buffer must not be
+            // touched with the filesystem locked.
+            let data = file::with_fat12(|fs| {
+                let entry = match fs.find_entry(base, &name83) {
+                    Some(e) if e.attr & 0x10 == 0 => e,
+                    _ => return Err(SyscallReturnCode::FileNotFound),
+                };
+                let mut data = Vec::new();
+                if data
+                    .try_reserve_exact(entry.file_size.next_multiple_of(512) as usize)
+                    .is_err()
+                {
+                    return Err(SyscallReturnCode::FilesystemError);
+                }
+                let mut cluster = entry.start_cluster;
+                while (data.len() as u32) < entry.file_size {
+                    let lba = fs.cluster_to_lba(cluster);
+                    let mut sector = [0u8; 512];
+                    fs.device.read_sector(lba, &mut sector);
+                    data.extend_from_slice(&sector);
+                    cluster = fs.read_fat12_entry(cluster);
+                    if cluster >= 0xFF8 || cluster == 0 {
+                        break;
+                    }
+                }
+                data.truncate(entry.file_size as usize);
+                Ok(data)
+            });
+
+            match data {
+                Ok(Ok(data)) => {
+                    if !is_user_buffer(arg2, data.len() as u64) {
+                        return SyscallReturnCode::InvalidInput as u64;
                     }
+                    unsafe { copy_nonoverlapping(data.as_ptr(), buf_ptr, data.len()) };
                 }
+                Ok(Err(code)) => return code as u64,
                 Err(_e) => {
                     rprint!(e);
                     rprint!("\n");
@@ -754,9 +1149,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to byte buffer (*mut [u8; 512])
          */
         0x21 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1)
-                || !(USERLAND_START..=USERLAND_END).contains(&arg2)
-            {
+            if !is_user_ptr(arg1) || !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -766,13 +1159,10 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             }
             let (rel, base) = vfs_resolve_fat12(name_slice);
             let name83 = fat83(rel);
-            let buf_ptr = arg2 as *const [u8; 512];
-            let floppy = Floppy::init();
+            let data = unsafe { (arg2 as *const [u8; 512]).read_unaligned() };
 
-            match Filesystem::new(&floppy) {
-                Ok(fs) => unsafe {
-                    fs.write_file(base, &name83, &*buf_ptr);
-                },
+            match file::with_fat12(|fs| fs.write_file(base, &name83, &data)) {
+                Ok(_) => {}
                 Err(_e) => {
                     rprint!(e);
                     rprint!("\n");
@@ -788,9 +1178,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to new filename
          */
         0x22 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1)
-                || !(USERLAND_START..=USERLAND_END).contains(&arg2)
-            {
+            if !is_user_ptr(arg1) || !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -802,15 +1190,15 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             let (rel_old, base) = vfs_resolve_fat12(old_slice);
             let old83 = fat83(rel_old);
             let new83 = fat83(new_slice);
-            let floppy = Floppy::init();
 
-            match Filesystem::new(&floppy) {
-                Ok(fs) => {
-                    if fs.find_entry(base, &old83).is_none() {
-                        return SyscallReturnCode::FileNotFound as u64;
-                    }
-                    fs.rename_file(base, &old83, &new83);
-                }
+            let found = file::with_fat12(|fs| {
+                fs.find_entry(base, &old83)
+                    .map(|_| fs.rename_file(base, &old83, &new83))
+            });
+
+            match found {
+                Ok(Some(_)) => {}
+                Ok(None) => return SyscallReturnCode::FileNotFound as u64,
                 Err(_e) => {
                     rprint!(e);
                     rprint!("\n");
@@ -826,7 +1214,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: 0x00
          */
         0x23 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) || arg2 != 0 {
+            if !is_user_ptr(arg1) || arg2 != 0 {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -836,15 +1224,15 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             }
             let (rel, base) = vfs_resolve_fat12(name_slice);
             let name83 = fat83(rel);
-            let floppy = Floppy::init();
 
-            match Filesystem::new(&floppy) {
-                Ok(fs) => {
-                    if fs.find_entry(base, &name83).is_none() {
-                        return SyscallReturnCode::FileNotFound as u64;
-                    }
-                    fs.delete_file(base, &name83);
-                }
+            let found = file::with_fat12(|fs| {
+                fs.find_entry(base, &name83)
+                    .map(|_| fs.delete_file(base, &name83))
+            });
+
+            match found {
+                Ok(Some(_)) => {}
+                Ok(None) => return SyscallReturnCode::FileNotFound as u64,
                 Err(_e) => {
                     rprint!(e);
                     rprint!("\n");
@@ -893,9 +1281,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to new subdirectory name (*const u8)
          */
         0x27 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1)
-                || !(USERLAND_START..=USERLAND_END).contains(&arg2)
-            {
+            if !is_user_ptr(arg1) || !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -911,23 +1297,26 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             filename[8..11].copy_from_slice(&ext);
 
             let (rel, base) = vfs_resolve_fat12(parent_slice);
-            let floppy = Floppy::init();
 
-            match Filesystem::new(&floppy) {
-                Ok(fs) => {
-                    let parent_cluster: u16 = if rel.is_empty() {
-                        base
-                    } else {
-                        match fs.resolve_path_from(base, rel) {
-                            None => return SyscallReturnCode::FileNotFound as u64,
-                            Some(e) if e.attr & 0x10 == 0 => {
-                                return SyscallReturnCode::InvalidInput as u64
-                            }
-                            Some(e) => e.start_cluster,
+            let created = file::with_fat12(|fs| {
+                let parent_cluster: u16 = if rel.is_empty() {
+                    base
+                } else {
+                    match fs.resolve_path_from(base, rel) {
+                        None => return Err(SyscallReturnCode::FileNotFound),
+                        Some(e) if e.attr & 0x10 == 0 => {
+                            return Err(SyscallReturnCode::InvalidInput)
                         }
-                    };
-                    fs.create_subdirectory(&filename, parent_cluster);
-                }
+                        Some(e) => e.start_cluster,
+                    }
+                };
+                fs.create_subdirectory(&filename, parent_cluster);
+                Ok(())
+            });
+
+            match created {
+                Ok(Ok(())) => {}
+                Ok(Err(code)) => return code as u64,
                 Err(_e) => {
                     rprint!(e);
                     rprint!("\n");
@@ -950,28 +1339,29 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                 [crate::fs::fat12::entry::Entry::default(); 32];
             let mut offset = 0;
 
-            let floppy = Floppy::init();
-
-            match Filesystem::new(&floppy) {
-                Ok(fs) => {
-                    fs.for_each_entry(path, |entry| {
-                        if entry.name[0] == 0x00
-                            || entry.name[0] == 0xE5
-                            || entry.name[0] == 0xFF
-                            || entry.attr & 0x08 != 0
-                        {
-                            return;
-                        }
+            let listed = file::with_fat12(|fs| {
+                fs.for_each_entry(path, |entry| {
+                    if entry.name[0] == 0x00
+                        || entry.name[0] == 0xE5
+                        || entry.name[0] == 0xFF
+                        || entry.attr & 0x08 != 0
+                    {
+                        return;
+                    }
 
-                        if let Some(entry_mut) = kentries.get_mut(offset) {
-                            *entry_mut = *entry;
-                            offset += 1;
-                        }
-                    });
+                    if let Some(entry_mut) = kentries.get_mut(offset) {
+                        *entry_mut = *entry;
+                        offset += 1;
+                    }
+                })
+            });
 
-                    unsafe {
-                        core::ptr::copy_nonoverlapping(kentries.as_ptr(), entries, 32);
+            match listed {
+                Ok(_) => {
+                    if !is_user_buffer(arg2, core::mem::size_of_val(&kentries) as u64) {
+                        return SyscallReturnCode::InvalidInput as u64;
                     }
+                    unsafe { core::ptr::copy_nonoverlapping(kentries.as_ptr(), entries, 32) };
                 }
                 Err(_e) => {
                     rprint!(e);
@@ -1001,7 +1391,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Returns: PID on success, 0 on failure
          */
         0x2A => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1009,14 +1399,17 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
 
             // arg2: optional full args string matching push_user_args convention.
             // If absent or out of range, use the name as the sole argv[0] token.
-            let args_slice: &[u8] = if arg2 != 0 && (USERLAND_START..=USERLAND_END).contains(&arg2)
-            {
+            let args_slice: &[u8] = if arg2 != 0 && is_user_ptr(arg2) {
                 unsafe { nul_terminated_slice(arg2 as *const u8, 128) }
             } else {
                 name_slice
             };
 
-            let pid = elf::run_elf(name_slice, args_slice, elf::RunMode::Background);
+            // The child inherits the caller's priority.
+            let priority = unsafe { scheduler::get_priority(scheduler::get_current_pid()) }
+                .unwrap_or(DEFAULT_PRIORITY);
+
+            let pid = elf::run_elf(name_slice, args_slice, priority);
             return pid as u64;
         }
 
@@ -1027,11 +1420,15 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to FsckReport_T (4 × u64: errors, orphans, cross_linked, invalid)
          */
         0x2B => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_buffer(arg2, 4 * 8) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
-            let report = check::run_check();
+            // The check mounts the floppy itself; the lock keeps it from
+            // racing with other accesses.
+            let Ok(report) = file::with_fat12(|_| check::run_check()) else {
+                return SyscallReturnCode::FilesystemError as u64;
+            };
             let out = arg2 as *mut u64;
 
             unsafe {
@@ -1051,7 +1448,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Returns: number of mounts written
          */
         0x2C => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1095,9 +1492,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             // u64::MAX reads as -1 in C's int64_t — unambiguously not a valid count.
             const ERR: u64 = u64::MAX;
 
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1)
-                || !(USERLAND_START..=USERLAND_END).contains(&arg2)
-            {
+            if !is_user_ptr(arg1) || !is_user_ptr(arg2) {
                 return ERR;
             }
 
@@ -1125,6 +1520,9 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                         };
                         let mut entries = [crate::fs::iso9660::IsoEntry::default(); 64];
                         let count = iso.list_dir(dir.lba, dir.size, &mut entries);
+                        if !is_user_buffer(arg2, count as u64 * 38) {
+                            return ERR;
+                        }
                         for (i, e) in entries[..count].iter().enumerate() {
                             unsafe {
                                 let out = buf.add(i * 38);
@@ -1141,76 +1539,72 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
 
             // FAT12 branch.
             let (rel, base) = vfs_resolve_fat12(path);
-            let floppy = Floppy::init();
-            match Filesystem::new(&floppy) {
-                Err(_) => return ERR,
-                Ok(fs) => {
-                    let dir_cluster: u16 = if rel.is_empty() {
-                        base
-                    } else {
-                        match fs.resolve_path_from(base, rel) {
-                            None => return ERR,
-                            Some(e) if e.attr & 0x10 == 0 => return ERR,
-                            Some(e) => e.start_cluster,
-                        }
-                    };
+            let listed = file::with_fat12(|fs| {
+                let dir_cluster: u16 = if rel.is_empty() {
+                    base
+                } else {
+                    match fs.resolve_path_from(base, rel) {
+                        None => return None,
+                        Some(e) if e.attr & 0x10 == 0 => return None,
+                        Some(e) => e.start_cluster,
+                    }
+                };
+
+                // Collect FAT12 entries into a local array first.
+                let mut fat_entries = [crate::fs::fat12::entry::Entry::default(); 64];
+                let mut kcount = 0usize;
+                fs.for_each_entry(dir_cluster, |entry| {
+                    if entry.name[0] == 0x00 || entry.name[0] == 0xE5 {
+                        return;
+                    }
+                    if entry.attr & 0x08 != 0 {
+                        return;
+                    } // volume label
+                    if kcount < 64 {
+                        fat_entries[kcount] = *entry;
+                        kcount += 1;
+                    }
+                });
+                Some((fat_entries, kcount))
+            });
+            let Ok(Some((fat_entries, kcount))) = listed else {
+                return ERR;
+            };
+            if !is_user_buffer(arg2, kcount as u64 * 38) {
+                return ERR;
+            }
 
-                    // Collect FAT12 entries into a local array first.
-                    let mut fat_entries = [crate::fs::fat12::entry::Entry::default(); 64];
-                    let mut kcount = 0usize;
-                    fs.for_each_entry(dir_cluster, |entry| {
-                        if entry.name[0] == 0x00 || entry.name[0] == 0xE5 {
-                            return;
-                        }
-                        if entry.attr & 0x08 != 0 {
-                            return;
-                        } // volume label
-                        if kcount < 64 {
-                            fat_entries[kcount] = *entry;
-                            kcount += 1;
+            for (i, entry) in fat_entries[..kcount].iter().enumerate() {
+                unsafe {
+                    let out = buf.add(i * 38);
+                    let mut name_buf = [0u8; 32];
+                    let mut name_len = 0usize;
+                    // base name (trim trailing spaces)
+                    for j in 0..8usize {
+                        if entry.name[j] != b' ' {
+                            name_buf[name_len] = entry.name[j];
+                            name_len += 1;
                         }
-                    });
-
-                    for (i, entry) in fat_entries[..kcount].iter().enumerate() {
-                        unsafe {
-                            let out = buf.add(i * 38);
-                            let mut name_buf = [0u8; 32];
-                            let mut name_len = 0usize;
-                            // base name (trim trailing spaces)
-                            for j in 0..8usize {
-                                if entry.name[j] != b' ' {
-                                    name_buf[name_len] = entry.name[j];
-                                    name_len += 1;
-                                }
-                            }
-                            // extension (files only, trim spaces)
-                            if entry.attr & 0x10 == 0 && entry.ext[0] != b' ' {
-                                name_buf[name_len] = b'.';
+                    }
+                    // extension (files only, trim spaces)
+                    if entry.attr & 0x10 == 0 && entry.ext[0] != b' ' {
+                        name_buf[name_len] = b'.';
+                        name_len += 1;
+                        for j in 0..3usize {
+                            if entry.ext[j] != b' ' {
+                                name_buf[name_len] = entry.ext[j];
                                 name_len += 1;
-                                for j in 0..3usize {
-                                    if entry.ext[j] != b' ' {
-                                        name_buf[name_len] = entry.ext[j];
-                                        name_len += 1;
-                                    }
-                                }
                             }
-                            copy_nonoverlapping(name_buf.as_ptr(), out, 32);
-                            out.add(32).write_volatile(name_len as u8);
-                            out.add(33).write_volatile(if entry.attr & 0x10 != 0 {
-                                1u8
-                            } else {
-                                0u8
-                            });
-                            copy_nonoverlapping(
-                                entry.file_size.to_le_bytes().as_ptr(),
-                                out.add(34),
-                                4,
-                            );
                         }
                     }
-                    return kcount as u64;
+                    copy_nonoverlapping(name_buf.as_ptr(), out, 32);
+                    out.add(32).write_volatile(name_len as u8);
+                    out.add(33)
+                        .write_volatile(if entry.attr & 0x10 != 0 { 1u8 } else { 0u8 });
+                    copy_nonoverlapping(entry.file_size.to_le_bytes().as_ptr(), out.add(34), 4);
                 }
             }
+            return kcount as u64;
         }
 
         /*
@@ -1223,7 +1617,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  with the new path string and FAT12 cluster (0 for ISO9660 or FAT12 root).
          */
         0x2E => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1255,25 +1649,25 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
 
             // FAT12 (absolute /mnt/fat/... or relative to cwd)
             let (rel, base) = vfs_resolve_fat12(path);
-            let floppy = Floppy::init();
-            match Filesystem::new(&floppy) {
-                Err(_e) => {
-                    rprint!(e);
+            let cluster = file::with_fat12(|fs| {
+                if rel.is_empty() {
+                    return Ok(base);
+                }
+                match fs.resolve_path_from(base, rel) {
+                    None => Err(SyscallReturnCode::FileNotFound),
+                    Some(e) if e.attr & 0x10 == 0 => Err(SyscallReturnCode::InvalidInput),
+                    Some(e) => Ok(e.start_cluster),
+                }
+            });
+
+            match cluster {
+                Err(_e) => {
+                    rprint!(e);
                     rprint!("\n");
                     return SyscallReturnCode::FilesystemError as u64;
                 }
-                Ok(fs) => {
-                    let cluster: u16 = if rel.is_empty() {
-                        base
-                    } else {
-                        match fs.resolve_path_from(base, rel) {
-                            None => return SyscallReturnCode::FileNotFound as u64,
-                            Some(e) if e.attr & 0x10 == 0 => {
-                                return SyscallReturnCode::InvalidInput as u64
-                            }
-                            Some(e) => e.start_cluster,
-                        }
-                    };
+                Ok(Err(code)) => return code as u64,
+                Ok(Ok(cluster)) => {
                     if let Some(mut c) = SYSTEM_CONFIG.try_lock() {
                         c.set_path(path, cluster);
                     }
@@ -1284,20 +1678,23 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
         /*
          *  Syscall 0x2F --- List scheduler tasks
          *
-         *  Arg1: pointer to output buffer (max 10 × 20-byte TaskInfo entries)
-         *  Arg2: max entries to write (capped at 10)
+         *  Arg1: pointer to output buffer (24-byte TaskInfo entries)
+         *  Arg2: max entries to write in bits 0-31 (0 = 10, the legacy buffer
+         *        size); bit 32 set selects 48-byte TaskStats entries, which add
+         *        CPU ticks, blocked ticks and context switches
          *  Returns: number of entries written
          */
         0x2F => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            let stats = arg2 & TASK_LIST_STATS != 0;
+            let max = match arg2 & 0xFFFF_FFFF {
+                0 => 10,
+                n => n as usize,
+            };
+            let size = if stats { 48 } else { 24 };
+            if !is_user_buffer(arg1, (max * size) as u64) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
-            let max = if arg2 > 0 && (arg2 as usize) <= 10 {
-                arg2 as usize
-            } else {
-                10
-            };
-            let count = scheduler::list_tasks(arg1 as *mut u8, max);
+            let count = scheduler::list_tasks(arg1 as *mut u8, max, stats);
             return count as u64;
         }
 
@@ -1326,7 +1723,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to value (u64)
          */
         0x31 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1358,7 +1755,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                 // Read from UART — returns InvalidInput when no byte is ready,
                 // so userland can distinguish "no data" from a real read.
                 0x02 => {
-                    if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+                    if !is_user_ptr(arg2) {
                         return SyscallReturnCode::InvalidInput as u64;
                     }
 
@@ -1375,7 +1772,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
 
                 // Write to UART
                 0x03 => {
-                    if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+                    if !is_user_ptr(arg2) {
                         return SyscallReturnCode::InvalidInput as u64;
                     }
 
@@ -1399,7 +1796,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to buffer (*mut u8)
          */
         0x33 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1529,7 +1926,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg2: pointer to buffer (*const u8)
          */
         0x34 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            if !is_user_ptr(arg2) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1588,9 +1985,12 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Arg1: 0 = non-blocking (return 0 if queue empty),
          *        non-zero = blocking (suspend until frame arrives, legacy default)
          *  Arg2: pointer to a buffer
+         *  Arg3: buffer length (0x00 = 512)
+         *  Returns: bytes copied; the rest of a longer message is dropped
          */
         0x35 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            let len = socket_buf_len(arg3);
+            if !is_user_buffer(arg2, len as u64) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1601,13 +2001,13 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
                 let current_pid = scheduler::get_current_pid();
 
                 if let Some(msg) = scheduler::pop_msg(current_pid) {
-                    let len = if msg.port_id > 0 { msg.port_id } else { 512 };
-                    copy_nonoverlapping(msg.buf_addr as *const u8, buf, len);
-                    return len as u64;
+                    let copied = msg.data.len().min(len);
+                    copy_nonoverlapping(msg.data.as_ptr(), buf, copied);
+                    return copied as u64;
                 } else if non_blocking {
                     return 0;
                 } else {
-                    scheduler::block(current_pid, Message::new(0, 0xff, current_pid, 512));
+                    scheduler::block(current_pid);
                 }
             }
         }
@@ -1617,9 +2017,11 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *
          *  Arg1: op code
          *  Arg2: pointer to a buffer
+         *  Arg3: bytes to send, up to MAX_MSG_SIZE (0x00 = 512)
          */
         0x36 => {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg2) {
+            let len = socket_buf_len(arg3);
+            if len > MAX_MSG_SIZE || !is_user_buffer(arg2, len as u64) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
 
@@ -1627,10 +2029,12 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             let buf = arg2 as *const u8;
 
             unsafe {
-                copy_nonoverlapping(buf, MSG_BUF[0].as_mut_ptr(), 512);
                 let current_pid = scheduler::get_current_pid();
 
-                let msg = Message::new(0, current_pid, target_pid, 512);
+                let Some(msg) = Message::copy_from(0, current_pid, buf, len) else {
+                    return SyscallReturnCode::OutOfMemory as u64;
+                };
+
                 scheduler::push_msg(target_pid, msg);
                 scheduler::wake(target_pid);
             }
@@ -1664,7 +2068,7 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
          *  Port table comes from the kernel port-binding registry.
          */
         0x38 => unsafe {
-            if !(USERLAND_START..=USERLAND_END).contains(&arg1) {
+            if !is_user_ptr(arg1) {
                 return SyscallReturnCode::InvalidInput as u64;
             }
             let ns = arg1 as *mut NetStatus;
@@ -1681,6 +2085,804 @@ extern "C" fn syscall_inner(arg1: u64, arg2: u64, syscall_no: u64) -> u64 {
             core::ptr::write_unaligned(core::ptr::addr_of_mut!((*ns).ports), ports);
         },
 
+        /*
+         *  Syscall 0x40 --- Install a signal handler
+         *
+         *  Arg1: signal number
+         *  Arg2: pointer to the new action (*const SigAction)
+         *
+         *  The handler is 0x00 for the default action, 0x01 to ignore the
+         *  signal, or the address of a function taking the signal number.
+         *  It returns into the restorer, which must call syscall 0x41.
+         *  SIGKILL cannot be caught.
+         */
+        0x40 => {
+            if !is_user_ptr(arg2) || arg1 > u8::MAX as u64 {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let action = unsafe { (arg2 as *const SigAction).read_unaligned() };
+
+            if action.handler > SIG_IGN
+                && (!is_user_ptr(action.handler) || !is_user_ptr(action.restorer))
+            {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            unsafe {
+                let pid = scheduler::get_current_pid();
+
+                if !scheduler::set_action(pid, arg1 as u8, action) {
+                    return SyscallReturnCode::InvalidInput as u64;
+                }
+            }
+        }
+
+        /*
+         *  Syscall 0x41 --- Return from a signal handler
+         *
+         *  Arg1: 0x00 (unused)
+         *  Arg2: 0x00 (unused)
+         *
+         *  Only valid from the restorer a handler returns into.  Restores the
+         *  registers and signal mask saved when the handler was entered and
+         *  resumes the interrupted code; it does not return to the caller.
+         *  A user process without a valid SignalFrame is ended with exit
+         *  code 139, like a segmentation fault.
+         */
+        0x41 => {
+            return match unsafe { scheduler::sigreturn(frame) } {
+                Some(rax) => rax,
+                None if unsafe { (*frame).cs } == scheduler::USER_CS => unsafe {
+                    // Ended by sigreturn: wait to be reaped, like syscall 0x00.
+                    core::arch::asm!("sti");
+                    loop {
+                        core::arch::asm!("int 0x20");
+                        core::arch::asm!("hlt");
+                    }
+                },
+                None => SyscallReturnCode::InvalidInput as u64,
+            };
+        }
+
+        /*
+         *  Syscall 0x42 --- Send a signal
+         *
+         *  Arg1: PID (0x00 = calling process)
+         *  Arg2: signal number
+         */
+        0x42 => {
+            if arg2 > u8::MAX as u64 {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            unsafe {
+                let pid = if arg1 == 0 {
+                    scheduler::get_current_pid()
+                } else {
+                    arg1 as usize
+                };
+
+                if !scheduler::signal(pid, arg2 as u8) {
+                    return SyscallReturnCode::InvalidInput as u64;
+                }
+            }
+        }
+
+        /*
+         *  Syscall 0x43 --- Set an alarm
+         *
+         *  Arg1: delay in milliseconds (rounded up to the next 10 ms PIT tick), 0x00 cancels
+         *  Arg2: 0x00 (unused)
+         *  Returns: milliseconds left of the alarm it replaces, or 0
+         *
+         *  SIGALRM is sent to the calling process once the delay has elapsed.
+         */
+        0x43 => {
+            let now = crate::time::acpi::get_tick_count();
+            let at = if arg1 > 0 { now + ms_to_ticks(arg1) } else { 0 };
+
+            let previous = unsafe { scheduler::set_alarm(scheduler::get_current_pid(), at) };
+
+            return previous.saturating_sub(now) * 10;
+        }
+
+        /*
+         *  Syscall 0x44 --- Create a thread
+         *
+         *  Arg1: entry point (void entry(uint64_t arg))
+         *  Arg2: argument passed to the entry point in RDI
+         *  Returns: PID of the new thread, or 0 on failure
+         *
+         *  The thread shares the address space of the calling process and
+         *  runs on a 256 KiB stack slot of its own.  The entry point must not
+         *  return; the thread ends through syscall 0x45.
+         */
+        0x44 => {
+            if !is_user_ptr(arg1) {
+                return 0;
+            }
+
+            return match unsafe { scheduler::new_thread(arg1, arg2) } {
+                Some(tid) => tid as u64,
+                None => 0,
+            };
+        }
+
+        /*
+         *  Syscall 0x45 --- Exit the calling thread
+         *
+         *  Arg1: any
+         *  Arg2: thread return code, collected by syscall 0x46
+         *
+         *  Called from the main thread, ends the whole process like 0x00.
+         */
+        0x45 => {
+            unsafe {
+                let pid = scheduler::get_current_pid();
+                scheduler::exit(pid, arg2);
+
+                core::arch::asm!("sti");
+                loop {
+                    core::arch::asm!("int 0x20");
+                    core::arch::asm!("hlt");
+                }
+            };
+        }
+
+        /*
+         *  Syscall 0x46 --- Join a thread
+         *
+         *  Arg1: PID of a thread of the calling process
+         *  Arg2: pointer to return code (*mut u64), or 0x00 if not needed
+         *  Returns: the thread PID, or 0 if there is no such thread
+         *
+         *  Blocks the calling thread until the other one has ended.  Any
+         *  thread of the process may join it, but only once.
+         */
+        0x46 => {
+            if arg2 != 0 && !is_user_ptr(arg2) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            unsafe {
+                let Some(code) = scheduler::join(arg1 as usize) else {
+                    return 0;
+                };
+
+                if arg2 != 0 {
+                    *(arg2 as *mut u64) = code;
+                }
+
+                return arg1;
+            }
+        }
+
+        /*
+         *  Syscall 0x47 --- Wait on a futex
+         *
+         *  Arg1: pointer to the futex word (*const u32, 4-byte aligned)
+         *  Arg2: expected value in bits 0-31, timeout in milliseconds in
+         *        bits 32-63 (0 = wait forever)
+         *  Returns: 0x00 when woken, TryAgain if the word did not hold the
+         *           expected value, TimedOut once the timeout has passed
+         *
+         *  Futexes are keyed on the physical address of the word, so threads
+         *  and processes sharing the memory wait on the same futex.  A wait
+         *  may also end early with 0x00 when a signal arrives; callers should
+         *  recheck the word.
+         */
+        0x47 => {
+            if !is_futex_word(arg1) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let Some(key) = (unsafe { vm::resolve(arg1) }) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            let expected = arg2 as u32;
+            let timeout_ms = arg2 >> 32;
+            let deadline = if timeout_ms > 0 {
+                crate::time::acpi::get_tick_count() + ms_to_ticks(timeout_ms)
+            } else {
+                0
+            };
+
+            return match unsafe { scheduler::futex_wait(key, arg1 as *const u32, expected, deadline) } {
+                FutexWait::Woken => SyscallReturnCode::Ok,
+                FutexWait::Mismatch => SyscallReturnCode::TryAgain,
+                FutexWait::TimedOut => SyscallReturnCode::TimedOut,
+            } as u64;
+        }
+
+        /*
+         *  Syscall 0x48 --- Wake futex waiters
+         *
+         *  Arg1: pointer to the futex word (*const u32, 4-byte aligned)
+         *  Arg2: maximum number of waiters to wake (0 = all)
+         *  Returns: number of waiters woken
+         */
+        0x48 => {
+            if !is_futex_word(arg1) {
+                return 0;
+            }
+
+            let Some(key) = (unsafe { vm::resolve(arg1) }) else {
+                return 0;
+            };
+
+            let count = if arg2 == 0 { usize::MAX } else { arg2 as usize };
+
+            return unsafe { scheduler::futex_wake(key, count) } as u64;
+        }
+
+        /*
+         *  Syscall 0x49 --- Create an IPC port
+         *
+         *  Returns: ID of the new port, or 0 if the process has MAX_PORTS ports
+         *
+         *  The port belongs to the calling process; any of its threads can
+         *  receive on it.  Port IDs are unique and never reused.
+         */
+        0x49 => {
+            let pid = unsafe { scheduler::get_current_pid() };
+
+            return unsafe { scheduler::port_create(pid) }.unwrap_or(0) as u64;
+        }
+
+        /*
+         *  Syscall 0x4A --- Destroy an IPC port
+         *
+         *  Arg1: port ID
+         *
+         *  Queued messages are dropped; pending calls on them fail with
+         *  BrokenPipe.  The default port of a process cannot be destroyed.
+         */
+        0x4A => {
+            let pid = unsafe { scheduler::get_current_pid() };
+
+            if !unsafe { scheduler::port_destroy(pid, arg1 as usize) } {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+        }
+
+        /*
+         *  Syscall 0x4B --- Send a message to an IPC port
+         *
+         *  Arg1: port ID
+         *  Arg2: pointer to IpcMessage (buf, len)
+         *  Returns: 0x00, or TryAgain if the port's queue is full
+         *
+         *  The payload is copied into the kernel, so the buffer can be reused
+         *  right away.  It may be at most MAX_MSG_SIZE (4096) bytes.
+         */
+        0x4B => unsafe {
+            let Some(req) = read_ipc_message(arg2) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            if req.len as usize > MAX_MSG_SIZE || !is_user_buffer(req.buf, req.len) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let pid = scheduler::get_current_pid();
+
+            let Some(msg) = Message::copy_from(0, pid, req.buf as *const u8, req.len as usize)
+            else {
+                return SyscallReturnCode::OutOfMemory as u64;
+            };
+
+            if let Err(err) = scheduler::send(arg1 as usize, msg) {
+                return ipc_error(err);
+            }
+        },
+
+        /*
+         *  Syscall 0x4C --- Receive a message from an IPC port
+         *
+         *  Arg1: port ID, owned by the calling process
+         *  Arg2: pointer to IpcMessage (buf, cap; len, sender, token are set)
+         *  Returns: 0x00, or Interrupted if a signal arrived first
+         *
+         *  Blocks until a message is queued.  At most `cap` bytes are copied;
+         *  `len` is set to the full payload length.  A non-zero `token` means
+         *  the sender waits in a call for syscall 0x4E.
+         */
+        0x4C => unsafe {
+            let Some(mut req) = read_ipc_message(arg2) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            if !is_user_buffer(req.buf, req.cap) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let msg = match scheduler::receive(arg1 as usize) {
+                Ok(msg) => msg,
+                Err(err) => return ipc_error(err),
+            };
+
+            copy_payload(&mut req, &msg.data);
+            req.sender = msg.src_pid as u64;
+            req.token = msg.token;
+
+            (arg2 as *mut IpcMessage).write_unaligned(req);
+        },
+
+        /*
+         *  Syscall 0x4D --- Call an IPC port
+         *
+         *  Arg1: port ID
+         *  Arg2: pointer to IpcMessage (buf, len, cap; len is set)
+         *  Returns: 0x00, or BrokenPipe if the server went away unanswered
+         *
+         *  Sends the request like 0x4B, then blocks until the server replies.
+         *  The reply replaces the request in `buf`, truncated to `cap` bytes.
+         *  Signals do not interrupt a call.
+         */
+        0x4D => unsafe {
+            let Some(mut req) = read_ipc_message(arg2) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            if req.len as usize > MAX_MSG_SIZE
+                || !is_user_buffer(req.buf, req.len)
+                || !is_user_buffer(req.buf, req.cap)
+            {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let pid = scheduler::get_current_pid();
+
+            let Some(msg) = Message::copy_from(0, pid, req.buf as *const u8, req.len as usize)
+            else {
+                return SyscallReturnCode::OutOfMemory as u64;
+            };
+
+            let reply = match scheduler::call(arg1 as usize, msg) {
+                Ok(reply) => reply,
+                Err(err) => return ipc_error(err),
+            };
+
+            copy_payload(&mut req, &reply);
+
+            (arg2 as *mut IpcMessage).write_unaligned(req);
+        },
+
+        /*
+         *  Syscall 0x4E --- Reply to a call
+         *
+         *  Arg1: reply token, from the IpcMessage of 0x4C
+         *  Arg2: pointer to IpcMessage (buf, len)
+         *
+         *  Wakes the caller with a copy of the payload.  Each call is
+         *  answered once, by a thread of the process it was sent to.
+         */
+        0x4E => unsafe {
+            let Some(req) = read_ipc_message(arg2) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            if req.len as usize > MAX_MSG_SIZE || !is_user_buffer(req.buf, req.len) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let pid = scheduler::get_current_pid();
+
+            let Some(msg) = Message::copy_from(0, pid, req.buf as *const u8, req.len as usize)
+            else {
+                return SyscallReturnCode::OutOfMemory as u64;
+            };
+
+            if let Err(err) = scheduler::reply(pid, arg1, msg.data) {
+                return ipc_error(err);
+            }
+        },
+
+        /*
+         *  Syscall 0x4F --- Register a service name
+         *
+         *  Arg1: pointer to the name (NUL-terminated, 1-16 printable chars)
+         *  Arg2: port ID, owned by the calling process
+         *
+         *  Fails if the name is taken or the registry is full.  The name is
+         *  dropped when the port is destroyed or the process exits.
+         */
+        0x4F => unsafe {
+            let Some(name) = user_service_name(arg1) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            let pid = scheduler::get_current_pid();
+
+            if !scheduler::register_service(pid, name, arg2 as usize) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+        },
+
+        /*
+         *  Syscall 0x50 --- Look up a service name
+         *
+         *  Arg1: pointer to the name (NUL-terminated)
+         *  Arg2: LOOKUP_WAIT in bit 0 to block until the name is registered,
+         *        timeout in milliseconds in bits 32-63 (0 = wait forever)
+         *  Returns: port ID, or 0 if the name is not registered (in time)
+         *
+         *  A wait also ends with 0 when a signal arrives.
+         */
+        0x50 => unsafe {
+            let Some(name) = user_service_name(arg1) else {
+                return 0;
+            };
+
+            let timeout_ms = arg2 >> 32;
+            let deadline = if timeout_ms > 0 {
+                crate::time::acpi::get_tick_count() + ms_to_ticks(timeout_ms)
+            } else {
+                0
+            };
+
+            return scheduler::lookup_service(name, arg2 & LOOKUP_WAIT != 0, deadline).unwrap_or(0)
+                as u64;
+        },
+
+        /*
+         *  Syscall 0x51 --- Unregister a service name
+         *
+         *  Arg1: pointer to the name (NUL-terminated), registered by the
+         *        calling process
+         */
+        0x51 => unsafe {
+            let Some(name) = user_service_name(arg1) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            if !scheduler::unregister_service(scheduler::get_current_pid(), name) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+        },
+
+        /*
+         *  Syscall 0x52 --- Create a shared memory region
+         *
+         *  Arg1: size in bytes, rounded up to 2 MiB (at most 16 MiB)
+         *  Arg2: pointer to a name (NUL-terminated), or 0 for an anonymous region
+         *  Arg3: SHM_WRITE in bit 0 to let processes that open it map it
+         *        writable, else they may only map it read-only
+         *  Returns: region ID, or 0 on failure
+         *
+         *  The region is zeroed and not mapped yet; the calling process holds
+         *  it until it maps and unmaps it, or exits.  Only the creator may map
+         *  an anonymous region.
+         */
+        0x52 => unsafe {
+            let name = if arg2 == 0 {
+                None
+            } else {
+                match user_service_name(arg2) {
+                    Some(name) => Some(name),
+                    None => return 0,
+                }
+            };
+
+            let cr3 = pages::read_cr3() as u64;
+
+            return shm::create(cr3, arg1, name, arg3 & SHM_WRITE != 0).unwrap_or(0) as u64;
+        },
+
+        /*
+         *  Syscall 0x53 --- Open a named shared memory region
+         *
+         *  Arg1: pointer to the name (NUL-terminated)
+         *  Returns: region ID, or 0 if there is no such region
+         *
+         *  The calling process may then map the region, writable only if its
+         *  creator allowed it, and holds it until it maps and unmaps it, or
+         *  exits.
+         */
+        0x53 => unsafe {
+            let Some(name) = user_service_name(arg1) else {
+                return 0;
+            };
+
+            return shm::open(pages::read_cr3() as u64, name).unwrap_or(0) as u64;
+        },
+
+        /*
+         *  Syscall 0x54 --- Map a shared memory region
+         *
+         *  Arg1: region ID
+         *  Arg2: SHM_WRITE in bit 0 to map it writable, else read-only
+         *  Returns: address of the mapping, or 0 on failure
+         *
+         *  The region must have been created or opened by the calling
+         *  process.  Regions are mapped in the window at 0x2_0000_0000;
+         *  threads of a process share the mapping.  Each process maps a
+         *  region once.
+         */
+        0x54 => unsafe {
+            let cr3 = pages::read_cr3() as u64;
+
+            return shm::map(cr3, arg1 as usize, arg2 & SHM_WRITE != 0).unwrap_or(0);
+        },
+
+        /*
+         *  Syscall 0x55 --- Unmap a shared memory region
+         *
+         *  Arg1: address returned by 0x54
+         *
+         *  The region is freed once no process maps or holds it.
+         */
+        0x55 => unsafe {
+            if !shm::unmap(pages::read_cr3() as u64, arg1) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+        },
+
+        /*
+         *  Syscall 0x56 --- Open a file
+         *
+         *  Arg1: pointer to NUL-terminated VFS path (*const u8)
+         *  Arg2: flags: O_READ 0x01, O_WRITE 0x02, O_CREATE 0x04, O_TRUNC 0x08,
+         *        O_APPEND 0x10
+         *  Returns: file descriptor, or OutOfMemory if MAX_FILES (16)
+         *  descriptors are open
+         *
+         *  Relative paths start at the working directory.  Files under
+         *  /mnt/iso can only be opened with O_READ alone.
+         */
+        0x56 => {
+            let Some(path) = (unsafe { user_cstr(arg1, 64) }) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            let file = match file::open(path, arg2) {
+                Ok(file) => file,
+                Err(err) => return file_error(err),
+            };
+
+            return match unsafe { scheduler::fd_install(scheduler::get_current_pid(), file) } {
+                Some(fd) => fd as u64,
+                None => SyscallReturnCode::OutOfMemory as u64,
+            };
+        }
+
+        /*
+         *  Syscall 0x57 --- Read from a file descriptor
+         *
+         *  Arg1: file descriptor
+         *  Arg2: pointer to FileIo (buf, len); count is set to the bytes read
+         *
+         *  Reads from the current position and advances it; a count of 0
+         *  means end of file.  Descriptor 0 reads standard input like 0x19,
+         *  without its STDIN_READ_MAX limit.
+         */
+        0x57 => unsafe {
+            return file_io(arg1 as usize, arg2, false);
+        },
+
+        /*
+         *  Syscall 0x58 --- Write to a file descriptor
+         *
+         *  Arg1: file descriptor
+         *  Arg2: pointer to FileIo (buf, len); count is set to the bytes written
+         *
+         *  Writes at the current position, or at the end with O_APPEND, and
+         *  advances it.  The count is short only when the disk is full.
+         *  Descriptor 1 writes standard output like 0x10, descriptor 2 always
+         *  the screen.
+         */
+        0x58 => unsafe {
+            return file_io(arg1 as usize, arg2, true);
+        },
+
+        /*
+         *  Syscall 0x59 --- Set the position of a file descriptor
+         *
+         *  Arg1: file descriptor in bits 0-31, whence in bits 32-63
+         *        (SEEK_SET 0, SEEK_CUR 1, SEEK_END 2)
+         *  Arg2: pointer to offset (*mut i64); set to the new position
+         *
+         *  The position may go past the end of the file; writing there fills
+         *  the gap with zeros.  Console descriptors cannot seek.
+         */
+        0x59 => unsafe {
+            if !is_user_buffer(arg2, 8) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let pid = scheduler::get_current_pid();
+            let fd = (arg1 & 0xFFFF_FFFF) as usize;
+
+            let Some(mut file) = scheduler::fd_get(pid, fd) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            let offset = (arg2 as *const i64).read_unaligned();
+
+            match file::seek(&mut file, offset, arg1 >> 32) {
+                Ok(pos) => {
+                    scheduler::fd_update(pid, fd, file);
+                    (arg2 as *mut u64).write_unaligned(pos);
+                }
+                Err(err) => return file_error(err),
+            }
+        },
+
+        /*
+         *  Syscall 0x5A --- Get the status of a file descriptor
+         *
+         *  Arg1: file descriptor
+         *  Arg2: pointer to FileStat (size, pos, kind, flags)
+         *
+         *  kind is 0 for the console, 1 for a FAT12 file and 2 for an
+         *  ISO9660 file.
+         */
+        0x5A => unsafe {
+            if !is_user_buffer(arg2, core::mem::size_of::<FileStat>() as u64) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let Some(file) = scheduler::fd_get(scheduler::get_current_pid(), arg1 as usize) else {
+                return SyscallReturnCode::InvalidInput as u64;
+            };
+
+            let size = file::size(&file);
+
+            let kind = match file.node {
+                Node::Fat12 { .. } => 1,
+                Node::Iso9660 { .. } => 2,
+                _ => 0,
+            };
+
+            (arg2 as *mut FileStat).write_unaligned(FileStat {
+                size,
+                pos: file.pos,
+                kind,
+                flags: file.flags,
+            });
+        },
+
+        /*
+         *  Syscall 0x5B --- Close a file descriptor
+         *
+         *  Arg1: file descriptor
+         *
+         *  The descriptor is free for the next open.  Descriptors still open
+         *  when a process exits are closed with it.
+         */
+        0x5B => {
+            if !unsafe { scheduler::fd_close(scheduler::get_current_pid(), arg1 as usize) } {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+        }
+
+        /*
+         *  Syscall 0x5C --- Wait for events on several sources
+         *
+         *  Arg1: pointer to an array of PollSource (kind, id, events, revents)
+         *  Arg2: number of sources in bits 0-15 (at most MAX_POLL_SOURCES),
+         *        POLL_NOWAIT in bit 16 to return at once,
+         *        timeout in milliseconds in bits 32-63 (0 = wait forever)
+         *  Returns: number of sources with revents set, 0 if none became
+         *           ready in time, Interrupted if a signal arrived first
+         *
+         *  Kinds: 0 port, 1 keyboard pipe, 2 mouse, 3 serial, 4 file
+         *  descriptor.  The keyboard and mouse sources need a subscription
+         *  made with syscall 0x03.
+         */
+        0x5C => unsafe {
+            let count = (arg2 & 0xFFFF) as usize;
+            let size = (count * core::mem::size_of::<PollSource>()) as u64;
+
+            if count == 0 || count > MAX_POLL_SOURCES || !is_user_buffer(arg1, size) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+
+            let user = arg1 as *mut PollSource;
+            let mut sources = [PollSource::EMPTY; MAX_POLL_SOURCES];
+
+            for (i, source) in sources.iter_mut().take(count).enumerate() {
+                *source = user.add(i).read_unaligned();
+
+                if !poll::is_valid_kind(source.kind) {
+                    return SyscallReturnCode::InvalidInput as u64;
+                }
+            }
+
+            let timeout_ms = arg2 >> 32;
+            let deadline = if timeout_ms > 0 {
+                crate::time::acpi::get_tick_count() + ms_to_ticks(timeout_ms)
+            } else {
+                0
+            };
+
+            let result = scheduler::poll(&mut sources[..count], arg2 & POLL_NOWAIT == 0, deadline);
+
+            for (i, source) in sources.iter().take(count).enumerate() {
+                user.add(i).write_unaligned(*source);
+            }
+
+            return match result {
+                Ok(ready) => ready as u64,
+                Err(err) => ipc_error(err),
+            };
+        },
+
+        /*
+         *  Syscall 0x5D --- Set the program break
+         *
+         *  Arg1: new end of the heap, or 0 to only ask for it
+         *  Returns: the break in effect, or 0 if it could not be moved
+         *
+         *  The heap starts above the program image in the high window.
+         *  Memory up to the break is zeroed when first touched; pages above
+         *  a lowered break are returned.
+         */
+        0x5D => unsafe {
+            return vm::brk(pages::read_cr3() as u64, arg1).unwrap_or(0);
+        },
+
+        /*
+         *  Syscall 0x5E --- Move the program break
+         *
+         *  Arg1: signed increment in bytes (0 to ask for the break)
+         *  Returns: the old break, where new memory starts, or 0 on failure
+         */
+        0x5E => unsafe {
+            return vm::sbrk(pages::read_cr3() as u64, arg1 as i64).unwrap_or(0);
+        },
+
+        /*
+         *  Syscall 0x5F --- Map memory or a file
+         *
+         *  Arg1: length in bytes, rounded up to whole pages
+         *  Arg2: MAP_WRITE in bit 0 to map it writable, else read-only,
+         *        MAP_FILE in bit 1 to map the file in Arg3
+         *  Arg3: file descriptor opened with O_READ (MAP_FILE only)
+         *  Arg4: page-aligned offset in the file (MAP_FILE only)
+         *  Returns: page-aligned address of the mapping, or 0 on failure
+         *
+         *  Anonymous memory is zeroed when first touched; file pages are
+         *  read then, with zeroes past the end of the file.  A writable file
+         *  mapping is a private copy: writes never reach the file.  Mappings
+         *  are placed below the thread stacks and freed when the process
+         *  exits; closing the descriptor does not unmap the file.
+         */
+        0x5F => unsafe {
+            let cr3 = pages::read_cr3() as u64;
+            let writable = arg2 & MAP_WRITE != 0;
+
+            if arg2 & MAP_FILE == 0 {
+                return vm::map_anon(cr3, arg1, writable).unwrap_or(0);
+            }
+
+            let Some(file) = scheduler::fd_get(scheduler::get_current_pid(), arg3 as usize) else {
+                return 0;
+            };
+            if file.is_console() || file.flags & file::O_READ == 0 {
+                return 0;
+            }
+
+            return vm::map_file(cr3, arg1, writable, file, arg4).unwrap_or(0);
+        },
+
+        /*
+         *  Syscall 0x60 --- Unmap memory or a file
+         *
+         *  Arg1: page-aligned address inside mappings made by 0x5F
+         *  Arg2: length in bytes, rounded up to whole pages
+         *
+         *  Part of a mapping may be unmapped; its pages are freed.  Changes
+         *  to a private file mapping are lost.
+         */
+        0x60 => unsafe {
+            if !vm::unmap(pages::read_cr3() as u64, arg1, arg2) {
+                return SyscallReturnCode::InvalidInput as u64;
+            }
+        },
+
         /*
          *  Unknown syscall
          */
@@ -1702,6 +2904,252 @@ unsafe fn nul_terminated_slice(ptr: *const u8, max: usize) -> &'static [u8] {
     core::slice::from_raw_parts(ptr, len)
 }
 
+/// Read a NUL-terminated string of at most `max` bytes from user memory.
+/// Every byte read is checked first, a page at a time, so a short string
+/// near the end of the caller's memory is fine.  None if the string runs
+/// out of it.
+unsafe fn user_cstr(ptr: u64, max: usize) -> Option<&'static [u8]> {
+    let end = ptr.checked_add(max as u64)?;
+    let mut checked = ptr;
+    let mut at = ptr;
+
+    while at < end {
+        if at == checked {
+            checked = ((at | 0xFFF) + 1).min(end);
+            if !is_user_buffer(at, checked - at) {
+                return None;
+            }
+        }
+        if *(at as *const u8) == 0 {
+            break;
+        }
+        at += 1;
+    }
+
+    let len = (at - ptr) as usize;
+    Some(core::slice::from_raw_parts(ptr as *const u8, len))
+}
+
+/// Collect a NULL-terminated array of user string pointers (argv / envp); a
+/// NULL array is empty.  Returns None if the array or any string lies outside
+/// userland, or if it holds more than elf::MAX_ARGS entries.
+unsafe fn user_str_array(ptr: u64) -> Option<Vec<&'static [u8]>> {
+    let mut strings = Vec::new();
+
+    if ptr == 0 {
+        return Some(strings);
+    }
+
+    loop {
+        let slot = ptr + 8 * strings.len() as u64;
+        if !is_user_buffer(slot, 8) {
+            return None;
+        }
+
+        let s = *(slot as *const u64);
+        if s == 0 {
+            return Some(strings);
+        }
+
+        if !is_user_ptr(s) || strings.len() == elf::MAX_ARGS {
+            return None;
+        }
+
+        strings.push(nul_terminated_slice(s as *const u8, 256));
+    }
+}
+
+/// Buffer length in Arg3 of syscalls 0x35 and 0x36.
+fn socket_buf_len(arg: u64) -> usize {
+    match arg {
+        0 => SOCKET_BUF_LEGACY,
+        n => n as usize,
+    }
+}
+
+/// PIT ticks covering `ms` milliseconds, rounded up to the next 10 ms tick.
+fn ms_to_ticks(ms: u64) -> u64 {
+    ms.div_ceil(10)
+}
+
+/// True if `len` bytes at `ptr` lie within the areas of the caller's address
+/// space: its image, stacks, heap and mappings.
+fn is_user_buffer(ptr: u64, len: u64) -> bool {
+    vm::is_user_range(ptr, len)
+}
+
+/// True if `ptr` points into the caller's memory; the syscall checks how far
+/// the data it points to reaches.
+fn is_user_ptr(ptr: u64) -> bool {
+    is_user_buffer(ptr, 1)
+}
+
+/// Futex words may live anywhere a user buffer may, or in shared memory, and
+/// must be 4-byte aligned.  Whether shared memory is mapped there is checked
+/// when the address is translated.
+fn is_futex_word(addr: u64) -> bool {
+    (is_user_buffer(addr, 4) || (pages::SHM_BASE..pages::SHM_END).contains(&addr)) && addr % 4 == 0
+}
+
+/// Read the IpcMessage block at `ptr`, if it lies in user memory.
+unsafe fn read_ipc_message(ptr: u64) -> Option<IpcMessage> {
+    if !is_user_buffer(ptr, core::mem::size_of::<IpcMessage>() as u64) {
+        return None;
+    }
+
+    Some((ptr as *const IpcMessage).read_unaligned())
+}
+
+/// Copy a received payload into the buffer of `req`, truncated to its `cap`,
+/// and set `len` to the full payload length.
+unsafe fn copy_payload(req: &mut IpcMessage, data: &[u8]) {
+    let n = data.len().min(req.cap as usize);
+    copy_nonoverlapping(data.as_ptr(), req.buf as *mut u8, n);
+    req.len = data.len() as u64;
+}
+
+/// Read a NUL-terminated service name from user memory.
+unsafe fn user_service_name(ptr: u64) -> Option<[u8; NAME_LEN]> {
+    service::parse_name(user_cstr(ptr, NAME_LEN + 1)?)
+}
+
+/// Write to standard output: the pipe set up by the shell, or the screen.
+/// Returns a syscall return code.
+unsafe fn write_stdout(data: &[u8]) -> u64 {
+    // In a pipeline, standard output goes to the next process.
+    if scheduler::stdio().1.is_some() {
+        if let Err(err) = scheduler::pipe_write(data) {
+            return ipc_error(err);
+        }
+        return SyscallReturnCode::Ok as u64;
+    }
+
+    printb!(data);
+    SyscallReturnCode::Ok as u64
+}
+
+/// Read standard input: the pipe set up by the shell, or a line from the
+/// keyboard.
+unsafe fn read_stdin(buf: &mut [u8]) -> Result<usize, IpcError> {
+    if scheduler::stdio().0.is_none() {
+        return Ok(keyboard::read_line(buf));
+    }
+
+    scheduler::pipe_read(buf)
+}
+
+/// Read or write descriptor `fd` of the running process as described by the
+/// FileIo block at `ptr`, and store the byte count in it.
+unsafe fn file_io(fd: usize, ptr: u64, write: bool) -> u64 {
+    if !is_user_buffer(ptr, core::mem::size_of::<FileIo>() as u64) {
+        return SyscallReturnCode::InvalidInput as u64;
+    }
+
+    let mut req = (ptr as *const FileIo).read_unaligned();
+
+    if !is_user_buffer(req.buf, req.len) {
+        return SyscallReturnCode::InvalidInput as u64;
+    }
+
+    let pid = scheduler::get_current_pid();
+
+    let Some(mut file) = scheduler::fd_get(pid, fd) else {
+        return SyscallReturnCode::InvalidInput as u64;
+    };
+
+    let buf = core::slice::from_raw_parts_mut(req.buf as *mut u8, req.len as usize);
+
+    let count = match (file.node, write) {
+        (_, _) if buf.is_empty() => 0,
+        (Node::Stdin, false) => match read_stdin(buf) {
+            Ok(count) => count,
+            Err(err) => return ipc_error(err),
+        },
+        (Node::Stdout, true) => {
+            let code = write_stdout(buf);
+            if code != SyscallReturnCode::Ok as u64 {
+                return code;
+            }
+            buf.len()
+        }
+        (Node::Stderr, true) => {
+            printb!(&*buf);
+            buf.len()
+        }
+        (Node::Stdin | Node::Stdout | Node::Stderr, _) => {
+            return SyscallReturnCode::InvalidInput as u64;
+        }
+        (_, write) => match bounce_io(&mut file, buf, write) {
+            Ok(count) => count,
+            Err(err) => return file_error(err),
+        },
+    };
+
+    if !file.is_console() {
+        scheduler::fd_update(pid, fd, file);
+    }
+
+    req.count = count as u64;
+    (ptr as *mut FileIo).write_unaligned(req);
+
+    SyscallReturnCode::Ok as u64
+}
+
+/// Read or write `buf` at the position of `file` through a kernel buffer.
+/// The filesystem is locked with interrupts off while a chunk is moved, and a
+/// fault on the user buffer there could need the same lock to read a file
+/// mapping, so user memory is only touched between chunks.  An error after
+/// some bytes were moved ends the transfer with the count so far.
+unsafe fn bounce_io(file: &mut File, buf: &mut [u8], write: bool) -> Result<usize, FileError> {
+    let mut bounce = [0u8; 512];
+    let mut done = 0;
+
+    while done < buf.len() {
+        let n = bounce.len().min(buf.len() - done);
+
+        let result = if write {
+            bounce[..n].copy_from_slice(&buf[done..done + n]);
+            file::write(file, &bounce[..n])
+        } else {
+            file::read(file, &mut bounce[..n])
+        };
+
+        let count = match result {
+            Ok(count) => count,
+            Err(_) if done > 0 => break,
+            Err(err) => return Err(err),
+        };
+
+        if !write {
+            buf[done..done + count].copy_from_slice(&bounce[..count]);
+        }
+        done += count;
+
+        if count < n {
+            break;
+        }
+    }
+
+    Ok(done)
+}
+
+fn file_error(err: FileError) -> u64 {
+    (match err {
+        FileError::NotFound => SyscallReturnCode::FileNotFound,
+        FileError::InvalidInput => SyscallReturnCode::InvalidInput,
+        FileError::Filesystem => SyscallReturnCode::FilesystemError,
+    }) as u64
+}
+
+fn ipc_error(err: IpcError) -> u64 {
+    (match err {
+        IpcError::NoPort => SyscallReturnCode::InvalidInput,
+        IpcError::QueueFull => SyscallReturnCode::TryAgain,
+        IpcError::PeerGone => SyscallReturnCode::BrokenPipe,
+        IpcError::Interrupted => SyscallReturnCode::Interrupted,
+    }) as u64
+}
+
 /// Resolve a user-provided filename/path to (fat12_relative_slice, base_cluster).
 /// Absolute paths under /mnt/fat are stripped to their FAT12-relative tail;
 /// bare names are resolved relative to the current working directory.
@@ -1810,6 +3258,41 @@ pub struct SysInfo {
     pub ip_addr: [u8; 4],
 }
 
+/// Argument block of syscall 0x08.  All fields are user pointers.
+#[repr(C, packed)]
+pub struct SpawnRequest {
+    pub path: u64, // *const u8, NUL-terminated
+    pub argv: u64, // *const *const u8, NULL-terminated (or NULL)
+    pub envp: u64, // *const *const u8, NULL-terminated (or NULL)
+}
+
+/// Argument block of the IPC syscalls 0x4B-0x4E.
+#[repr(C, packed)]
+pub struct IpcMessage {
+    pub buf: u64,    // *mut u8, payload
+    pub len: u64,    // payload length; set to the received length
+    pub cap: u64,    // size of buf for received data (0x4C, 0x4D)
+    pub sender: u64, // set by 0x4C: PID of the sender
+    pub token: u64,  // set by 0x4C: reply token of a call, 0 for a plain send
+}
+
+/// Argument block of the descriptor I/O syscalls 0x57 and 0x58.
+#[repr(C, packed)]
+pub struct FileIo {
+    pub buf: u64,   // *mut u8
+    pub len: u64,   // bytes to read or write
+    pub count: u64, // set to the bytes read or written
+}
+
+/// Filled in by syscall 0x5A.
+#[repr(C, packed)]
+pub struct FileStat {
+    pub size: u64,
+    pub pos: u64,
+    pub kind: u64, // 0 = console, 1 = FAT12 file, 2 = ISO9660 file
+    pub flags: u64,
+}
+
 #[repr(C, packed)]
 pub struct NetStatus {
     pub mac: [u8; 6],
@@ -0,0 +1,150 @@
+use core::fmt::{self, Write};
+
+use crate::{
+    mem::{
+        kheap::{KHEAP_END, KHEAP_START},
+        pages,
+    },
+    net::serial,
+};
+
+/// Size of the `.ksyms` section.  utils/ksyms.py fills it in after linking and
+/// drops the symbols that do not fit.
+pub const KSYMS_SIZE: usize = 0x20000;
+
+/// Symbol table of the kernel functions, sorted by address: the magic "KSYM",
+/// a u32 count, then per symbol its u64 address, u32 size, u8 name length and
+/// the name.  All zeros if the kernel was not built with utils/ksyms.py.
+#[used]
+#[link_section = ".ksyms"]
+static mut KSYMS: [u8; KSYMS_SIZE] = [0; KSYMS_SIZE];
+
+/// Frames printed at most, the faulting one included.
+const MAX_FRAMES: usize = 32;
+
+/// Kernel stacks live in the kernel image (the boot and IST stacks), below the
+/// user code frame, or on the kernel heap (the process stacks).
+const KERNEL_IMAGE_START: u64 = 0x100_000;
+const KERNEL_IMAGE_END: u64 = 0x600_000;
+
+/// Writes to the VGA console and to COM1, so a crash can be read off either.
+pub struct CrashWriter;
+
+impl Write for CrashWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!(s);
+
+        for b in s.bytes() {
+            serial::write(b);
+        }
+
+        Ok(())
+    }
+}
+
+/// Name of the kernel function containing `addr` and the offset of `addr` in
+/// it, or None if the symbol table has no such function.
+pub fn lookup(addr: u64) -> Option<(&'static str, u64)> {
+    #[expect(static_mut_refs)]
+    let table: &'static [u8; KSYMS_SIZE] = unsafe { &KSYMS };
+
+    if table[..4] != *b"KSYM" {
+        return None;
+    }
+
+    let count = u32::from_le_bytes(table[4..8].try_into().ok()?);
+    let mut off = 8;
+    let mut found = None;
+
+    for _ in 0..count {
+        let start = u64::from_le_bytes(table.get(off..off + 8)?.try_into().ok()?);
+        let size = u32::from_le_bytes(table.get(off + 8..off + 12)?.try_into().ok()?);
+        let len = *table.get(off + 12)? as usize;
+        let name = table.get(off + 13..off + 13 + len)?;
+
+        if start > addr {
+            break;
+        }
+
+        found = (size == 0 || addr < start + size as u64).then_some((name, start));
+        off += 13 + len;
+    }
+
+    let (name, start) = found?;
+
+    Some((core::str::from_utf8(name).unwrap_or("?"), addr - start))
+}
+
+fn is_kernel_stack(addr: u64) -> bool {
+    addr.is_multiple_of(8)
+        && ((KERNEL_IMAGE_START..KERNEL_IMAGE_END - 16).contains(&addr)
+            || ((KHEAP_START..KHEAP_END - 16).contains(&addr) && is_mapped(addr)))
+}
+
+/// Kernel stacks are fenced by unmapped guard pages: a frame record must not
+/// be read from one.
+fn is_mapped(addr: u64) -> bool {
+    let cr3 = unsafe { pages::read_cr3() as u64 };
+
+    [addr, addr + 8]
+        .iter()
+        .all(|&a| unsafe { pages::translate_in(cr3, a).is_some() })
+}
+
+/// Print the call stack to the VGA console and COM1: `rip` first, then the
+/// return addresses found by following the saved frame pointers from `rbp`.
+/// Needs a kernel built with frame pointers (see x86_64-r2.json).
+pub fn print(rip: u64, rbp: u64) {
+    let mut w = CrashWriter;
+    let _ = writeln!(w, "Backtrace:");
+
+    print_frame(&mut w, 0, rip);
+
+    let mut rbp = rbp;
+
+    for i in 1..MAX_FRAMES {
+        if !is_kernel_stack(rbp) {
+            break;
+        }
+
+        let (next, ret) = unsafe { (*(rbp as *const u64), *((rbp + 8) as *const u64)) };
+        if ret == 0 {
+            break;
+        }
+
+        print_frame(&mut w, i, ret);
+
+        // Stacks grow down, so the caller's frame is always above.
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+}
+
+/// Print the call stack of the running code.
+#[inline(always)]
+pub fn print_here() {
+    let (rip, rbp): (u64, u64);
+
+    unsafe {
+        core::arch::asm!(
+            "lea {}, [rip]",
+            "mov {}, rbp",
+            out(reg) rip,
+            out(reg) rbp,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+
+    print(rip, rbp);
+}
+
+fn print_frame(w: &mut CrashWriter, index: usize, addr: u64) {
+    let _ = match lookup(addr) {
+        Some((name, offset)) => {
+            writeln!(w, "  #{:<2} {:#010x} {}+{:#x}", index, addr, name, offset)
+        }
+        None => writeln!(w, "  #{:<2} {:#010x} ?", index, addr),
+    };
+}
@@ -236,6 +236,215 @@ impl<'a, D: BlockDevice> Filesystem<'a, D> {
         debugln!("Data written to a file successfully");
     }
 
+    /// read_at method reads up to `buf.len()` bytes of the file described by `entry`, starting at
+    /// byte `offset`. Returns how many bytes were read, 0 at the end of the file.
+    pub fn read_at(&self, entry: &Entry, offset: u32, buf: &mut [u8]) -> usize {
+        let size = entry.file_size;
+        if offset >= size {
+            return 0;
+        }
+
+        let total = buf.len().min((size - offset) as usize);
+        let cluster_size = self.cluster_size();
+
+        let mut cluster = entry.start_cluster;
+        for _ in 0..offset / cluster_size {
+            cluster = self.read_fat12_entry(cluster);
+        }
+
+        let mut pos = offset;
+        let mut done = 0;
+        let mut sector = [0u8; 512];
+
+        while done < total && (2..0xFF8).contains(&cluster) {
+            let in_cluster = pos % cluster_size;
+            let in_sector = (in_cluster % 512) as usize;
+            let lba = self.cluster_to_lba(cluster) + (in_cluster / 512) as u64;
+
+            self.device.read_sector(lba, &mut sector);
+
+            let n = (512 - in_sector).min(total - done);
+            buf[done..done + n].copy_from_slice(&sector[in_sector..in_sector + n]);
+
+            done += n;
+            pos += n as u32;
+
+            if pos.is_multiple_of(cluster_size) {
+                cluster = self.read_fat12_entry(cluster);
+            }
+        }
+
+        done
+    }
+
+    /// write_at method writes `data` into an existing file at byte `offset`, extending its cluster
+    /// chain as needed. A gap between the old end of the file and `offset` reads as zeros. Returns
+    /// how many bytes were written, which is less than `data.len()` if the disk fills up, or None
+    /// if there is no such file.
+    pub fn write_at(
+        &self,
+        dir_cluster: u16,
+        filename: &[u8; 11],
+        offset: u32,
+        data: &[u8],
+    ) -> Option<usize> {
+        let (entry_lba, entry_offset, entry) = self.locate_entry(dir_cluster, filename)?;
+
+        let (done, _) = self.write_entry_at(entry_lba, entry_offset, entry, offset, data);
+        Some(done)
+    }
+
+    /// locate_entry method returns the directory entry of `filename` together with the LBA of the
+    /// sector holding it and its byte offset there, for callers that keep the file open.
+    pub fn locate_entry(
+        &self,
+        dir_cluster: u16,
+        filename: &[u8; 11],
+    ) -> Option<(u64, usize, Entry)> {
+        let (lba, offset, raw) = self.find_dir_entry_mut(dir_cluster, filename)?;
+        let entry = unsafe { (raw.as_ptr() as *const Entry).read_unaligned() };
+
+        Some((lba, offset, entry))
+    }
+
+    /// write_entry_at method works like write_at on the file whose directory entry is at `offset`
+    /// in sector `lba`, with `entry` holding its first cluster and size as the caller last saw
+    /// them. Returns how many bytes were written and the entry as updated on the disk.
+    pub fn write_entry_at(
+        &self,
+        entry_lba: u64,
+        entry_offset: usize,
+        mut entry: Entry,
+        offset: u32,
+        data: &[u8],
+    ) -> (usize, Entry) {
+        let mut start = entry.start_cluster;
+        let size = entry.file_size;
+
+        // FAT12 sizes are 32-bit
+        let data = &data[..data.len().min((u32::MAX - offset) as usize)];
+        if data.is_empty() {
+            return (0, entry);
+        }
+
+        if start < 2 {
+            start = self.allocate_zeroed_cluster();
+            if start == 0 {
+                debugln!("write_at: disk is full");
+                return (0, entry);
+            }
+        }
+
+        let cluster_size = self.cluster_size();
+        let mut cluster = start;
+        let mut index = 0;
+        let mut pos = offset;
+        let mut done = 0;
+        let mut sector = [0u8; 512];
+
+        while done < data.len() {
+            // Walk the chain up to the cluster holding `pos`, growing it at the end.
+            while index < pos / cluster_size {
+                let next = self.read_fat12_entry(cluster);
+
+                cluster = if (2..0xFF8).contains(&next) {
+                    next
+                } else {
+                    let new = self.allocate_zeroed_cluster();
+                    if new == 0 {
+                        break;
+                    }
+
+                    self.write_fat12_entry(cluster, new);
+                    new
+                };
+                index += 1;
+            }
+
+            if index < pos / cluster_size {
+                debugln!("write_at: disk full mid-write, stopping");
+                break;
+            }
+
+            let in_cluster = pos % cluster_size;
+            let in_sector = (in_cluster % 512) as usize;
+            let lba = self.cluster_to_lba(cluster) + (in_cluster / 512) as u64;
+            let n = (512 - in_sector).min(data.len() - done);
+
+            // Only partly overwritten sectors need their old contents.
+            if n < 512 {
+                self.device.read_sector(lba, &mut sector);
+            }
+
+            sector[in_sector..in_sector + n].copy_from_slice(&data[done..done + n]);
+            self.device.write_sector(lba, &sector);
+
+            done += n;
+            pos += n as u32;
+        }
+
+        let size = size.max(offset + done as u32);
+        self.set_entry_chain(entry_lba, entry_offset, start, size);
+
+        entry.start_cluster = start;
+        entry.file_size = size;
+        (done, entry)
+    }
+
+    /// create_file method adds an empty file to the directory.
+    pub fn create_file(&self, dir_cluster: u16, filename: &[u8; 11]) {
+        self.write_dir_entry(dir_cluster, filename, 0, 0);
+    }
+
+    /// truncate_file method frees all clusters of a file and sets its size to 0. Returns false if
+    /// there is no such file.
+    pub fn truncate_file(&self, dir_cluster: u16, filename: &[u8; 11]) -> bool {
+        let Some((entry_lba, entry_offset, dir_entry)) =
+            self.find_dir_entry_mut(dir_cluster, filename)
+        else {
+            return false;
+        };
+
+        let first_cluster = u16::from_le_bytes([dir_entry[26], dir_entry[27]]);
+        if first_cluster >= 2 {
+            self.free_cluster_chain(first_cluster);
+        }
+
+        self.set_entry_chain(entry_lba, entry_offset, 0, 0);
+        true
+    }
+
+    /// Bytes per cluster
+    fn cluster_size(&self) -> u32 {
+        512 * self.sectors_per_cluster as u32
+    }
+
+    /// Allocates a cluster and fills it with zeros, returns 0 if the disk is full
+    fn allocate_zeroed_cluster(&self) -> u16 {
+        let cluster = self.allocate_cluster();
+        if cluster == 0 {
+            return 0;
+        }
+
+        let lba = self.cluster_to_lba(cluster);
+        for i in 0..self.sectors_per_cluster as u64 {
+            self.device.write_sector(lba + i, &[0u8; 512]);
+        }
+
+        cluster
+    }
+
+    /// Updates the first cluster and size of the directory entry at `offset` in sector `lba`
+    fn set_entry_chain(&self, lba: u64, offset: usize, first_cluster: u16, file_size: u32) {
+        let mut sector = [0u8; 512];
+        self.device.read_sector(lba, &mut sector);
+
+        sector[offset + 26..offset + 28].copy_from_slice(&first_cluster.to_le_bytes());
+        sector[offset + 28..offset + 32].copy_from_slice(&file_size.to_le_bytes());
+
+        self.device.write_sector(lba, &sector);
+    }
+
     /// write_dir_entry method ensures a new directory entry is written into the directory file list
     fn write_dir_entry(
         &self,
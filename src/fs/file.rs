@@ -0,0 +1,340 @@
+/// Files opened through per-process descriptors.
+///
+/// `open` resolves a VFS path once and keeps where the file lies on the disk,
+/// so reads and writes go straight to the sectors at the current position
+/// instead of loading or rewriting the whole file.  The FAT12 floppy is
+/// mounted on the first open and reused by every descriptor.  Writes reach the
+/// disk before the call returns, so closing a descriptor only frees it.
+/// Descriptors 0, 1 and 2 are the console streams of the process.
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::fs::{
+    fat12::{
+        block::Floppy,
+        entry::Entry,
+        fs::{fat83, Filesystem},
+    },
+    iso9660::{Iso9660, IsoEntry},
+    vfs,
+};
+use crate::init::config::SYSTEM_CONFIG;
+
+/// Descriptors per process, including the console streams.
+pub const MAX_FILES: usize = 16;
+
+/// Open for reading.
+pub const O_READ: u64 = 1;
+/// Open for writing; only FAT12 files can be written.
+pub const O_WRITE: u64 = 2;
+/// With O_WRITE, create the file if it does not exist.
+pub const O_CREATE: u64 = 4;
+/// With O_WRITE, empty the file when it is opened.
+pub const O_TRUNC: u64 = 8;
+/// With O_WRITE, every write goes to the end of the file.
+pub const O_APPEND: u64 = 16;
+
+const O_ALL: u64 = O_READ | O_WRITE | O_CREATE | O_TRUNC | O_APPEND;
+
+pub const SEEK_SET: u64 = 0;
+pub const SEEK_CUR: u64 = 1;
+pub const SEEK_END: u64 = 2;
+
+/// Why a file operation failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileError {
+    /// The path or file does not exist (any more).
+    NotFound,
+    /// Bad flags or offset, a directory, or an operation the descriptor was
+    /// not opened for.
+    InvalidInput,
+    /// The filesystem could not be read.
+    Filesystem,
+}
+
+static FLOPPY: Floppy = Floppy;
+
+/// The FAT12 filesystem descriptors and syscalls read and write, mounted by
+/// the first access that needs it.
+static FAT12: Mutex<Option<Filesystem<'static, Floppy>>> = Mutex::new(None);
+
+/// Run `f` on the mounted FAT12 filesystem, mounting it first if needed.
+///
+/// The lock is held with interrupts off, so its holder is never preempted;
+/// the floppy driver polls and needs no IRQ.  The page-fault handler reads
+/// file mappings through here, so `f` must not touch user memory.
+pub fn with_fat12<T>(f: impl FnOnce(&Filesystem<'static, Floppy>) -> T) -> Result<T, FileError> {
+    without_interrupts(|| {
+        let mut fat12 = FAT12.lock();
+
+        if fat12.is_none() {
+            Floppy::init();
+            *fat12 = Some(Filesystem::new(&FLOPPY).map_err(|_| FileError::Filesystem)?);
+        }
+
+        fat12.as_ref().map(f).ok_or(FileError::Filesystem)
+    })
+}
+
+/// What a descriptor refers to.
+#[derive(Debug, Clone, Copy)]
+pub enum Node {
+    Stdin,
+    Stdout,
+    Stderr,
+    /// A file whose directory entry is at `entry_offset` in sector
+    /// `entry_lba`.  Its first cluster and size are read at open and kept up
+    /// to date by writes through this descriptor; writes through another
+    /// descriptor that grow the file are not seen here.
+    Fat12 {
+        entry_lba: u64,
+        entry_offset: usize,
+        start_cluster: u16,
+        size: u32,
+    },
+    Iso9660 {
+        lba: u32,
+        size: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct File {
+    pub node: Node,
+    pub flags: u64,
+    /// Byte offset of the next read or write.
+    pub pos: u64,
+}
+
+impl File {
+    pub const STDIN: File = File::console(Node::Stdin, O_READ);
+    pub const STDOUT: File = File::console(Node::Stdout, O_WRITE);
+    pub const STDERR: File = File::console(Node::Stderr, O_WRITE);
+
+    const fn console(node: Node, flags: u64) -> File {
+        File {
+            node,
+            flags,
+            pos: 0,
+        }
+    }
+
+    pub fn is_console(&self) -> bool {
+        matches!(self.node, Node::Stdin | Node::Stdout | Node::Stderr)
+    }
+}
+
+/// Open the file at `path`, absolute or relative to the working directory.
+/// Files under /mnt/iso can only be opened for reading.
+pub fn open(path: &[u8], flags: u64) -> Result<File, FileError> {
+    if flags & !O_ALL != 0 || flags & (O_READ | O_WRITE) == 0 {
+        return Err(FileError::InvalidInput);
+    }
+
+    if let Some(rel) = vfs::try_iso9660_absolute(path) {
+        if flags != O_READ {
+            return Err(FileError::InvalidInput);
+        }
+
+        let iso = Iso9660::probe().ok_or(FileError::Filesystem)?;
+        let entry = iso.resolve(rel).ok_or(FileError::NotFound)?;
+
+        if entry.is_dir {
+            return Err(FileError::InvalidInput);
+        }
+
+        let node = Node::Iso9660 {
+            lba: entry.lba,
+            size: entry.size,
+        };
+        return Ok(File {
+            node,
+            flags,
+            pos: 0,
+        });
+    }
+
+    let (rel, base) = match vfs::try_fat12_absolute(path) {
+        Some(rel) => (rel, 0),
+        None => (
+            path,
+            SYSTEM_CONFIG.try_lock().map_or(0, |c| c.get_path_cluster()),
+        ),
+    };
+
+    let (dir_path, name) = match rel.iter().rposition(|&b| b == b'/') {
+        Some(pos) => (&rel[..pos], &rel[pos + 1..]),
+        None => (&b""[..], rel),
+    };
+
+    if name.is_empty() {
+        return Err(FileError::InvalidInput);
+    }
+
+    let name = fat83(name);
+    let writing = flags & O_WRITE != 0;
+
+    let (entry_lba, entry_offset, entry) = with_fat12(|fs| {
+        let dir = fs
+            .resolve_path_from(base, dir_path)
+            .ok_or(FileError::NotFound)?;
+        if dir.attr & 0x10 == 0 {
+            return Err(FileError::NotFound);
+        }
+
+        let dir_cluster = dir.start_cluster;
+
+        match fs.find_entry(dir_cluster, &name) {
+            Some(entry) if entry.attr & 0x10 != 0 => return Err(FileError::InvalidInput),
+            Some(_) => {
+                if writing && flags & O_TRUNC != 0 {
+                    fs.truncate_file(dir_cluster, &name);
+                }
+            }
+            None if writing && flags & O_CREATE != 0 => {
+                fs.create_file(dir_cluster, &name);
+            }
+            None => return Err(FileError::NotFound),
+        }
+
+        // After O_CREATE the directory may have had no free entry.
+        fs.locate_entry(dir_cluster, &name)
+            .ok_or(FileError::Filesystem)
+    })??;
+
+    let node = Node::Fat12 {
+        entry_lba,
+        entry_offset,
+        start_cluster: entry.start_cluster,
+        size: entry.file_size,
+    };
+    Ok(File {
+        node,
+        flags,
+        pos: 0,
+    })
+}
+
+/// Size of the file in bytes; 0 for the console streams.
+pub fn size(file: &File) -> u64 {
+    match file.node {
+        Node::Fat12 { size, .. } | Node::Iso9660 { size, .. } => size as u64,
+        _ => 0,
+    }
+}
+
+/// Read from the current position into `buf` and advance past the bytes read.
+/// Returns 0 at the end of the file.  The console streams are not handled
+/// here.
+pub fn read(file: &mut File, buf: &mut [u8]) -> Result<usize, FileError> {
+    let count = read_at(file, file.pos, buf)?;
+
+    file.pos += count as u64;
+    Ok(count)
+}
+
+/// Read from byte `offset` into `buf`, leaving the position alone.  Returns
+/// 0 at the end of the file.
+pub fn read_at(file: &File, offset: u64, buf: &mut [u8]) -> Result<usize, FileError> {
+    if file.flags & O_READ == 0 {
+        return Err(FileError::InvalidInput);
+    }
+
+    // Sizes are 32-bit on both filesystems.
+    let Ok(offset) = u32::try_from(offset) else {
+        return Ok(0);
+    };
+
+    match file.node {
+        Node::Fat12 {
+            start_cluster,
+            size,
+            ..
+        } => {
+            let entry = Entry {
+                start_cluster,
+                file_size: size,
+                ..Entry::default()
+            };
+
+            with_fat12(|fs| fs.read_at(&entry, offset, buf))
+        }
+        Node::Iso9660 { lba, size } => {
+            let iso = Iso9660::probe().ok_or(FileError::Filesystem)?;
+            let entry = IsoEntry {
+                lba,
+                size,
+                ..IsoEntry::default()
+            };
+
+            Ok(iso.read_at(&entry, offset, buf))
+        }
+        _ => Err(FileError::InvalidInput),
+    }
+}
+
+/// Write `data` at the current position, or at the end of the file with
+/// O_APPEND, and advance past the bytes written.  Fewer bytes than asked are
+/// written only when the disk is full.  The console streams are not handled
+/// here.
+pub fn write(file: &mut File, data: &[u8]) -> Result<usize, FileError> {
+    if file.flags & O_WRITE == 0 {
+        return Err(FileError::InvalidInput);
+    }
+
+    let Node::Fat12 {
+        entry_lba,
+        entry_offset,
+        start_cluster,
+        size,
+    } = file.node
+    else {
+        return Err(FileError::InvalidInput);
+    };
+
+    if file.flags & O_APPEND != 0 {
+        file.pos = size as u64;
+    }
+
+    let offset = u32::try_from(file.pos).map_err(|_| FileError::InvalidInput)?;
+
+    let entry = Entry {
+        start_cluster,
+        file_size: size,
+        ..Entry::default()
+    };
+    let (count, entry) =
+        with_fat12(|fs| fs.write_entry_at(entry_lba, entry_offset, entry, offset, data))?;
+
+    file.node = Node::Fat12 {
+        entry_lba,
+        entry_offset,
+        start_cluster: entry.start_cluster,
+        size: entry.file_size,
+    };
+    file.pos += count as u64;
+    Ok(count)
+}
+
+/// Move the position to `offset` bytes from the start (SEEK_SET), the current
+/// position (SEEK_CUR) or the end of the file (SEEK_END).  The position may
+/// go past the end; a write there fills the gap with zeros.  Returns the new
+/// position.
+pub fn seek(file: &mut File, offset: i64, whence: u64) -> Result<u64, FileError> {
+    if file.is_console() {
+        return Err(FileError::InvalidInput);
+    }
+
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => file.pos,
+        SEEK_END => size(file),
+        _ => return Err(FileError::InvalidInput),
+    };
+
+    file.pos = base
+        .checked_add_signed(offset)
+        .ok_or(FileError::InvalidInput)?;
+
+    Ok(file.pos)
+}
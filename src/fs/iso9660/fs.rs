@@ -188,6 +188,27 @@ impl Iso9660 {
         }
         done
     }
+
+    /// Read up to `buf.len()` bytes of a file starting at byte `offset`.
+    /// Returns the number of bytes read, 0 at the end of the file.
+    pub fn read_at(&self, entry: &IsoEntry, offset: u32, buf: &mut [u8]) -> usize {
+        if offset >= entry.size { return 0; }
+
+        let total = ((entry.size - offset) as usize).min(buf.len());
+        let mut done = 0usize;
+        let mut pos = offset as usize;
+
+        while done < total {
+            let mut block = [0u8; BLOCK_SIZE];
+            if !self.atapi.read_block(entry.lba + (pos / BLOCK_SIZE) as u32, &mut block) { break; }
+            let in_block = pos % BLOCK_SIZE;
+            let to_copy = (total - done).min(BLOCK_SIZE - in_block);
+            buf[done .. done + to_copy].copy_from_slice(&block[in_block .. in_block + to_copy]);
+            done += to_copy;
+            pos += to_copy;
+        }
+        done
+    }
 }
 
 fn strip_version(name: &[u8]) -> &[u8] {
@@ -1,5 +1,6 @@
 pub mod block;
 pub mod fat12;
+pub mod file;
 pub mod iso9660;
 pub mod memdisk;
 pub mod ustar;
@@ -0,0 +1,791 @@
+//! GDB Remote Serial Protocol stub.
+//!
+//! Once started, breakpoints (#BP), single steps (#DB) and faults stop the
+//! whole system in `stop`, which then serves gdb's packets on a COM port with
+//! interrupts off until gdb resumes it.  Every scheduler entry is reported as
+//! a thread with ID PID + 1: registers of the stopped one come from its trap
+//! frame, those of the others from the InterruptFrame saved when they were
+//! switched out.  Memory is accessed through the page table of the selected
+//! thread.
+use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+
+use crate::{
+    mem::{
+        pages::{self, KERNEL_CR3},
+        vm,
+    },
+    net::serial,
+    task::{
+        context::{InterruptFrame, TrapFrame},
+        scheduler::{self, DebugThread, RFLAGS_USER},
+        signal::{SIGINT, SIGTRAP},
+    },
+};
+
+/// Trap flag: the CPU raises #DB after the next instruction.
+pub const RFLAGS_TF: u64 = 0x100;
+
+/// Largest packet exchanged; advertised to gdb in qSupported.
+const PACKET_SIZE: usize = 2048;
+
+/// Software breakpoints set at once.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// Scheduler entries reported to gdb.
+const MAX_THREADS: usize = 64;
+
+/// Registers of the `g` packet: rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp,
+/// r8-r15 and rip as 64-bit values, then eflags, cs, ss, ds, es, fs and gs as
+/// 32-bit ones.  gdb takes the FPU and SSE registers as unavailable.
+const NUM_REGS: usize = 24;
+const NUM_REGS_64: usize = 17;
+
+/// I/O base of the COM port of the session, 0 while the stub is not active.
+static PORT: AtomicU16 = AtomicU16::new(0);
+
+/// COM port asked for by the `gdb` boot option.
+static BOOT_PORT: AtomicU16 = AtomicU16::new(0);
+
+/// gdb resumed the system and waits for a stop reply.
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+/// gdb sent a Ctrl-C; the next single step trap is reported as SIGINT.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    /// Physical address of the patched byte, so a breakpoint in kernel code
+    /// is found whichever address space hits it.
+    phys: u64,
+    orig: u8,
+}
+
+static mut BREAKPOINTS: [Option<Breakpoint>; MAX_BREAKPOINTS] = [None; MAX_BREAKPOINTS];
+
+/// Port named by a `gdb` command or boot option argument: COM2 by default,
+/// as COM1 carries SLIP and the kernel log.
+pub fn parse_port(arg: &[u8]) -> Option<u16> {
+    if arg.is_empty() || arg.eq_ignore_ascii_case(b"com2") {
+        Some(serial::COM2)
+    } else if arg.eq_ignore_ascii_case(b"com1") {
+        Some(serial::COM1)
+    } else {
+        None
+    }
+}
+
+/// Remember the `gdb[=comN]` option of the kernel command line.
+pub fn parse_cmdline(cmdline: &[u8]) {
+    for opt in cmdline.split(|&b| b == b' ' || b == 0) {
+        let port = match opt.strip_prefix(b"gdb") {
+            Some(b"") => parse_port(b""),
+            Some(arg) => arg.strip_prefix(b"=").and_then(parse_port),
+            None => None,
+        };
+
+        if let Some(port) = port {
+            BOOT_PORT.store(port, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Wait for gdb at the end of kernel init if the boot option asked for it.
+pub fn boot_break() {
+    let port = BOOT_PORT.load(Ordering::Relaxed);
+
+    if port != 0 && !start(port) {
+        error!("gdb: no serial port found\n");
+    }
+}
+
+pub fn is_active() -> bool {
+    PORT.load(Ordering::Relaxed) != 0
+}
+
+/// Start the stub on the COM port at `port` and stop the system until gdb
+/// attaches and resumes it.  Returns false if there is no UART there.
+pub fn start(port: u16) -> bool {
+    if !serial::present(port) {
+        return false;
+    }
+
+    serial::init_port(port);
+
+    // Drop what gdb may have sent before the stub listened.
+    while serial::ready_on(port) {
+        serial::read_from(port);
+    }
+
+    RESUMED.store(false, Ordering::Relaxed);
+    PORT.store(port, Ordering::Relaxed);
+
+    unsafe {
+        core::arch::asm!("int3");
+    }
+
+    true
+}
+
+/// Poll the session port for a Ctrl-C from gdb, called every PIT tick.
+pub fn break_requested() -> bool {
+    let port = PORT.load(Ordering::Relaxed);
+
+    if port == 0 || !serial::ready_on(port) || serial::read_from(port) != 0x03 {
+        return false;
+    }
+
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    true
+}
+
+/// Handle a #BP or #DB trap.  Returns false if no debugger is attached.
+pub fn trap(frame: &mut TrapFrame) -> bool {
+    if !is_active() {
+        return false;
+    }
+
+    frame.rflags &= !RFLAGS_TF;
+
+    let mut swbreak = false;
+    let mut sig = SIGTRAP;
+
+    if frame.vector == 3 {
+        // int3 has already been executed; report the breakpoint address.
+        let addr = frame.rip.wrapping_sub(1);
+
+        if phys_of(current_cr3(), addr).is_some_and(|phys| find_breakpoint(phys).is_some()) {
+            frame.rip = addr;
+            swbreak = true;
+        }
+    } else if INTERRUPTED.swap(false, Ordering::Relaxed) {
+        sig = SIGINT;
+    }
+
+    stop(frame, sig, swbreak);
+    true
+}
+
+/// Let gdb look at a fault before the process is killed or the system
+/// halted.  Register writes have no effect.
+pub fn report_fault(frame: &TrapFrame, sig: u8) {
+    if is_active() {
+        let mut copy = *frame;
+        stop(&mut copy, sig, false);
+    }
+}
+
+/// Serve gdb until it resumes the system.
+fn stop(frame: &mut TrapFrame, sig: u8, swbreak: bool) {
+    let mut session = Session {
+        port: PORT.load(Ordering::Relaxed),
+        frame,
+        threads: [DebugThread::EMPTY; MAX_THREADS],
+        count: 0,
+        current: 0,
+        selected: None,
+        sig,
+        swbreak,
+    };
+
+    session.count = scheduler::debug_threads(&mut session.threads);
+    session.current = session.threads[..session.count]
+        .iter()
+        .find(|t| t.running)
+        .map_or(0, |t| t.pid);
+
+    if RESUMED.swap(false, Ordering::Relaxed) {
+        let mut out = Reply::new();
+        session.stop_reply(&mut out);
+        session.send(out.as_bytes());
+    }
+
+    session.serve();
+}
+
+fn current_cr3() -> u64 {
+    unsafe { pages::read_cr3() as u64 }
+}
+
+/// Identity-mapped address backing `virt` in the address space of `cr3`.
+/// Pages the process has not touched yet are filled in, so breakpoints can
+/// be set in code that has not run.
+fn phys_of(cr3: u64, virt: u64) -> Option<u64> {
+    unsafe { vm::resolve_in(cr3, virt) }
+}
+
+fn find_breakpoint(phys: u64) -> Option<usize> {
+    #[expect(static_mut_refs)]
+    unsafe {
+        BREAKPOINTS
+            .iter()
+            .position(|bp| bp.is_some_and(|bp| bp.phys == phys))
+    }
+}
+
+fn remove_all_breakpoints() {
+    #[expect(static_mut_refs)]
+    unsafe {
+        for bp in BREAKPOINTS.iter_mut() {
+            if let Some(b) = bp.take() {
+                *(b.phys as *mut u8) = b.orig;
+            }
+        }
+    }
+}
+
+struct Session<'a> {
+    port: u16,
+    frame: &'a mut TrapFrame,
+    threads: [DebugThread; MAX_THREADS],
+    count: usize,
+    /// PID of the stopped thread.
+    current: usize,
+    /// PID selected by `Hg`, None for the stopped thread.
+    selected: Option<usize>,
+    sig: u8,
+    swbreak: bool,
+}
+
+impl Session<'_> {
+    fn serve(&mut self) {
+        let mut packet = [0u8; PACKET_SIZE];
+
+        loop {
+            let len = self.recv(&mut packet);
+            let mut out = Reply::new();
+
+            let data = &packet[..len];
+            let Some((&cmd, args)) = data.split_first() else {
+                self.send(b"");
+                continue;
+            };
+
+            match cmd {
+                b'?' => self.stop_reply(&mut out),
+                b'g' => self.read_registers(&mut out),
+                b'G' => self.write_registers(args, &mut out),
+                b'm' => self.read_memory(args, &mut out),
+                b'M' => self.write_memory(args, &mut out),
+                b'c' | b's' => {
+                    if let Some(addr) = parse_hex(args) {
+                        self.frame.rip = addr;
+                    }
+                    if cmd == b's' {
+                        self.frame.rflags |= RFLAGS_TF;
+                    }
+
+                    RESUMED.store(true, Ordering::Relaxed);
+                    return;
+                }
+                b'D' | b'k' => {
+                    remove_all_breakpoints();
+                    PORT.store(0, Ordering::Relaxed);
+
+                    if cmd == b'D' {
+                        self.send(b"OK");
+                    }
+                    return;
+                }
+                b'H' => self.select_thread(args, &mut out),
+                b'T' => {
+                    if self.thread(parse_tid(args)).is_some() {
+                        out.push(b"OK");
+                    } else {
+                        out.push(b"E01");
+                    }
+                }
+                b'q' => self.query(args, &mut out),
+                b'Z' | b'z' => self.breakpoint(cmd == b'Z', args, &mut out),
+                // Anything else is not supported: an empty reply says so.
+                _ => {}
+            }
+
+            self.send(out.as_bytes());
+        }
+    }
+
+    fn stop_reply(&self, out: &mut Reply) {
+        out.push(b"T");
+        out.push_hex_u8(self.sig);
+        out.push(b"thread:");
+        out.push_num(self.current as u64 + 1);
+        out.push(b";");
+
+        if self.swbreak {
+            out.push(b"swbreak:;");
+        }
+    }
+
+    /// PID of thread ID `tid` if there is such a thread; 0 and -1 stand for
+    /// the stopped one.
+    fn thread(&self, tid: Option<i64>) -> Option<usize> {
+        match tid? {
+            0 | -1 => Some(self.current),
+            tid if tid > 0 => {
+                let pid = tid as usize - 1;
+
+                (pid == self.current || self.threads[..self.count].iter().any(|t| t.pid == pid))
+                    .then_some(pid)
+            }
+            _ => None,
+        }
+    }
+
+    /// The saved frame of `pid` if it is not the stopped thread.
+    fn saved_frame(&self, pid: usize) -> Option<*mut InterruptFrame> {
+        if pid == self.current {
+            return None;
+        }
+
+        self.threads[..self.count]
+            .iter()
+            .find(|t| t.pid == pid && t.last_rsp != 0)
+            .map(|t| t.last_rsp as *mut InterruptFrame)
+    }
+
+    /// Page table the memory packets go through.
+    fn cr3(&self) -> u64 {
+        let pid = self.selected.unwrap_or(self.current);
+
+        if pid == self.current {
+            return current_cr3();
+        }
+
+        match self.threads[..self.count].iter().find(|t| t.pid == pid) {
+            Some(t) if t.cr3 != 0 => t.cr3,
+            _ => unsafe { KERNEL_CR3 },
+        }
+    }
+
+    /// Registers of the selected thread, in `g` packet order.
+    fn registers(&self) -> [u64; NUM_REGS] {
+        let f = match self.selected.and_then(|pid| self.saved_frame(pid)) {
+            Some(saved) => from_interrupt_frame(unsafe { &*saved }),
+            None => *self.frame,
+        };
+
+        [
+            f.rax, f.rbx, f.rcx, f.rdx, f.rsi, f.rdi, f.rbp, f.rsp, f.r8, f.r9, f.r10, f.r11,
+            f.r12, f.r13, f.r14, f.r15, f.rip, f.rflags, f.cs, f.ss, 0, 0, 0, 0,
+        ]
+    }
+
+    fn read_registers(&self, out: &mut Reply) {
+        for (i, reg) in self.registers().iter().enumerate() {
+            let size = if i < NUM_REGS_64 { 8 } else { 4 };
+            out.push_hex_le(*reg, size);
+        }
+    }
+
+    /// Apply a `G` packet.  Segment registers cannot be changed, and of
+    /// RFLAGS only the bits sigreturn lets user code restore.
+    fn write_registers(&mut self, args: &[u8], out: &mut Reply) {
+        let mut regs = self.registers();
+        let mut pos = 0;
+
+        for (i, reg) in regs.iter_mut().enumerate().take(NUM_REGS_64 + 1) {
+            let size = if i < NUM_REGS_64 { 8 } else { 4 };
+            let Some(value) = args.get(pos..pos + size * 2).and_then(parse_hex_le) else {
+                break;
+            };
+
+            *reg = value;
+            pos += size * 2;
+        }
+
+        let saved = self.selected.and_then(|pid| self.saved_frame(pid));
+        let mut f = match saved {
+            Some(saved) => from_interrupt_frame(unsafe { &*saved }),
+            None => *self.frame,
+        };
+
+        [
+            f.rax, f.rbx, f.rcx, f.rdx, f.rsi, f.rdi, f.rbp, f.rsp, f.r8, f.r9, f.r10, f.r11,
+            f.r12, f.r13, f.r14, f.r15, f.rip,
+        ] = [
+            regs[0], regs[1], regs[2], regs[3], regs[4], regs[5], regs[6], regs[7], regs[8],
+            regs[9], regs[10], regs[11], regs[12], regs[13], regs[14], regs[15], regs[16],
+        ];
+        f.rflags = (f.rflags & !RFLAGS_USER) | (regs[17] & RFLAGS_USER);
+
+        match saved {
+            Some(saved) => to_interrupt_frame(&f, unsafe { &mut *saved }),
+            None => *self.frame = f,
+        }
+
+        out.push(b"OK");
+    }
+
+    fn read_memory(&self, args: &[u8], out: &mut Reply) {
+        let Some((addr, len)) = parse_addr_len(args) else {
+            out.push(b"E01");
+            return;
+        };
+
+        let cr3 = self.cr3();
+        let len = len.min((PACKET_SIZE / 2) as u64);
+
+        for i in 0..len {
+            let Some(phys) = phys_of(cr3, addr.wrapping_add(i)) else {
+                break;
+            };
+
+            out.push_hex_u8(unsafe { *(phys as *const u8) });
+        }
+
+        if out.len == 0 && len > 0 {
+            out.push(b"E14");
+        }
+    }
+
+    fn write_memory(&self, args: &[u8], out: &mut Reply) {
+        let Some(colon) = args.iter().position(|&b| b == b':') else {
+            out.push(b"E01");
+            return;
+        };
+        let Some((addr, len)) = parse_addr_len(&args[..colon]) else {
+            out.push(b"E01");
+            return;
+        };
+
+        let data = &args[colon + 1..];
+        let cr3 = self.cr3();
+
+        for i in 0..len {
+            let i = i as usize;
+            let (Some(byte), Some(phys)) = (
+                data.get(i * 2..i * 2 + 2).and_then(parse_hex_le),
+                phys_of(cr3, addr.wrapping_add(i as u64)),
+            ) else {
+                out.push(b"E14");
+                return;
+            };
+
+            unsafe {
+                *(phys as *mut u8) = byte as u8;
+            }
+        }
+
+        out.push(b"OK");
+    }
+
+    fn select_thread(&mut self, args: &[u8], out: &mut Reply) {
+        let Some((&op, tid)) = args.split_first() else {
+            out.push(b"E01");
+            return;
+        };
+
+        // Resuming always resumes the whole system, so `Hc` has no effect.
+        if op == b'c' {
+            out.push(b"OK");
+            return;
+        }
+
+        match self.thread(parse_tid(tid)) {
+            Some(pid) => {
+                self.selected = (pid != self.current).then_some(pid);
+                out.push(b"OK");
+            }
+            None => out.push(b"E01"),
+        }
+    }
+
+    fn query(&self, args: &[u8], out: &mut Reply) {
+        if args.starts_with(b"Supported") {
+            out.push(b"PacketSize=");
+            out.push_num(PACKET_SIZE as u64);
+            out.push(b";swbreak+");
+        } else if args == b"Attached" {
+            out.push(b"1");
+        } else if args == b"C" {
+            out.push(b"QC");
+            out.push_num(self.current as u64 + 1);
+        } else if args == b"fThreadInfo" {
+            out.push(b"m");
+            out.push_num(self.current as u64 + 1);
+
+            for t in &self.threads[..self.count] {
+                if t.pid != self.current {
+                    out.push(b",");
+                    out.push_num(t.pid as u64 + 1);
+                }
+            }
+        } else if args == b"sThreadInfo" {
+            out.push(b"l");
+        } else if let Some(tid) = args.strip_prefix(b"ThreadExtraInfo,") {
+            let Some(t) = self
+                .thread(parse_tid(tid))
+                .and_then(|pid| self.threads[..self.count].iter().find(|t| t.pid == pid))
+            else {
+                out.push_hex_bytes(b"kernel");
+                return;
+            };
+
+            let name_len = t
+                .name
+                .iter()
+                .rposition(|&b| b != b' ' && b != 0)
+                .map_or(0, |i| i + 1);
+
+            out.push_hex_bytes(&t.name[..name_len]);
+            out.push_hex_bytes(b" ");
+            out.push_hex_bytes(t.status.as_str().as_bytes());
+        } else if args.starts_with(b"Symbol") {
+            out.push(b"OK");
+        }
+    }
+
+    /// `Z0`/`z0`: set or remove a software breakpoint by patching in int3.
+    fn breakpoint(&self, insert: bool, args: &[u8], out: &mut Reply) {
+        let Some(rest) = args.strip_prefix(b"0,") else {
+            return;
+        };
+        let addr_end = rest.iter().position(|&b| b == b',').unwrap_or(rest.len());
+        let Some(phys) = parse_hex(&rest[..addr_end]).and_then(|addr| phys_of(self.cr3(), addr))
+        else {
+            out.push(b"E14");
+            return;
+        };
+
+        let found = find_breakpoint(phys);
+
+        #[expect(static_mut_refs)]
+        unsafe {
+            if insert {
+                if found.is_none() {
+                    let Some(slot) = BREAKPOINTS.iter_mut().find(|bp| bp.is_none()) else {
+                        out.push(b"E0C");
+                        return;
+                    };
+
+                    *slot = Some(Breakpoint {
+                        phys,
+                        orig: *(phys as *const u8),
+                    });
+                    *(phys as *mut u8) = 0xCC;
+                }
+            } else if let Some(i) = found {
+                if let Some(bp) = BREAKPOINTS[i].take() {
+                    *(bp.phys as *mut u8) = bp.orig;
+                }
+            }
+        }
+
+        out.push(b"OK");
+    }
+
+    fn getc(&self) -> u8 {
+        while !serial::ready_on(self.port) {
+            core::hint::spin_loop();
+        }
+
+        serial::read_from(self.port)
+    }
+
+    fn putc(&self, b: u8) {
+        serial::write_to(self.port, b);
+    }
+
+    /// Receive the next packet with a valid checksum into `buf` and ack it.
+    /// Returns its length; longer packets are cut to the buffer.
+    fn recv(&self, buf: &mut [u8; PACKET_SIZE]) -> usize {
+        loop {
+            while self.getc() != b'$' {}
+
+            let mut len = 0;
+            let mut sum: u8 = 0;
+
+            loop {
+                let c = self.getc();
+                if c == b'#' {
+                    break;
+                }
+
+                sum = sum.wrapping_add(c);
+                if let Some(slot) = buf.get_mut(len) {
+                    *slot = c;
+                    len += 1;
+                }
+            }
+
+            let checksum = [self.getc(), self.getc()];
+
+            if parse_hex_le(&checksum) == Some(sum as u64) {
+                self.putc(b'+');
+                return len;
+            }
+
+            self.putc(b'-');
+        }
+    }
+
+    /// Send a packet, again until gdb acknowledges it.
+    fn send(&self, data: &[u8]) {
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+
+        loop {
+            self.putc(b'$');
+            for &b in data {
+                self.putc(b);
+            }
+            self.putc(b'#');
+            self.putc(HEX[(sum >> 4) as usize]);
+            self.putc(HEX[(sum & 0xF) as usize]);
+
+            loop {
+                match self.getc() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn from_interrupt_frame(f: &InterruptFrame) -> TrapFrame {
+    TrapFrame {
+        rax: f.rax,
+        rbx: f.rbx,
+        rcx: f.rcx,
+        rdx: f.rdx,
+        rbp: f.rbp,
+        rdi: f.rdi,
+        rsi: f.rsi,
+        r8: f.r8,
+        r9: f.r9,
+        r10: f.r10,
+        r11: f.r11,
+        r12: f.r12,
+        r13: f.r13,
+        r14: f.r14,
+        r15: f.r15,
+        rip: f.rip,
+        cs: f.cs,
+        rflags: f.rflags,
+        rsp: f.rsp,
+        ss: f.ss,
+        ..TrapFrame::ZERO
+    }
+}
+
+fn to_interrupt_frame(t: &TrapFrame, f: &mut InterruptFrame) {
+    *f = InterruptFrame {
+        rax: t.rax,
+        rbx: t.rbx,
+        rcx: t.rcx,
+        rdx: t.rdx,
+        rbp: t.rbp,
+        rdi: t.rdi,
+        rsi: t.rsi,
+        r8: t.r8,
+        r9: t.r9,
+        r10: t.r10,
+        r11: t.r11,
+        r12: t.r12,
+        r13: t.r13,
+        r14: t.r14,
+        r15: t.r15,
+        rip: t.rip,
+        cs: t.cs,
+        rflags: t.rflags,
+        rsp: t.rsp,
+        ss: t.ss,
+    };
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// An outgoing packet body.
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        for &b in data {
+            if let Some(slot) = self.buf.get_mut(self.len) {
+                *slot = b;
+                self.len += 1;
+            }
+        }
+    }
+
+    fn push_hex_u8(&mut self, b: u8) {
+        self.push(&[HEX[(b >> 4) as usize], HEX[(b & 0xF) as usize]]);
+    }
+
+    /// `value` as `size` bytes in target (little endian) order.
+    fn push_hex_le(&mut self, value: u64, size: usize) {
+        for b in &value.to_le_bytes()[..size] {
+            self.push_hex_u8(*b);
+        }
+    }
+
+    fn push_hex_bytes(&mut self, data: &[u8]) {
+        for &b in data {
+            self.push_hex_u8(b);
+        }
+    }
+
+    /// `n` as a big endian hex number without leading zeros.
+    fn push_num(&mut self, n: u64) {
+        let digits = (64 - n.leading_zeros()).div_ceil(4).max(1);
+
+        for i in (0..digits).rev() {
+            self.push(&[HEX[((n >> (i * 4)) & 0xF) as usize]]);
+        }
+    }
+}
+
+fn hex_digit(c: u8) -> Option<u64> {
+    (c as char).to_digit(16).map(|d| d as u64)
+}
+
+/// A big endian hex number, such as an address or a length.
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+
+    s.iter()
+        .try_fold(0u64, |n, &c| Some((n << 4) | hex_digit(c)?))
+}
+
+/// Hex bytes in target (little endian) order, such as a register value.
+fn parse_hex_le(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 || s.len() % 2 != 0 {
+        return None;
+    }
+
+    s.chunks(2).enumerate().try_fold(0u64, |n, (i, pair)| {
+        Some(n | ((hex_digit(pair[0])? << 4 | hex_digit(pair[1])?) << (i * 8)))
+    })
+}
+
+/// A thread ID, which may be -1 for all threads.
+fn parse_tid(s: &[u8]) -> Option<i64> {
+    match s {
+        b"-1" => Some(-1),
+        _ => parse_hex(s).map(|n| n as i64),
+    }
+}
+
+/// `addr,len` of the memory packets.
+fn parse_addr_len(s: &[u8]) -> Option<(u64, u64)> {
+    let comma = s.iter().position(|&b| b == b',')?;
+
+    Some((parse_hex(&s[..comma])?, parse_hex(&s[comma + 1..])?))
+}
@@ -76,6 +76,9 @@ pub unsafe fn parse_multiboot2_info(base_addr: u32, fb_tag: &mut FramebufferTag)
     let mut ptr = addr + 8;
     let end = addr + total_size;
 
+    // The tags stay in usable RAM; keep the frame allocator off them.
+    crate::mem::frames::reserve(addr as u64, total_size as u64);
+
     let mut tag_count = 0;
 
     while ptr < end {
@@ -100,13 +103,17 @@ pub unsafe fn parse_multiboot2_info(base_addr: u32, fb_tag: &mut FramebufferTag)
 
                 let cmdline = core::str::from_utf8_unchecked(raw_bytes);
                 debugln!(cmdline);
+
+                crate::gdb::parse_cmdline(raw_bytes);
             }
 
             3 => {
                 debug!("Module tag found: ");
 
-                //let start = *((ptr + 8) as *const u32);
-                //let end = *((ptr + 12) as *const u32);
+                let start = *((ptr + 8) as *const u32);
+                let end = *((ptr + 12) as *const u32);
+                crate::mem::frames::reserve(start as u64, end.saturating_sub(start) as u64);
+
                 let str_ptr = ptr + 16;
                 let str_len = tag.size as usize - 16;
                 let raw_bytes = core::slice::from_raw_parts(str_ptr as *const u8, str_len);
@@ -119,7 +126,7 @@ pub unsafe fn parse_multiboot2_info(base_addr: u32, fb_tag: &mut FramebufferTag)
                 debugln!("Memory map tag");
 
                 let mmap_tag = &*(ptr as *const MemoryMapTag);
-                let entries_start = (addr + core::mem::size_of::<MemoryMapTag>()) as *const u8;
+                let entries_start = (ptr + core::mem::size_of::<MemoryMapTag>()) as *const u8;
                 let entry_size = mmap_tag.entry_size as usize;
 
                 if entry_size > 0 {
@@ -137,6 +144,8 @@ pub unsafe fn parse_multiboot2_info(base_addr: u32, fb_tag: &mut FramebufferTag)
                             debug!(" - ");
                             debugn!(entry.length as u64);
                             debugln!(" B");
+
+                            crate::mem::frames::add_region(entry.base_addr, entry.length);
                         }
                     }
                 }
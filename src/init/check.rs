@@ -1,6 +1,6 @@
 //use crate::debug::dump_debug_log_to_file;
 
-use crate::init::{ascii, boot, color, cpu, fs, heap, idt, parser, pit, video};
+use crate::init::{ascii, boot, color, cpu, fs, heap, idt, paging, parser, pit, video};
 
 use crate::video::vga;
 //Results of init system
@@ -46,6 +46,8 @@ pub fn init(m2_ptr: u32) {
 
     result!("Initializing heap allocation", heap::pmm_heap_init());
 
+    result!("Initializing frame allocator", heap::pmm_frames_init());
+
     result!("Initializing video", unsafe {
         video::print_result(&FRAMEBUFFER_PTR)
     });
@@ -63,6 +65,11 @@ pub fn init(m2_ptr: u32) {
         super::process::init_processes();
         //crate::task::scheduler::list_processes();
     }
+
+    result!("Checking demand paging", paging::demand_paging_check());
     //result!("Starting PIC time and task scheduler", pit::pic_pit_init());
     pit::pic_pit_init();
+
+    // Boot option `gdb[=com1|com2]`
+    crate::gdb::boot_break();
 }
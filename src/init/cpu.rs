@@ -1,3 +1,4 @@
+use crate::abi::syscall::syscall_entry;
 use crate::video::sysprint::Result;
 use core::arch::asm;
 
@@ -70,7 +71,20 @@ fn cpuid(eax: u32) -> u32 {
 //
 
 const IA32_EFER: u32 = 0xC0000080;
+const IA32_STAR: u32 = 0xC0000081;
 const IA32_LSTAR: u32 = 0xC0000082;
+const IA32_FMASK: u32 = 0xC0000084;
+
+/// Kernel code selector; SYSCALL loads CS from it and SS from the next entry.
+const KERNEL_CS: u64 = 0x08;
+
+/// SYSRET loads SS from this selector + 8 and CS from + 16, both with RPL 3:
+/// the user data (0x18) and user code (0x20) entries of the GDT in boot.asm.
+const SYSRET_BASE: u64 = 0x10;
+
+/// RFLAGS bits cleared on SYSCALL: TF, IF, DF and AC.  Interrupts stay off
+/// until `syscall_entry` is on the kernel stack.
+const SYSCALL_FMASK: u64 = (1 << 8) | (1 << 9) | (1 << 10) | (1 << 18);
 
 unsafe fn wrmsr(msr: u32, value: u64) {
     let low = value as u32;
@@ -97,22 +111,13 @@ unsafe fn rdmsr(msr: u32) -> u64 {
     ((high as u64) << 32) | (low as u64)
 }
 
-#[unsafe(naked)]
-unsafe extern "C" fn syscall_handler() {
-    core::arch::naked_asm!(
-        "swapgs",   // swap GS base to kernel GS base
-        "push rcx", // save RCX (return RIP)
-        "push r11", // save R11 (RFLAGS)
-        //
-        "pop r11", "pop rcx", "sysretq",
-    );
-}
-
 fn enable_syscalls() {
     unsafe {
-        // Set IA32_LSTAR to syscall_handler address
-        #[expect(clippy::fn_to_numeric_cast)]
-        wrmsr(IA32_LSTAR, syscall_handler as u64);
+        wrmsr(IA32_STAR, (SYSRET_BASE << 48) | (KERNEL_CS << 32));
+
+        // Set IA32_LSTAR to syscall_entry address
+        wrmsr(IA32_LSTAR, syscall_entry as *const () as u64);
+        wrmsr(IA32_FMASK, SYSCALL_FMASK);
 
         // Enable syscall/sysret in EFER (bit 0 = SCE)
         let mut efer = rdmsr(IA32_EFER);
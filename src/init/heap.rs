@@ -1,12 +1,7 @@
-use crate::mem::bump::{ALLOCATOR};
-use core::ptr;
-
 use crate::video::sysprint::{Result};
 
 pub fn pmm_heap_init() -> Result {
-    /*if !init_heap_allocator() {
-      return InitResult::Failed;
-      }*/
+    init_heap_allocator();
 
     crate::mem::heap::init();
 
@@ -61,24 +56,18 @@ pub fn pmm_heap_init() -> Result {
     Result::Passed
 }
 
-fn init_heap_allocator() -> bool {
+fn init_heap_allocator() {
     debugln!("Heap allocator init start");
 
-    unsafe {
-        unsafe extern "C" {
-            static __heap_start: u8;
-            static __heap_end: u8;
-        }
-
-        let heap_start = &__heap_start as *const u8 as usize;
-        let heap_end = &__heap_end as *const u8 as usize;
-        let heap_size = heap_end - heap_start;
+    crate::mem::kheap::init();
+}
 
-        //#![allow(static_mut_refs)]
-        let allocator_ptr = ptr::addr_of_mut!(ALLOCATOR);
-        (*allocator_ptr).init(heap_start, heap_size);
+/// Seed the physical frame allocator with the memory map read from the Multiboot2 tags.  Needs
+/// the kernel heap for its bitmap.
+pub fn pmm_frames_init() -> Result {
+    if crate::mem::frames::init() {
+        return Result::Passed;
     }
 
-    true
+    Result::Failed
 }
-
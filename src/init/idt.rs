@@ -26,6 +26,7 @@ extern "C" {
     static mut __stack_top: u64;
     static mut ist0_stack_top: u64;
     static mut ist1_stack_top: u64;
+    static mut ist2_stack_top: u64;
 }
 
 #[repr(C, packed)]
@@ -136,6 +137,9 @@ fn init_tss() {
         tss64.rsp0 = core::ptr::addr_of!(__stack_top) as u64;
         tss64.ist1 = core::ptr::addr_of!(ist0_stack_top) as u64;
         tss64.ist2 = core::ptr::addr_of!(ist1_stack_top) as u64;
+        // `set_stack_index(n)` in the IDT selects IST n + 1: #DF uses 1 and
+        // #PF 2.
+        tss64.ist3 = core::ptr::addr_of!(ist2_stack_top) as u64;
 
         // IO Map base: set to size of TSS to disable IO bitmap
         tss64.io_map_base = core::mem::size_of::<Tss64>() as u16;
@@ -6,6 +6,7 @@ pub mod font;
 pub mod fs;
 pub mod heap;
 pub mod idt;
+pub mod paging;
 pub mod pit;
 //mod multiboot2;
 pub mod boot;
@@ -0,0 +1,55 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::mem::{pages, vm};
+use crate::video::sysprint::Result;
+
+/// Stack area of the throwaway address space; only its top page is touched.
+const CHECK_STACK: Range<u64> = vm::HIGH_START + 0x10_000..vm::HIGH_START + 0x20_000;
+
+/// Value written to the demand-paged page and read back.
+const PATTERN: u64 = 0x5EED_F00D_CAFE_B0BA;
+
+/// Take a real demand fault at boot: build a user address space with one
+/// untouched stack area, switch to it and write to the area from ring 0.  The
+/// #PF handler, on its own IST stack, must map the page through
+/// `vm::handle_fault` for the write to be retried.  Needs `save_kernel_cr3`
+/// and `split_kernel_heap`.
+pub fn demand_paging_check() -> Result {
+    unsafe {
+        let cr3 = pages::create_user_page_table();
+
+        if !vm::create(cr3, Vec::new(), &[], &[CHECK_STACK]) {
+            pages::free_user_page_table(cr3);
+            return Result::Failed;
+        }
+
+        let addr = CHECK_STACK.end - 8;
+        let mapped_before = pages::translate_in(cr3, addr).is_some();
+
+        let read = without_interrupts(|| {
+            let kernel_cr3 = pages::read_cr3() as u64;
+
+            core::arch::asm!("mov cr3, {}", in(reg) cr3, options(nostack, preserves_flags));
+
+            (addr as *mut u64).write_volatile(PATTERN);
+            let read = (addr as *const u64).read_volatile();
+
+            core::arch::asm!("mov cr3, {}", in(reg) kernel_cr3, options(nostack, preserves_flags));
+
+            read
+        });
+
+        let mapped_after = pages::translate_in(cr3, addr).is_some();
+
+        vm::release(cr3);
+        pages::free_user_page_table(cr3);
+
+        if !mapped_before && mapped_after && read == PATTERN {
+            Result::Passed
+        } else {
+            Result::Failed
+        }
+    }
+}
@@ -82,6 +82,12 @@ pub fn pic_pit_init() -> Result {
         remap_pic();
     }
 
+    // IRQ12 is routed to the handler at 0x2C only after the remap.
+    debugln!("Enabling PS/2 mouse");
+    if !crate::input::mouse::init() {
+        debugln!("No PS/2 mouse answered, skipping it");
+    }
+
     debugln!("Starting 100Hz timer");
     init_pit(TICKS_PER_SECOND as u32);
 
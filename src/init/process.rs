@@ -1,12 +1,15 @@
 use crate::input::{cmd, keyboard::keyboard_loop};
-use crate::task::{process::Mode, scheduler};
+use crate::task::{
+    process::{Mode, SpawnOptions, DEFAULT_PRIORITY, PRIORITY_LEVELS},
+    scheduler,
+};
 pub unsafe fn init_processes() {
     // Snapshot the boot-time CR3 before any per-process tables are created.
     crate::mem::pages::save_kernel_cr3();
-    // Map 0xC00_000–0xFFF_FFF as user-accessible and initialise the heap.
-    // Must run after save_kernel_cr3 so create_user_page_table inherits the
-    // updated P2[6/7] entries when it clones the kernel page table.
-    crate::mem::uheap::init();
+    // Map the kernel heap with 4 KiB pages so kernel stacks get guard pages.
+    // Must run before the first kernel stack is taken and before any user
+    // table clones the kernel P2.
+    crate::mem::pages::split_kernel_heap();
     setup_processes();
 }
 
@@ -17,34 +20,47 @@ unsafe fn setup_processes() {
     // Without this sentinel that write would clobber the iretq entry frame
     // of whichever real process occupies slot 0, preventing it from ever
     // executing its entry function.
-    scheduler::new_process(
-        *b"kmain           ",
-        Mode::Kernel,
-        kernel_idle as *const () as u64,
-        0,
-        0,
-    );
-    scheduler::new_process(
-        *b"init_rc         ",
-        Mode::Kernel,
-        init_rc as *const () as u64,
-        0,
-        0,
-    );
-    scheduler::new_process(
-        *b"clock           ",
-        Mode::Kernel,
-        clock_test as *const () as u64,
-        0x2B0_000,
-        0,
-    );
-    let shell_pid = scheduler::new_process(
-        *b"shell           ",
-        Mode::Kernel,
-        keyboard_loop as *const () as u64,
-        0x2D0_000,
-        0,
-    );
+    scheduler::new_process(SpawnOptions {
+        name: *b"kmain           ",
+        mode: Mode::Kernel,
+        entry: kernel_idle as *const () as u64,
+        stack_top: 0,
+        cr3: 0,
+        priority: PRIORITY_LEVELS - 1,
+        image_start: 0,
+        image_end: 0,
+    });
+    scheduler::new_process(SpawnOptions {
+        name: *b"init_rc         ",
+        mode: Mode::Kernel,
+        entry: init_rc as *const () as u64,
+        stack_top: 0,
+        cr3: 0,
+        priority: DEFAULT_PRIORITY,
+        image_start: 0,
+        image_end: 0,
+    });
+    scheduler::new_process(SpawnOptions {
+        name: *b"clock           ",
+        mode: Mode::Kernel,
+        entry: clock_test as *const () as u64,
+        stack_top: 0x2B0_000,
+        cr3: 0,
+        priority: DEFAULT_PRIORITY + 2,
+        image_start: 0,
+        image_end: 0,
+    });
+    // The rescue shell runs ahead of background ELFs so it stays responsive.
+    let shell_pid = scheduler::new_process(SpawnOptions {
+        name: *b"shell           ",
+        mode: Mode::Kernel,
+        entry: keyboard_loop as *const () as u64,
+        stack_top: 0x2D0_000,
+        cr3: 0,
+        priority: DEFAULT_PRIORITY - 2,
+        image_start: 0,
+        image_end: 0,
+    });
     scheduler::set_shell_pid(shell_pid);
 }
 
@@ -96,7 +112,7 @@ extern "C" fn init_rc() -> ! {
 
     let pid = unsafe { scheduler::get_current_pid() };
     unsafe {
-        scheduler::kill(pid);
+        scheduler::exit(pid, 0);
     }
     loop {
         unsafe {
@@ -1,3 +1,8 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::abi::strace::{self, Trace};
 use crate::acpi;
 use crate::audio;
 use crate::debug;
@@ -8,13 +13,31 @@ use crate::fs::fat12::{
 };
 use crate::fs::iso9660::Iso9660;
 use crate::fs::vfs;
+use crate::gdb;
 use crate::init::config;
+use crate::init::pit::TICKS_PER_SECOND;
 use crate::input::keyboard;
+use crate::mem::frames;
+use crate::net::serial;
+use crate::task::{
+    process::{Mode, SpawnOptions, DEFAULT_PRIORITY, PRIORITY_LEVELS},
+    scheduler, signal,
+};
 use crate::time;
-use crate::video::vga::Color;
+use crate::video::vga::{self, Color};
 
 const KERNEL_VERSION: &[u8] = b"0.11.0";
 
+/// Exit status of the last command, like `$?` in a POSIX shell (0 = success). Built-in commands
+/// always succeed; `fg` stores the exit code of the program.
+static LAST_STATUS: AtomicU64 = AtomicU64::new(0);
+
+/// Status of an unknown command, as in POSIX shells.
+const STATUS_NOT_FOUND: u64 = 127;
+
+/// Output of a built-in command at the head of a pipeline, until `feed_pipe` writes it out.
+static PIPE_FEED: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
 struct Command {
     name: &'static [u8],
     description: &'static [u8],
@@ -71,12 +94,24 @@ static COMMANDS: &[Command] = &[
         function: cmd_fg,
         hidden: false,
     },
+    Command {
+        name: b"free",
+        description: b"shows the physical memory use",
+        function: cmd_free,
+        hidden: false,
+    },
     Command {
         name: b"fsck",
         description: b"runs the filesystem check",
         function: cmd_fsck,
         hidden: false,
     },
+    Command {
+        name: b"gdb",
+        description: b"waits for gdb on a serial port (COM2 by default)",
+        function: cmd_gdb,
+        hidden: false,
+    },
     Command {
         name: b"help",
         description: b"shows this output",
@@ -91,7 +126,7 @@ static COMMANDS: &[Command] = &[
     },
     Command {
         name: b"kill",
-        description: b"makes a process dead",
+        description: b"sends a signal to a process (TERM by default)",
         function: cmd_kill,
         hidden: false,
     },
@@ -119,6 +154,18 @@ static COMMANDS: &[Command] = &[
         function: cmd_mv,
         hidden: false,
     },
+    Command {
+        name: b"nice",
+        description: b"sets the scheduling priority of a process",
+        function: cmd_nice,
+        hidden: false,
+    },
+    Command {
+        name: b"overflow",
+        description: b"overflows a kernel stack (panics)",
+        function: cmd_overflow,
+        hidden: true,
+    },
     Command {
         name: b"read",
         description: b"prints the output of a file",
@@ -143,12 +190,30 @@ static COMMANDS: &[Command] = &[
         function: cmd_run,
         hidden: true,
     },
+    Command {
+        name: b"strace",
+        description: b"logs the syscalls of a process",
+        function: cmd_strace,
+        hidden: false,
+    },
+    Command {
+        name: b"svc",
+        description: b"lists the registered services",
+        function: cmd_svc,
+        hidden: false,
+    },
     Command {
         name: b"time",
         description: b"prints system time and date",
         function: cmd_time,
         hidden: false,
     },
+    Command {
+        name: b"top",
+        description: b"shows the busiest tasks, refreshed every second",
+        function: cmd_top,
+        hidden: false,
+    },
     Command {
         name: b"ts",
         description: b"lists currently running tasks",
@@ -171,11 +236,32 @@ static COMMANDS: &[Command] = &[
 
 /// Handle takes in an input from keyboard and tries to match it to a defined Command to execute it
 /// with given arguments.
+///
+/// Commands can be chained like in a POSIX shell: `a && b` runs `b` only if `a` succeeded,
+/// `a || b` only if it failed. Programs can be connected with pipes: `a | b` feeds the output of
+/// `a` to `b`.
 pub fn handle(input: &[u8]) {
+    if let Some((left, op, right)) = split_chain(input) {
+        handle(left);
+
+        let succeeded = LAST_STATUS.load(Ordering::Relaxed) == 0;
+        if succeeded == (op == b"&&") {
+            handle(right);
+        }
+        return;
+    }
+
+    if input.contains(&b'|') {
+        run_pipeline(input);
+        return;
+    }
+
     let (cmd_name, cmd_args) = split_cmd(input);
 
     match find_cmd(cmd_name) {
         Some(cmd) => {
+            LAST_STATUS.store(0, Ordering::Relaxed);
+
             // Call the command function
             (cmd.function)(cmd_args);
         }
@@ -184,6 +270,8 @@ pub fn handle(input: &[u8]) {
                 return;
             }
 
+            LAST_STATUS.store(STATUS_NOT_FOUND, Ordering::Relaxed);
+
             // Echo back the input
             error!("Unknown command: ");
             printb!(cmd_name);
@@ -208,6 +296,187 @@ fn find_cmd(name: &[u8]) -> Option<&'static Command> {
     None
 }
 
+/// Splits `a && b` or `a || b` at the last operator, so that chains are evaluated from left to
+/// right. Returns None if there is no operator.
+fn split_chain(input: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let pos = input
+        .windows(2)
+        .rposition(|w| w == b"&&" || w == b"||")?;
+
+    Some((
+        input[..pos].trim_ascii(),
+        &input[pos..pos + 2],
+        input[pos + 2..].trim_ascii(),
+    ))
+}
+
+/// Starts an ELF binary in background. Fails the command if it could not be started.
+fn run_background(filename: &[u8], args: &[u8], priority: u8) {
+    if super::elf::run_elf(filename, args, priority) == 0 {
+        LAST_STATUS.store(1, Ordering::Relaxed);
+    }
+}
+
+/// Starts an ELF binary and blocks until it exits. A non-zero exit code is printed and becomes
+/// the status of the command.
+fn run_foreground(filename: &[u8], args: &[u8], priority: u8) {
+    let pid = super::elf::run_elf(filename, args, priority);
+    if pid == 0 {
+        LAST_STATUS.store(1, Ordering::Relaxed);
+        return;
+    }
+
+    if let Some(code) = wait_child(pid) {
+        LAST_STATUS.store(code, Ordering::Relaxed);
+    }
+}
+
+/// Runs `a | b | ...`. Every stage is started as a program with its standard output piped into the
+/// standard input of the next stage, except that the first one may be a built-in command, whose
+/// output is fed into the pipe by `run_builtin_stage`. Blocks until all of them exit; the exit code
+/// of the last one becomes the status of the command.
+fn run_pipeline(input: &[u8]) {
+    let stages: Vec<&[u8]> = input
+        .split(|&b| b == b'|')
+        .map(|s| s.trim_ascii())
+        .collect();
+
+    // Built-in commands do not read standard input, so they can only start a pipeline.
+    if let Some(stage) = stages.iter().skip(1).find(|stage| {
+        let (filename, _) = split_cmd(stage);
+        find_cmd(filename).is_some()
+    }) {
+        let (filename, _) = split_cmd(stage);
+        error!("Built-in commands can only start a pipeline: ");
+        printb!(filename);
+        println!();
+        LAST_STATUS.store(1, Ordering::Relaxed);
+        return;
+    }
+
+    if stages.iter().any(|stage| {
+        let (filename, _) = split_cmd(stage);
+        filename.is_empty() || filename.len() > 8
+    }) {
+        warn!("Usage: <command> [args] | <binary> [args]\n");
+        LAST_STATUS.store(1, Ordering::Relaxed);
+        return;
+    }
+
+    let shell = unsafe { scheduler::get_current_pid() };
+    let mut pids = Vec::new();
+
+    // Each stage inherits the standard input and output the shell has while starting it: the pipe
+    // from the previous stage, and a new pipe to the next one except for the last stage.
+    for (i, stage) in stages.iter().enumerate() {
+        let last = i + 1 == stages.len();
+
+        if !last && unsafe { scheduler::pipe_open(shell) }.is_none() {
+            error!("Could not create a pipe\n");
+            break;
+        }
+
+        let (filename, args) = split_cmd(stage);
+        let pid = match find_cmd(filename) {
+            Some(cmd) => run_builtin_stage(cmd, args),
+            None => super::elf::run_elf(filename, stage, DEFAULT_PRIORITY),
+        };
+        if pid == 0 {
+            break;
+        }
+        pids.push(pid);
+
+        if !last {
+            let (_, stdout) = scheduler::stdio();
+            unsafe { scheduler::set_stdio(shell, stdout, None) };
+        }
+    }
+
+    // Let go of the last pipe, so that a stage reading it sees EOF if the next one is missing.
+    unsafe { scheduler::set_stdio(shell, None, None) };
+
+    let mut status = 1;
+
+    for &pid in pids.iter() {
+        if let Some(code) = wait_child(pid) {
+            status = code;
+        }
+    }
+
+    if pids.len() < stages.len() {
+        status = 1;
+    }
+
+    LAST_STATUS.store(status, Ordering::Relaxed);
+}
+
+/// Runs a built-in command at the head of a pipeline with its output collected instead of printed,
+/// and starts a kernel process that writes the output to the pipe it inherits, so that the next
+/// stage reads it up to EOF like the output of a program. Returns the PID of that process, or 0 if
+/// it could not be started.
+fn run_builtin_stage(cmd: &Command, args: &[u8]) -> usize {
+    let shell = unsafe { scheduler::get_current_pid() };
+
+    vga::start_capture(shell);
+    (cmd.function)(args);
+    *PIPE_FEED.lock() = vga::end_capture();
+
+    let pid = unsafe {
+        scheduler::new_process(SpawnOptions {
+            name: *b"pipe_feed       ",
+            mode: Mode::Kernel,
+            entry: feed_pipe as *const () as u64,
+            stack_top: 0,
+            cr3: 0,
+            priority: DEFAULT_PRIORITY,
+            image_start: 0,
+            image_end: 0,
+        })
+    };
+
+    if pid == 0xff {
+        PIPE_FEED.lock().clear();
+        error!("Could not start the pipeline\n");
+        return 0;
+    }
+
+    pid
+}
+
+/// Entry of the process started by `run_builtin_stage`: writes the collected output to its
+/// standard output and exits, failing if the next stage stopped reading.
+extern "C" fn feed_pipe() -> ! {
+    let output = core::mem::take(&mut *PIPE_FEED.lock());
+    let code = match unsafe { scheduler::pipe_write(&output) } {
+        Ok(()) => 0,
+        Err(_) => 1,
+    };
+
+    unsafe {
+        scheduler::exit(scheduler::get_current_pid(), code);
+    }
+    loop {
+        unsafe {
+            core::arch::asm!("hlt");
+        }
+    }
+}
+
+/// Blocks until the child `pid` exits and returns its exit code, which is printed if non-zero.
+fn wait_child(pid: usize) -> Option<u64> {
+    let (_, code) = unsafe { scheduler::wait(Some(pid)) }?;
+
+    if code != 0 {
+        warn!("PID ");
+        printn!(pid);
+        warn!(" exited with code ");
+        printn!(code);
+        println!();
+    }
+
+    Some(code)
+}
+
 /// Splits the provided `input` in to tokens, where the delimitor is a single whitespace (space).
 pub fn split_cmd(input: &[u8]) -> (&[u8], &[u8]) {
     // Find the first space
@@ -313,7 +582,7 @@ fn cmd_beep(_args: &[u8]) {
     audio::beep::stop_beep();
 }
 
-/// Runs an ELF binary in background (won't make kernel shell Idle).
+/// Runs an ELF binary in background (the shell stays interactive).
 fn cmd_bg(args: &[u8]) {
     if args.is_empty() {
         warn!("usage: bg <binary name>\n");
@@ -328,7 +597,7 @@ fn cmd_bg(args: &[u8]) {
         return;
     }
 
-    super::elf::run_elf(filename_input, args, super::elf::RunMode::Background);
+    run_background(filename_input, args, DEFAULT_PRIORITY);
 }
 
 fn cmd_fg(args: &[u8]) {
@@ -345,7 +614,7 @@ fn cmd_fg(args: &[u8]) {
         return;
     }
 
-    super::elf::run_elf(filename_input, args, super::elf::RunMode::Foreground);
+    run_foreground(filename_input, args, DEFAULT_PRIORITY);
 }
 
 fn cmd_cd(args: &[u8]) {
@@ -680,11 +949,59 @@ fn cmd_echo(args: &[u8]) {
     println!();
 }
 
+/// Prints the physical memory reported by the memory map and how much of it the frame allocator
+/// has handed out, in KiB (`free -m` for MiB). The fixed kernel layout below 32 MiB counts as used.
+fn cmd_free(args: &[u8]) {
+    let (flag, _) = keyboard::split_cmd(args);
+
+    let unit = match flag {
+        b"" => 1024,
+        b"-m" => 1024 * 1024,
+        _ => {
+            warn!("usage: free [-m]\n");
+            return;
+        }
+    };
+
+    let stats = frames::stats();
+
+    print!("           total        used        free\n");
+    print!("Mem:");
+    print_right(stats.total / unit, 12);
+    print_right(stats.total.saturating_sub(stats.free) / unit, 12);
+    print_right(stats.free / unit, 12);
+    println!();
+}
+
 /// Filesystem check utility.
 fn cmd_fsck(_args: &[u8]) {
     run_check();
 }
 
+/// Starts the GDB stub on COM1 or COM2 (`gdb [com1|com2]`) and stops the system until gdb
+/// attaches and continues it.
+fn cmd_gdb(args: &[u8]) {
+    let (arg, _) = keyboard::split_cmd(args);
+
+    let Some(port) = gdb::parse_port(arg) else {
+        warn!("usage: gdb [com1|com2]\n");
+        return;
+    };
+
+    if gdb::is_active() {
+        error!("gdb is already attached\n");
+        return;
+    }
+
+    print!("Waiting for gdb on ", Color::White);
+    print!(if port == serial::COM1 { "COM1" } else { "COM2" }, Color::White);
+    println!("...");
+
+    if !gdb::start(port) {
+        error!("no serial port found\n");
+    }
+}
+
 /// Meta command to dump all non-hidden commands.
 fn cmd_help(_args: &[u8]) {
     println!("List of commands:");
@@ -721,20 +1038,33 @@ fn cmd_hlt(_args: &[u8]) {
 }
 
 fn cmd_kill(args: &[u8]) {
-    if args.is_empty() {
-        warn!("usage: kill <pid>\n");
-        return;
+    let (mut first, rest) = keyboard::split_cmd(args);
+    let mut sig = signal::SIGTERM;
+
+    if let Some(name) = first.strip_prefix(b"-") {
+        let Some(s) = signal::parse(name) else {
+            error!("unknown signal\n");
+            return;
+        };
+
+        sig = s;
+        (first, _) = keyboard::split_cmd(rest);
     }
 
-    let (first, _) = keyboard::split_cmd(args);
+    if first.is_empty() {
+        warn!("usage: kill [-SIGNAL] <pid>\n");
+        return;
+    }
 
     if let Some(pid) = parse_u64(first) {
-        print!("Killing PID ", Color::White);
-        printn!(pid);
-        println!();
-
-        unsafe {
-            crate::task::scheduler::kill(pid as usize);
+        if unsafe { scheduler::signal(pid as usize, sig) } {
+            print!("Signal ", Color::White);
+            printn!(sig);
+            print!(" sent to PID ", Color::White);
+            printn!(pid);
+            println!();
+        } else {
+            error!("no such process\n");
         }
     } else {
         error!("invalid PID lmao\n");
@@ -875,7 +1205,110 @@ fn cmd_mv(args: &[u8]) {
     }
 }
 
+/// Changes the priority of a running process (`nice <pid> <priority>`), or starts an ELF binary
+/// with the given priority (`nice -n <priority> bg|fg <binary>`). 0 is the highest priority.
+fn cmd_nice(args: &[u8]) {
+    let (first, rest) = keyboard::split_cmd(args);
+
+    if first == b"-n" {
+        let (prio_input, rest) = split_cmd(rest);
+        let (mode_input, bin_args) = split_cmd(rest);
+        let (filename_input, _) = keyboard::split_cmd(bin_args);
+
+        let priority = match parse_u64(prio_input) {
+            Some(p) if p < PRIORITY_LEVELS as u64 => p as u8,
+            _ => {
+                error!("invalid priority (0-7)\n");
+                return;
+            }
+        };
+
+        if filename_input.is_empty() || filename_input.len() > 8 {
+            warn!("usage: nice -n <priority> bg|fg <binary name>\n");
+            return;
+        }
+
+        match mode_input {
+            b"bg" => run_background(filename_input, bin_args, priority),
+            b"fg" => run_foreground(filename_input, bin_args, priority),
+            _ => warn!("usage: nice -n <priority> bg|fg <binary name>\n"),
+        }
+        return;
+    }
+
+    let (prio_input, _) = keyboard::split_cmd(rest);
+
+    let (Some(pid), Some(priority)) = (parse_u64(first), parse_u64(prio_input)) else {
+        warn!("usage: nice <pid> <priority>\n");
+        return;
+    };
+
+    if priority >= PRIORITY_LEVELS as u64 {
+        error!("invalid priority (0-7)\n");
+        return;
+    }
+
+    if unsafe { scheduler::set_priority(pid as usize, priority as u8) } {
+        print!("Priority of PID ", Color::White);
+        printn!(pid);
+        print!(" set to ", Color::White);
+        printn!(priority);
+        println!();
+    } else {
+        error!("no such process\n");
+    }
+}
+
 /// Prints the contents of a file.
+/// Starts a kernel process that recurses into the guard page below its kernel stack, to check that
+/// the overflow is caught and reported. The kernel panics, so this never returns.
+fn cmd_overflow(_args: &[u8]) {
+    warn!("Overflowing a kernel stack, the kernel will panic\n");
+
+    let pid = unsafe {
+        scheduler::new_process(SpawnOptions {
+            name: *b"overflow        ",
+            mode: Mode::Kernel,
+            entry: overflow_stack as *const () as u64,
+            stack_top: 0,
+            cr3: 0,
+            priority: DEFAULT_PRIORITY,
+            image_start: 0,
+            image_end: 0,
+        })
+    };
+
+    if pid == 0xff {
+        error!("Could not start the process\n");
+        LAST_STATUS.store(1, Ordering::Relaxed);
+        return;
+    }
+
+    wait_child(pid);
+}
+
+extern "C" fn overflow_stack() -> ! {
+    recurse(0);
+
+    loop {
+        unsafe {
+            core::arch::asm!("hlt");
+        }
+    }
+}
+
+/// Takes a little over 512 bytes of stack per call, less than a guard page, so the first write
+/// below the stack lands in the guard.
+#[inline(never)]
+fn recurse(depth: u64) -> u64 {
+    if depth == u64::MAX {
+        return 0;
+    }
+
+    let frame = core::hint::black_box([depth; 64]);
+    recurse(depth + 1) + frame[63]
+}
+
 fn cmd_read(args: &[u8]) {
     if args.is_empty() {
         warn!("Usage: read <filename>\n");
@@ -1046,7 +1479,86 @@ fn cmd_run(args: &[u8]) {
         return;
     }
 
-    super::elf::run_elf(filename_input, args, super::elf::RunMode::Foreground);
+    run_foreground(filename_input, args, DEFAULT_PRIORITY);
+}
+
+/// Logs the syscalls of a process (`strace [-o] <pid>`), or starts an ELF binary traced from its
+/// first syscall (`strace [-o] bg|fg <binary>`). `-o` appends the log to STRACE.TXT instead of
+/// printing it; `strace off <pid>` stops tracing.
+fn cmd_strace(args: &[u8]) {
+    let (mut first, mut rest) = keyboard::split_cmd(args);
+    let mut trace = Trace::Console;
+
+    if first == b"off" {
+        let (pid_input, _) = keyboard::split_cmd(rest);
+
+        let Some(pid) = parse_u64(pid_input) else {
+            warn!("usage: strace off <pid>\n");
+            return;
+        };
+
+        if !unsafe { scheduler::set_trace(pid as usize, None) } {
+            error!("no such process\n");
+        }
+        return;
+    }
+
+    if first == b"-o" {
+        trace = Trace::File;
+        (first, rest) = split_cmd(rest);
+    }
+
+    if first.is_empty() {
+        warn!("usage: strace [-o] <pid> | strace [-o] bg|fg <binary name>\n");
+        return;
+    }
+
+    if trace == Trace::File && !strace::open_file() {
+        error!("could not create STRACE.TXT\n");
+        return;
+    }
+
+    if first == b"bg" || first == b"fg" {
+        let (filename_input, _) = keyboard::split_cmd(rest);
+
+        if filename_input.is_empty() || filename_input.len() > 8 {
+            warn!("usage: strace [-o] bg|fg <binary name>\n");
+            return;
+        }
+
+        // The child inherits tracing from the shell while it is started, so not even its first
+        // syscall is missed.
+        let shell = unsafe { scheduler::get_current_pid() };
+
+        unsafe { scheduler::set_trace(shell, Some(trace)) };
+        let pid = super::elf::run_elf(filename_input, rest, DEFAULT_PRIORITY);
+        unsafe { scheduler::set_trace(shell, None) };
+
+        if pid == 0 {
+            LAST_STATUS.store(1, Ordering::Relaxed);
+            return;
+        }
+
+        if first == b"fg" {
+            if let Some(code) = wait_child(pid) {
+                LAST_STATUS.store(code, Ordering::Relaxed);
+            }
+        }
+        return;
+    }
+
+    let Some(pid) = parse_u64(first) else {
+        error!("invalid PID\n");
+        return;
+    };
+
+    if unsafe { scheduler::set_trace(pid as usize, Some(trace)) } {
+        print!("Tracing PID ", Color::White);
+        printn!(pid);
+        println!();
+    } else {
+        error!("no such process\n");
+    }
 }
 
 /// Prints current time and date in UTC as read from RTC in CMOS.
@@ -1093,6 +1605,122 @@ fn cmd_time(_args: &[u8]) {
     println!();
 }
 
+/// Redraws the task list every second, busiest task first, until a key is pressed.
+fn cmd_top(_args: &[u8]) {
+    let mut prev: Vec<scheduler::TaskStats> = Vec::new();
+    let mut prev_tick = 0;
+
+    loop {
+        let now = time::acpi::get_tick_count();
+        let interval = (now - prev_tick).max(1);
+        let mut tasks = scheduler::task_stats();
+
+        // CPU ticks used since the last redraw.  Tasks started since then
+        // count from zero, and on the first redraw everything counts from boot.
+        let used = |t: &scheduler::TaskStats| {
+            let before = prev
+                .iter()
+                .find(|p| p.pid == t.pid)
+                .map_or(0, |p| p.cpu_ticks);
+            t.cpu_ticks.saturating_sub(before)
+        };
+
+        tasks.sort_by_key(|t| core::cmp::Reverse(used(t)));
+
+        clear_screen!();
+        print!("top - ", Color::White);
+        printn!(tasks.len());
+        print!(" tasks, up ", Color::White);
+        printn!(now / TICKS_PER_SECOND);
+        print!(" s, press any key to quit\n\n", Color::White);
+        print!("  PID NAME              %CPU      TIME   BLOCKED  SWITCHES STATUS\n");
+
+        for task in tasks.iter() {
+            print_right(task.pid as u64, 5);
+            print!(" ");
+
+            let len = task.name.iter().position(|&b| b == 0).unwrap_or(16);
+            printb!(&task.name[..len]);
+            for _ in len..17 {
+                print!(" ");
+            }
+
+            print_right(used(task) * 100 / interval, 5);
+            print_seconds(task.cpu_ticks, 10);
+            print_seconds(task.blocked_ticks, 10);
+            print_right(task.switches, 10);
+            print!(" ");
+            print!(task.status.as_str());
+            println!();
+        }
+
+        prev = tasks;
+        prev_tick = now;
+
+        // Sleep in 100 ms steps so a key press is noticed quickly.
+        while time::acpi::get_tick_count() < now + TICKS_PER_SECOND {
+            if keyboard::try_load_scancode().is_some_and(|key| key & 0x80 == 0) {
+                return;
+            }
+
+            unsafe {
+                scheduler::sleep_current(time::acpi::get_tick_count() + TICKS_PER_SECOND / 10);
+                core::arch::asm!("hlt");
+            }
+        }
+    }
+}
+
+/// Prints `n` right-aligned in a field of `width` characters.
+fn print_right(n: u64, width: usize) {
+    let mut digits = 1;
+    let mut rest = n / 10;
+    while rest > 0 {
+        digits += 1;
+        rest /= 10;
+    }
+
+    for _ in digits..width {
+        print!(" ");
+    }
+    printn!(n);
+}
+
+/// Prints PIT ticks as seconds with two decimals, right-aligned in `width` characters.
+fn print_seconds(ticks: u64, width: usize) {
+    let centis = ticks * 100 / TICKS_PER_SECOND;
+
+    print_right(centis / 100, width - 3);
+    print!(".");
+    if centis % 100 < 10 {
+        print!("0");
+    }
+    printn!(centis % 100);
+}
+
+fn cmd_svc(_args: &[u8]) {
+    let services = scheduler::services();
+
+    if services.is_empty() {
+        println!("No services registered.");
+        return;
+    }
+
+    print!("NAME              PORT   PID\n");
+
+    for svc in services.iter() {
+        let len = svc.name.iter().position(|&b| b == 0).unwrap_or(16);
+        printb!(&svc.name[..len]);
+        for _ in len..17 {
+            print!(" ");
+        }
+
+        print_right(svc.port as u64, 5);
+        print_right(svc.pid as u64, 6);
+        println!();
+    }
+}
+
 fn cmd_ts(_args: &[u8]) {
     unsafe {
         crate::task::scheduler::list_processes();
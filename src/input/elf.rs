@@ -1,161 +1,182 @@
-use core::ptr::{copy_nonoverlapping, write_bytes};
+use alloc::vec::Vec;
+use core::ops::Range;
 
 use crate::fs::block::BlockDevice;
 use crate::input::keyboard::keyboard_loop;
+use crate::mem::{
+    pages,
+    vm::{self, Segment},
+};
+use crate::task::process::{Mode, SpawnOptions, GUARD_SIZE, MAX_THREADS, THREAD_STACK_SIZE};
 
 #[repr(C)]
 #[derive(Debug)]
-struct Elf64Ehdr {
-    e_ident: [u8; 16],
-    e_type: u16,
-    e_machine: u16,
-    e_version: u32,
-    e_entry: u64,
-    e_phoff: u64,
-    e_shoff: u64,
-    e_flags: u32,
-    e_ehsize: u16,
-    e_phentsize: u16,
-    e_phnum: u16,
-    e_shentsize: u16,
-    e_shnum: u16,
-    e_shstrndx: u16,
+pub(crate) struct Elf64Ehdr {
+    pub(crate) e_ident: [u8; 16],
+    pub(crate) e_type: u16,
+    pub(crate) e_machine: u16,
+    pub(crate) e_version: u32,
+    pub(crate) e_entry: u64,
+    pub(crate) e_phoff: u64,
+    pub(crate) e_shoff: u64,
+    pub(crate) e_flags: u32,
+    pub(crate) e_ehsize: u16,
+    pub(crate) e_phentsize: u16,
+    pub(crate) e_phnum: u16,
+    pub(crate) e_shentsize: u16,
+    pub(crate) e_shnum: u16,
+    pub(crate) e_shstrndx: u16,
 }
 
 #[repr(C)]
 #[derive(Debug)]
-struct Elf64Phdr {
-    p_type: u32,
-    p_flags: u32,
-    p_offset: u64,
-    p_vaddr: u64,
-    p_paddr: u64,
-    p_filesz: u64,
-    p_memsz: u64,
-    p_align: u64,
+pub(crate) struct Elf64Phdr {
+    pub(crate) p_type: u32,
+    pub(crate) p_flags: u32,
+    pub(crate) p_offset: u64,
+    pub(crate) p_vaddr: u64,
+    pub(crate) p_paddr: u64,
+    pub(crate) p_filesz: u64,
+    pub(crate) p_memsz: u64,
+    pub(crate) p_align: u64,
 }
 
-const PT_LOAD: u32 = 1;
+pub(crate) const PT_LOAD: u32 = 1;
 
-/// Lowest virtual address a userland ELF segment may occupy.
-const USERLAND_START: u64 = 0x600_000;
-/// Highest virtual address (exclusive) a userland ELF segment may occupy.
-const USERLAND_END: u64 = 0xA00_000;
+/// Segment flag: writable.
+const PF_W: u32 = 2;
 
-/// `phys_offset` is added to each segment's `p_vaddr` before writing so that
-/// the ELF data lands in the correct physical frame.  Pass 0 to write directly
-/// to the virtual address (used for inline kernel ELF loads via syscall 0x2A).
-pub unsafe fn load_elf64(elf_addr: usize, phys_offset: u64) -> usize {
-    let ehdr = &*(elf_addr as *const Elf64Ehdr);
+/// Top of the user stack area, a guard page below the end of the high user
+/// window.  The MAX_THREADS thread stack slots sit below it, the main
+/// thread's first.
+pub const USER_STACK_TOP: u64 = vm::HIGH_END - GUARD_SIZE;
+/// Bottom of the user stack area.
+pub const USER_STACK_BASE: u64 = USER_STACK_TOP - MAX_THREADS * THREAD_STACK_SIZE;
 
-    rprint!("First 16 bytes (elf_addr + 0x18): ");
-    for i in 0..16 {
-        rprintn!(*((elf_addr + 0x18) as *const u8).add(i));
-        rprint!(" ");
-    }
-    rprint!("\n");
+/// The stack of thread slot `slot`, 0 being the main thread's: the slot
+/// without the guard page at its bottom.
+pub fn thread_stack(slot: u64) -> Range<u64> {
+    let top = USER_STACK_TOP - slot * THREAD_STACK_SIZE;
+
+    top - THREAD_STACK_SIZE + GUARD_SIZE..top
+}
+
+/// True if the `len` bytes at `virt` fit in one of the user windows, below
+/// the stack area.
+fn in_image_window(virt: u64, len: u64) -> bool {
+    let Some(end) = virt.checked_add(len) else {
+        return false;
+    };
 
-    // Validate ELF magic
-    assert_eq!(&ehdr.e_ident[0..4], b"\x7FELF");
-    assert_eq!(ehdr.e_ident[4], 2); // ELF64
-
-    let phdrs = (elf_addr + ehdr.e_phoff as usize) as *const Elf64Phdr;
-
-    for i in 0..ehdr.e_phnum {
-        let ph = &*phdrs.add(i as usize);
-
-        if ph.p_type == PT_LOAD {
-            // Reject any segment that would land outside the user-accessible
-            // region.  This prevents a malformed ELF from scribbling over
-            // kernel memory.
-            let seg_end = ph.p_vaddr.saturating_add(ph.p_memsz);
-            if ph.p_vaddr < USERLAND_START || seg_end > USERLAND_END {
-                rprint!("ELF segment ");
-                rprintn!(i);
-                rprint!(" vaddr=");
-                rprintn!(ph.p_vaddr);
-                rprint!(" is outside userland — skipping\n");
-                continue;
-            }
-
-            let src = (elf_addr + ph.p_offset as usize) as *const u8;
-            let dst = ph.p_vaddr.wrapping_add(phys_offset) as *mut u8;
-
-            rprint!("Loading segment ");
-            rprintn!(i);
-            rprint!(" to ");
-            rprintn!(ph.p_vaddr);
-            rprint!(", filesz = ");
-            rprintn!(ph.p_filesz);
-            rprint!(", memsz = ");
-            rprintn!(ph.p_memsz);
-            rprint!("\n");
-
-            copy_nonoverlapping(src, dst, ph.p_filesz as usize);
-            if ph.p_memsz > ph.p_filesz {
-                write_bytes(
-                    dst.add(ph.p_filesz as usize),
-                    0,
-                    (ph.p_memsz - ph.p_filesz) as usize,
-                );
-            }
+    (vm::LOW_START <= virt && end <= vm::LOW_END)
+        || (vm::HIGH_START <= virt && end <= USER_STACK_BASE)
+}
+
+/// PT_LOAD segments of a validated ELF image, as address space areas are
+/// made of.
+fn load_segments(image: &[u8]) -> Vec<Segment> {
+    let ehdr = unsafe { (image.as_ptr() as *const Elf64Ehdr).read_unaligned() };
+    let mut segments = Vec::new();
+
+    for i in 0..ehdr.e_phnum as usize {
+        let ph = unsafe {
+            (image
+                .as_ptr()
+                .add(ehdr.e_phoff as usize + i * size_of::<Elf64Phdr>())
+                as *const Elf64Phdr)
+                .read_unaligned()
+        };
+
+        if ph.p_type != PT_LOAD || ph.p_memsz == 0 {
+            continue;
         }
+
+        rprint!("Segment ");
+        rprintn!(i);
+        rprint!(" at ");
+        rprintn!(ph.p_vaddr);
+        rprint!(", filesz = ");
+        rprintn!(ph.p_filesz);
+        rprint!(", memsz = ");
+        rprintn!(ph.p_memsz);
+        rprint!("\n");
+
+        segments.push(Segment {
+            virt: ph.p_vaddr,
+            offset: ph.p_offset,
+            file_len: ph.p_filesz,
+            mem_len: ph.p_memsz,
+            writable: ph.p_flags & PF_W != 0,
+        });
     }
 
-    ehdr.e_entry as usize
+    segments
 }
 
-#[derive(PartialEq, Clone, Copy)]
-pub enum RunMode {
-    Foreground,
-    Background,
+use crate::fs::{
+    fat12::{block::Floppy, fs::Filesystem},
+    iso9660::Iso9660,
+    vfs,
+};
+
+/// Maximum number of argv (and, separately, envp) strings a process can be
+/// started with.
+pub const MAX_ARGS: usize = 32;
+
+pub(crate) const EM_X86_64: u16 = 0x3E;
+
+/// Why `spawn` could not start a process.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpawnError {
+    /// No file at the given path.
+    NotFound,
+    /// The path names a directory.
+    IsDirectory,
+    /// The filesystem or its device could not be read.
+    Filesystem,
+    /// The file is not a loadable x86-64 ELF64 executable.
+    InvalidExecutable,
+    /// More than MAX_ARGS argv or envp strings.
+    TooManyArgs,
+    /// No kernel heap left for the image, or no kernel stack for the process.
+    OutOfMemory,
 }
 
-static mut STACK_NO: usize = 0;
-
-use crate::fs::fat12::{block::Floppy, fs::{fat83, Filesystem}};
+impl SpawnError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpawnError::NotFound => "no such file found",
+            SpawnError::IsDirectory => "is a directory",
+            SpawnError::Filesystem => "filesystem error",
+            SpawnError::InvalidExecutable => "not an x86-64 ELF executable",
+            SpawnError::TooManyArgs => "too many arguments",
+            SpawnError::OutOfMemory => "out of memory",
+        }
+    }
+}
 
-/// Write the x86-64 SysV initial-stack layout (argc / argv) into user memory
-/// just below `stack_top` and return the new RSP that points at `argc`.
+/// Write the x86-64 SysV initial-stack layout (argc / argv / envp) into user
+/// memory just below `stack_top` and return the new RSP that points at `argc`.
+///
+/// argv[0] is conventionally the program name.
 ///
-/// `args` is the raw command line (space-delimited tokens, may be NUL-padded).
-/// argv[0] is the first token (conventionally the program name).
-unsafe fn push_user_args(stack_top: u64, args: &[u8]) -> u64 {
-    const MAX_ARGS: usize = 8;
-    let mut ptrs = [0u64; MAX_ARGS];
-    let mut argc = 0usize;
+/// The stack belongs to the address space `cr3`, which is not live yet; its
+/// pages are filled through `vm::copy_to`.  None if they cannot be.
+unsafe fn push_user_args(stack_top: u64, argv: &[&[u8]], envp: &[&[u8]], cr3: u64) -> Option<u64> {
+    let mut ptrs = [0u64; 2 * MAX_ARGS];
+    let mut sp = stack_top;
 
-    // Trim trailing NUL padding.
-    let trimmed_len = args.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
-    let args = &args[..trimmed_len];
+    let put = |addr: u64, data: &[u8]| vm::copy_to(cr3, addr, data).then_some(());
 
-    let mut sp = stack_top;
+    // Write each string NUL-terminated, growing down from stack_top.
+    for (i, s) in argv.iter().chain(envp.iter()).enumerate() {
+        sp -= 1;
+        put(sp, &[0])?; // NUL terminator
 
-    // Write each space-delimited token as a NUL-terminated string, pushing
-    // bytes right-to-left so the string data grows down from stack_top.
-    let mut i = 0;
-    while i <= args.len() && argc < MAX_ARGS {
-        let mut j = i;
-        while j < args.len() && args[j] != b' ' {
-            j += 1;
-        }
-        if j > i {
-            sp -= 1;
-            *(sp as *mut u8) = 0; // NUL terminator
-            let mut k = j;
-            while k > i {
-                k -= 1;
-                sp -= 1;
-                *(sp as *mut u8) = args[k];
-            }
-            ptrs[argc] = sp;
-            argc += 1;
-        }
-        i = j + 1;
-    }
+        sp -= s.len() as u64;
+        put(sp, s)?;
 
-    if argc == 0 {
-        return stack_top;
+        ptrs[i] = sp;
     }
 
     sp &= !7u64; // align to 8 bytes before writing pointers
@@ -163,36 +184,263 @@ unsafe fn push_user_args(stack_top: u64, args: &[u8]) -> u64 {
     // _crt0: mov rdi,[rsp]; lea rsi,[rsp+8]; call main
     // `call` pushes 8 bytes, so main sees rsp = argc_addr - 8.
     // For main's rsp to be 16-byte aligned: argc_addr % 16 == 8.
-    // We will push: NULL(1) + argc pointers + argc_word(1) = (argc+2) words.
-    // argc_addr = sp - (argc+2)*8.  For argc_addr % 16 == 8:
-    //   sp % 16 must equal (8*(argc+3)) % 16.
+    // We will push: argc_word + argv pointers + NULL + envp pointers + NULL.
     // Insert any needed gap HERE (in string-data region) so argc and argv[0]
     // are always contiguous — the gap must never go between them.
-    let target = (8u64 * (argc as u64 + 3)) % 16;
-    if sp % 16 != target {
+    let words = (argv.len() + envp.len() + 3) as u64;
+    if (sp - words * 8) % 16 != 8 {
         sp -= 8; // alignment gap before the pointer array
     }
 
-    // argv[argc] = NULL terminator.
-    sp -= 8;
-    *(sp as *mut u64) = 0;
-
-    // argv pointers, highest index first so argv[0] ends up at [rsp+8].
-    let mut k = argc;
-    while k > 0 {
-        k -= 1;
+    // Pointer arrays, highest address first: envp NULL, envp, argv NULL, argv.
+    let (argv_ptrs, envp_ptrs) = ptrs[..argv.len() + envp.len()].split_at(argv.len());
+    for list in [envp_ptrs, argv_ptrs] {
         sp -= 8;
-        *(sp as *mut u64) = ptrs[k];
+        put(sp, &0u64.to_le_bytes())?;
+
+        for &ptr in list.iter().rev() {
+            sp -= 8;
+            put(sp, &ptr.to_le_bytes())?;
+        }
     }
 
     // argc — immediately below argv[0], no gap.
     sp -= 8;
-    *(sp as *mut u64) = argc as u64;
+    put(sp, &(argv.len() as u64).to_le_bytes())?;
+
+    Some(sp) // caller passes this as stack_top to new_process
+}
+
+/// Allocate a zeroed staging buffer on the kernel heap without aborting when
+/// the heap is exhausted.
+fn alloc_image(len: usize) -> Result<Vec<u8>, SpawnError> {
+    let mut image = Vec::new();
+    image
+        .try_reserve_exact(len)
+        .map_err(|_| SpawnError::OutOfMemory)?;
+    image.resize(len, 0);
+    Ok(image)
+}
+
+/// Read the whole file at `path` into a kernel heap buffer.  Absolute paths
+/// are resolved through the VFS mount table (FAT12 or ISO9660), relative ones
+/// against the current FAT12 directory.
+fn read_image(path: &[u8]) -> Result<Vec<u8>, SpawnError> {
+    if let Some(rel) = vfs::try_iso9660_absolute(path) {
+        let iso = Iso9660::probe().ok_or(SpawnError::Filesystem)?;
+        let entry = iso.resolve(rel).ok_or(SpawnError::NotFound)?;
+        if entry.is_dir {
+            return Err(SpawnError::IsDirectory);
+        }
+
+        let mut image = alloc_image(entry.size as usize)?;
+        iso.read_file(&entry, &mut image);
+        return Ok(image);
+    }
+
+    let (rel, base) = match vfs::try_fat12_absolute(path) {
+        Some(rel) => (rel, 0),
+        None => (
+            path,
+            crate::init::config::SYSTEM_CONFIG
+                .try_lock()
+                .map_or(0, |c| c.get_path_cluster()),
+        ),
+    };
+
+    let floppy = Floppy::init();
+    let fs = Filesystem::new(&floppy).map_err(|_| SpawnError::Filesystem)?;
+
+    let entry = fs.resolve_path_from(base, rel).ok_or(SpawnError::NotFound)?;
+    if entry.attr & 0x10 != 0 {
+        return Err(SpawnError::IsDirectory);
+    }
+    if entry.start_cluster == 0 {
+        // Empty file.
+        return Err(SpawnError::InvalidExecutable);
+    }
+
+    let size = entry.file_size as usize;
+
+    rprint!("Size: ");
+    rprintn!(size);
+    rprint!("\n");
+
+    // Stage the raw file in a kernel heap buffer, whole sectors at a time.
+    let mut image = alloc_image(size.div_ceil(512) * 512)?;
+    let mut cluster = entry.start_cluster;
+
+    for chunk in image.as_chunks_mut::<512>().0 {
+        let lba = fs.cluster_to_lba(cluster);
+        let mut sector = [0u8; 512];
 
-    sp // caller passes this as stack_top to new_process
+        fs.device.read_sector(lba, &mut sector);
+
+        rprint!("Loading ELF image to memory segment\n");
+        chunk.copy_from_slice(&sector);
+
+        cluster = fs.read_fat12_entry(cluster);
+
+        if cluster >= 0xFF8 || cluster == 0 {
+            break;
+        }
+    }
+
+    image.truncate(size);
+    Ok(image)
+}
+
+/// Check that `image` is an x86-64 ELF64 executable whose program headers and
+/// PT_LOAD segments lie within the image, so pages are never filled from past
+/// its end, and whose segments load into the user windows below the stacks,
+/// never over kernel memory.
+fn validate_elf64(image: &[u8]) -> bool {
+    if image.len() < size_of::<Elf64Ehdr>() {
+        return false;
+    }
+
+    let ehdr = unsafe { (image.as_ptr() as *const Elf64Ehdr).read_unaligned() };
+
+    if &ehdr.e_ident[0..4] != b"\x7FELF"
+        || ehdr.e_ident[4] != 2 // ELF64
+        || ehdr.e_ident[5] != 1 // little endian
+        || ehdr.e_machine != EM_X86_64
+        || ehdr.e_phentsize as usize != size_of::<Elf64Phdr>()
+        || !in_image_window(ehdr.e_entry, 1)
+    {
+        return false;
+    }
+
+    let phoff = ehdr.e_phoff as usize;
+    let Some(phdrs_end) = (ehdr.e_phnum as usize)
+        .checked_mul(size_of::<Elf64Phdr>())
+        .and_then(|n| n.checked_add(phoff))
+    else {
+        return false;
+    };
+    if phdrs_end > image.len() {
+        return false;
+    }
+
+    (0..ehdr.e_phnum as usize).all(|i| {
+        let ph = unsafe {
+            (image.as_ptr().add(phoff + i * size_of::<Elf64Phdr>()) as *const Elf64Phdr)
+                .read_unaligned()
+        };
+
+        ph.p_type != PT_LOAD
+            || ph.p_memsz == 0
+            || (ph.p_filesz <= ph.p_memsz
+                && in_image_window(ph.p_vaddr, ph.p_memsz)
+                && ph
+                    .p_offset
+                    .checked_add(ph.p_filesz)
+                    .is_some_and(|end| end <= image.len() as u64))
+    })
 }
 
-pub fn run_elf(filename_input: &[u8], args: &[u8], mode: RunMode) -> usize {
+/// Page-aligned range covering the loaded `segments`.
+fn image_extent(segments: &[Segment]) -> (u64, u64) {
+    let start = segments.iter().map(|s| s.virt).min().unwrap_or(0);
+    let end = segments
+        .iter()
+        .map(|s| s.virt + s.mem_len)
+        .max()
+        .unwrap_or(0);
+
+    if start >= end {
+        return (0, 0);
+    }
+
+    (start & !0xFFF, end.div_ceil(0x1000) * 0x1000)
+}
+
+/// Load the ELF executable at `path` into a fresh address space and start it
+/// as a child of the running process.  Returns the PID of the new process.
+pub fn spawn(
+    path: &[u8],
+    argv: &[&[u8]],
+    envp: &[&[u8]],
+    priority: u8,
+) -> Result<usize, SpawnError> {
+    if argv.len() > MAX_ARGS || envp.len() > MAX_ARGS {
+        return Err(SpawnError::TooManyArgs);
+    }
+
+    let image = read_image(path)?;
+
+    if !validate_elf64(&image) {
+        return Err(SpawnError::InvalidExecutable);
+    }
+
+    let segments = load_segments(&image);
+    let entry_addr = unsafe { (image.as_ptr() as *const Elf64Ehdr).read_unaligned() }.e_entry;
+    let (image_start, image_end) = image_extent(&segments);
+
+    rprint!("ELF entry point: ");
+    rprintn!(entry_addr);
+    rprint!("\n");
+
+    unsafe {
+        // Each process gets its own page tables.  Nothing is mapped yet: the
+        // segments and the stack are filled in page by page as the process
+        // touches them, from the image kept by its address space.
+        let cr3 = pages::create_user_page_table();
+        let stacks: [Range<u64>; MAX_THREADS as usize] =
+            core::array::from_fn(|slot| thread_stack(slot as u64));
+
+        if !vm::create(cr3, image, &segments, &stacks) {
+            pages::free_user_page_table(cr3);
+            return Err(SpawnError::OutOfMemory);
+        }
+
+        // Build the SysV argv frame just below the stack top so that
+        // _crt0 can read argc from [rsp] and &argv[0] from [rsp+8].
+        let Some(user_rsp) = push_user_args(USER_STACK_TOP, argv, envp, cr3) else {
+            vm::release(cr3);
+            pages::free_user_page_table(cr3);
+            return Err(SpawnError::OutOfMemory);
+        };
+
+        // Name the process after the last path component.
+        let file_name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+        let mut name: [u8; 16] = [b' '; 16];
+
+        for (dst, src) in name.iter_mut().zip(file_name) {
+            *dst = src.to_ascii_uppercase();
+        }
+
+        // Create a new process to be run
+        let pid = crate::task::scheduler::new_process(SpawnOptions {
+            name,
+            mode: Mode::User,
+            entry: entry_addr,
+            stack_top: user_rsp,
+            cr3,
+            priority,
+            image_start,
+            image_end,
+        });
+
+        if pid == 0xff || pid == 0x00 {
+            vm::release(cr3);
+            pages::free_user_page_table(cr3);
+            rprint!("Error starting new process...\n");
+            return Err(SpawnError::OutOfMemory);
+        }
+
+        Ok(pid)
+    }
+}
+
+/// Load an ELF binary from the current directory and start it as a child of
+/// the running process.  Returns its PID, or 0 on failure.  The process runs
+/// in background; callers wanting foreground semantics wait for it with
+/// `scheduler::wait`.
+///
+/// `args` is the raw command line (space-delimited tokens, may be NUL-padded);
+/// its first token becomes argv[0].
+pub fn run_elf(filename_input: &[u8], args: &[u8], priority: u8) -> usize {
     if filename_input.is_empty() || filename_input.len() > 12 {
         return 0;
     }
@@ -207,160 +455,17 @@ pub fn run_elf(filename_input: &[u8], args: &[u8], mode: RunMode) -> usize {
         name_buf[n..n + 4].copy_from_slice(b".elf");
         &name_buf[..n + 4]
     };
-    let name83 = fat83(full_name);
 
-    let floppy = Floppy::init();
-    match Filesystem::new(&floppy) {
-        Ok(fs) => {
-            unsafe {
-                let mut offset = 0;
-
-                let path_cluster = crate::init::config::SYSTEM_CONFIG
-                    .try_lock()
-                    .map_or(0, |c| c.get_path_cluster());
-
-                let entry = match fs.find_entry(path_cluster, &name83) {
-                    Some(e) if e.attr & 0x10 == 0 => e,
-                    _ => {
-                        error!("no such file found");
-                        error!();
-                        return 0;
-                    }
-                };
-                let cluster_start = entry.start_cluster;
-                let mut cluster = cluster_start;
-                let size = entry.file_size;
-
-                let mut filename = [b' '; 12];
-                filename[0..8].copy_from_slice(&entry.name);
-                filename[8] = b'.';
-                filename[9..12].copy_from_slice(&entry.ext);
-
-                if cluster == 0 {
-                    error!("no such file found");
-                    error!();
-                    return 0;
-                }
-
-                rprint!("Size: ");
-                rprintn!(size);
-                rprint!("\n");
-
-                let addrs = [
-                    0x640_000, 0x680_000, 0x6c0_000, 0x700_000, 0x720_000, 0x740_000, 0x760_000,
-                    0x780_000, 0x7A0_000, 0x7C0_000,
-                ];
-                let load_addr: u64 = addrs[STACK_NO % 10];
-
-                while size - offset > 0 {
-                    let lba = fs.cluster_to_lba(cluster);
-                    let mut sector = [0u8; 512];
-
-                    fs.device.read_sector(lba, &mut sector);
-
-                    let dst = load_addr as *mut u8;
-
-                    rprint!("Loading ELF image to memory segment\n");
-                    for i in 0..512 {
-                        if let Some(byte) = sector.get(i) {
-                            *dst.add(i + offset as usize) = *byte;
-                        }
-                    }
-
-                    cluster = fs.read_fat12_entry(cluster);
-
-                    if cluster >= 0xFF8 || cluster == 0 {
-                        break;
-                    }
-
-                    offset += 512;
-                }
-
-                let entry_ptr = (load_addr + 0x18) as *const u8;
-
-                rprint!("First 16 bytes (load_addr + 0x18): ");
-                for i in 0..16 {
-                    rprintn!(*entry_ptr.add(i));
-                    rprint!(" ");
-                }
-                rprint!("\n");
-
-                let slot = STACK_NO % 10;
-
-                // Each slot gets its own 2 MiB physical frame at 16 MiB + slot*2 MiB.
-                // phys_offset remaps virtual 0x600_000 writes into that private frame.
-                let phys_frame = crate::mem::pages::user_frame_phys(slot);
-                let phys_offset = phys_frame.wrapping_sub(USERLAND_START);
-
-                // Parse and copy ELF segments into the slot's private physical frame.
-                let entry_addr = load_elf64(load_addr as usize, phys_offset);
-
-                rprint!("ELF entry point: ");
-                rprintn!(entry_addr);
-                rprint!("\n");
-
-                // Build a per-process page table that maps vaddr 0x600_000 to
-                // the slot's private physical frame.
-                let cr3 = crate::mem::pages::create_user_page_table(slot);
-
-                // Stacks live at the top of the user-accessible region
-                // (0x400000–0x9FFFFF, user-flag set in boot.asm).  Each slot
-                // gets 256 KB of stack space, starting well above where code
-                // typically loads (0x600000+), so normal stack growth never
-                // collides with ELF segments.
-                let stacks = [
-                    0x8F0_000u64,
-                    0x8D0_000,
-                    0x8B0_000,
-                    0x890_000,
-                    0x870_000,
-                    0x850_000,
-                    0x830_000,
-                    0x810_000,
-                    0x7F0_000,
-                    0x7D0_000,
-                ];
-                let stack_top = stacks[slot];
-                STACK_NO += 1;
-
-                // Build the SysV argv frame just below stack_top so that
-                // _crt0 can read argc from [rsp] and &argv[0] from [rsp+8].
-                let user_rsp = push_user_args(stack_top, args);
-
-                let mut name: [u8; 16] = [b' '; 16];
-
-                if let Some(slice) = name.get_mut(0..12) {
-                    slice.copy_from_slice(&filename[0..12]);
-                }
-
-                // Create a new process to be run
-                let pid = crate::task::scheduler::new_process(
-                    name,
-                    crate::task::process::Mode::User,
-                    entry_addr as u64,
-                    user_rsp,
-                    cr3,
-                );
-
-                if pid == 0xff || pid == 0x00 {
-                    rprint!("Error starting new process...\n");
-                    error!("Error starting new process...\n\n");
-                    return 0;
-                }
-
-                match mode {
-                    RunMode::Background => {}
-                    RunMode::Foreground => {
-                        // Make the kernel shell idle
-                        crate::task::scheduler::idle(0xff);
-                    }
-                }
-
-                pid
-            }
-        }
+    let argv: Vec<&[u8]> = args
+        .split(|&b| b == b' ' || b == 0)
+        .filter(|token| !token.is_empty())
+        .take(MAX_ARGS)
+        .collect();
+
+    match spawn(full_name, &argv, &[], priority) {
+        Ok(pid) => pid,
         Err(e) => {
-            error!(e);
+            error!(e.as_str());
             error!();
             0
         }
@@ -395,15 +500,17 @@ pub unsafe extern "C" fn jump_to_elf(entry: ElfEntry, stack_top: u64, arg: u64)
         "mov rsp, {0}",
         "mov rdi, {1}",
 
-        "push 0x23",
+        "push {ss}",
         "push {0}",
         "pushfq",
-        "push 0x1B",
+        "push {cs}",
         "push {2}",
         "iretq",
         in(reg) user_stack,
         in(reg) arg,
         in(reg) entry,
+        ss = const crate::task::scheduler::USER_SS,
+        cs = const crate::task::scheduler::USER_CS,
         options(noreturn)
     );
 }
@@ -2,6 +2,8 @@ use crate::fs::fat12::{block::Floppy, fs::Filesystem};
 use crate::init::config::SYSTEM_CONFIG;
 use crate::input::cmd;
 use crate::input::port;
+use crate::task::scheduler;
+use crate::time;
 use crate::video::{self, vga};
 
 /// The macimum size of an input to the shell console.
@@ -161,6 +163,61 @@ fn handle_backspace(input_len: &mut usize) {
     }
 }
 
+/// Reads one line typed on the keyboard into `buf` for a user process, echoing it. The line ends
+/// with '\n' unless `buf` fills up first. Returns the length read, or 0 if Ctrl+D is pressed on an
+/// empty line. Waits for keys in PIT ticks, so other processes keep running meanwhile.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    let mut ctrl_down = false;
+
+    while len < buf.len() {
+        let Some(key) = try_load_scancode() else {
+            unsafe {
+                scheduler::sleep_current(time::acpi::get_tick_count() + 1);
+                core::arch::asm!("hlt");
+            }
+            continue;
+        };
+
+        match key {
+            // Control key pressed and released
+            0x1D => ctrl_down = true,
+            0x9D => ctrl_down = false,
+            // Other key released, only updates the Shift state
+            _ if key & 0x80 != 0 => {
+                scancode_to_ascii(key);
+            }
+            // D key
+            0x20 if ctrl_down => {
+                if len == 0 {
+                    return 0;
+                }
+            }
+            // Backspace key
+            0x0E => {
+                if len > 0 {
+                    len -= 1;
+                    print!("\r");
+                }
+            }
+            _ => {
+                if let Some(ascii) = scancode_to_ascii(key) {
+                    buf[len] = ascii;
+                    len += 1;
+
+                    printb!(&[ascii]);
+
+                    if ascii == b'\n' {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    len
+}
+
 /// Runs specialized operations related to the Tab key.
 fn handle_tab_completion(input_buffer: &mut [u8; INPUT_BUFFER_SIZE], input_len: &mut usize) {
     let mut input_cpy = [0u8; 128];
@@ -577,6 +634,23 @@ static mut SCANCODE_BUF_HEAD: usize = 0;
 static mut SCANCODE_BUF: [u8; 2048] = [0; 2048];
 pub static mut SCANCODE_BUF_LOCKED: bool = true;
 
+/// True if a scancode arrived that has not been loaded yet.
+pub fn scancode_pending() -> bool {
+    unsafe { !SCANCODE_BUF_LOCKED }
+}
+
+/// Like `load_scancode`, but returns None instead of waiting for a key.
+pub fn try_load_scancode() -> Option<u8> {
+    unsafe {
+        if SCANCODE_BUF_LOCKED {
+            return None;
+        }
+
+        SCANCODE_BUF_LOCKED = true;
+        Some(SCANCODE_BUF[(SCANCODE_BUF_HEAD - 1) % 2048])
+    }
+}
+
 pub fn load_scancode() -> u8 {
     loop {
         unsafe {
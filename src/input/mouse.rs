@@ -38,6 +38,13 @@ impl MouseSubscriber {
         }
     }
 
+    /// Packets buffered and not drained yet.
+    pub fn available(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Relaxed);
+        (head + MOUSE_BUF_SIZE - tail) % MOUSE_BUF_SIZE
+    }
+
     pub fn drain_to(&self, dst: *mut u8, max_pkts: usize) -> usize {
         let mut copied = 0usize;
         while copied < max_pkts {
@@ -134,62 +141,76 @@ pub fn mouse_drain(pid: usize, dst: *mut u8, max_bytes: usize) -> usize {
     0
 }
 
-fn wait_write() {
-    // Spin until the 8042 input buffer is empty (status bit 1 clear).
-    while port::read_u8(0x64) & 0x02 != 0 {}
+/// Polls of the 8042 status register before a wait gives up, about 100 ms on
+/// port I/O timing.  Keeps boot going when there is no controller or mouse.
+const WAIT_SPINS: usize = 100_000;
+
+/// Spin until the 8042 input buffer is empty (status bit 1 clear).  None on
+/// timeout.
+fn wait_write() -> Option<()> {
+    (0..WAIT_SPINS)
+        .any(|_| port::read_u8(0x64) & 0x02 == 0)
+        .then_some(())
 }
 
-fn wait_read() {
-    // Spin until the 8042 output buffer has data (status bit 0 set).
-    while port::read_u8(0x64) & 0x01 == 0 {}
+/// Spin until the 8042 output buffer has data (status bit 0 set) and read it.
+/// None on timeout.
+fn wait_read() -> Option<u8> {
+    (0..WAIT_SPINS)
+        .any(|_| port::read_u8(0x64) & 0x01 != 0)
+        .then(|| port::read_u8(0x60))
 }
 
 /// Initialise PS/2 mouse: enable auxiliary port on the 8042, unmask IRQ12
 /// on the slave PIC, and instruct the mouse to start sending packets.
-/// Must be called after the IDT is loaded so IRQ12 is handled.
-pub fn init() {
-    wait_write();
+/// Must be called after the IDT is loaded so IRQ12 is handled.  Returns false,
+/// skipping the rest of the setup, if the 8042 has no auxiliary port or stops
+/// answering.
+pub fn init() -> bool {
+    init_aux().is_some()
+}
+
+fn init_aux() -> Option<()> {
+    wait_write()?;
     port::write(0x64, 0xA8); // Enable Auxiliary Device
 
+    // Test the auxiliary port; 0x00 means it works.
+    wait_write()?;
+    port::write(0x64, 0xA9);
+    if wait_read()? != 0x00 {
+        return None;
+    }
+
     // Read the 8042 Controller Configuration Byte (CCB) and set bit 1 to enable
     // mouse IRQ12. QEMU initializes the 8042 with bit 0 (keyboard IRQ1) set and
     // bit 1 (mouse IRQ12) CLEAR. Without explicitly setting bit 1, the 8042 never
     // asserts IRQ12 even if the mouse is enabled and sending data.
-    wait_write();
+    wait_write()?;
     port::write(0x64, 0x20); // "Read CCB" command
-    wait_read();
-    let ccb = port::read_u8(0x60);
-    rprint!("MOUSE INIT: 8042 CCB was ");
-    rprintn!(ccb);
-    rprint!("\n");
-    wait_write();
+    let ccb = wait_read()?;
+    wait_write()?;
     port::write(0x64, 0x60); // "Write CCB" command
-    wait_write();
+    wait_write()?;
     port::write(0x60, ccb | 0x02); // bit 1 = enable mouse IRQ12
 
     // Unmask cascade IRQ2 on master PIC (bit 2 of port 0x21). Without this,
     // ALL slave PIC IRQs (8-15) are blocked — IRQ12 never fires, OBF stays
     // set after any mouse click and permanently blocks keyboard input.
     let master_mask = port::read_u8(0x21);
-    rprint!("MOUSE INIT: master PIC mask was ");
-    rprintn!(master_mask);
-    rprint!("\n");
     port::write_u8(0x21, master_mask & !0x04);
 
     // Unmask IRQ12 on slave PIC (port 0xA1, bit 4).
     let slave_mask = port::read_u8(0xA1);
-    rprint!("MOUSE INIT: slave PIC mask was ");
-    rprintn!(slave_mask);
-    rprint!("\n");
     port::write_u8(0xA1, slave_mask & !0x10);
 
     // Route 0xF4 (Enable Data Reporting) to the mouse via the 8042.
-    wait_write();
+    wait_write()?;
     port::write(0x64, 0xD4);
-    wait_write();
+    wait_write()?;
     port::write(0x60, 0xF4);
 
-    // Discard ACK byte.
-    wait_read();
-    let _ = port::read_u8(0x60);
+    // Discard ACK byte, unless IRQ12 took it already.
+    let _ = wait_read();
+
+    Some(())
 }
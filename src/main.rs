@@ -3,6 +3,8 @@
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
 
+extern crate alloc;
+
 #[macro_use]
 mod debug;
 mod multiboot2;
@@ -13,7 +15,9 @@ mod video;
 mod abi;
 mod acpi;
 mod audio;
+mod backtrace;
 mod fs;
+mod gdb;
 mod init;
 mod input;
 mod mem;
@@ -44,24 +48,33 @@ pub extern "C" fn kernel_main(_multiboot2_magic: u32, multiboot_ptr: u32) {
 //
 //
 
-use core::panic::PanicInfo;
+use core::{fmt::Write, panic::PanicInfo};
 
-/// Panic handler for panic fucntion invocations
+/// Panic handler for panic fucntion invocations.  The message, location and
+/// call stack go to both the screen and COM1.
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    x86_64::instructions::interrupts::disable();
+
     debugln!("kernel panic!");
     clear_screen!();
     error!("KERNEL PANIC\n");
 
+    for b in b"KERNEL PANIC\n" {
+        net::serial::write(*b);
+    }
+
+    let mut w = backtrace::CrashWriter;
+    let _ = writeln!(w, "{}", info.message());
+
     if let Some(location) = info.location() {
-        warn!(location.file());
-        warn!(": ");
-        printn!(location.line() as u64);
-        println!();
+        let _ = writeln!(w, "at {}:{}", location.file(), location.line());
     } else {
-        warn!("no location\n")
+        let _ = writeln!(w, "no location");
     }
 
+    backtrace::print_here();
+
     unsafe {
         loop {
             core::arch::asm!("hlt");
@@ -76,17 +89,12 @@ pub extern "C" fn rust_begin_unwind(_: &core::panic::PanicInfo) {
 
 #[no_mangle]
 pub extern "C" fn panic_bounds_check() -> ! {
-    //panic("bounds check failed");
-    loop {
-        x86_64::instructions::hlt();
-    }
+    panic!("bounds check failed");
 }
 
 #[no_mangle]
 pub extern "C" fn slice_end_index_len_fail() -> ! {
-    loop {
-        x86_64::instructions::hlt();
-    }
+    panic!("slice end index out of range");
 }
 
 #[no_mangle]
@@ -1,54 +0,0 @@
-use core::alloc::{GlobalAlloc, Layout};
-use core::ptr::null_mut;
-use core::sync::atomic::{AtomicUsize, Ordering};
-
-#[global_allocator]
-pub static mut ALLOCATOR: BumpAllocator = BumpAllocator::new();
-
-pub struct BumpAllocator {
-    heap_start: usize,
-    heap_end: usize,
-    next: AtomicUsize,
-}
-
-impl BumpAllocator {
-    pub const fn new() -> Self {
-        Self {
-            heap_start: 0,
-            heap_end: 0,
-            next: AtomicUsize::new(0),
-        }
-    }
-
-    /// Called once during kernel init
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.heap_start = heap_start;
-        self.heap_end = heap_start + heap_size;
-        self.next.store(heap_start, Ordering::SeqCst);
-    }
-}
-
-unsafe impl GlobalAlloc for BumpAllocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let align = layout.align();
-        let size = layout.size();
-
-        let current = self.next.load(Ordering::SeqCst);
-
-        // Align current pointer
-        let aligned = (current + align - 1) & !(align - 1);
-        let new_next = aligned.checked_add(size).unwrap_or(aligned);
-
-        if new_next > self.heap_end {
-            return null_mut(); // Out of memory
-        }
-
-        self.next.store(new_next, Ordering::SeqCst);
-        aligned as *mut u8
-    }
-
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
-        // Bump allocator does not deallocate
-    }
-}
-
@@ -0,0 +1,276 @@
+//! Physical frame allocator.  A bitmap with one bit per 4 KiB frame, seeded
+//! from the usable regions of the Multiboot2 memory map, hands out frames for
+//! page tables, user memory and DMA buffers.  Frames come back zeroed.
+//!
+//! Memory below KHEAP_END keeps its fixed layout (kernel image, floppy DMA
+//! buffer, userland and kernel heap) and is never handed out.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::mem::kheap::KHEAP_END;
+
+pub const FRAME_SIZE: u64 = 0x1000;
+
+/// The boot page tables identity-map the first 5 GiB; memory above is not
+/// reachable through physical addresses and is left out.
+pub const PHYS_LIMIT: u64 = 0x1_4000_0000;
+
+/// Highest address DMA buffers may use: bus masters on PCI take 32-bit
+/// addresses.
+pub const DMA_LIMIT: u64 = 0x1_0000_0000;
+
+const MAX_REGIONS: usize = 32;
+const MAX_RESERVED: usize = 8;
+
+/// Memory assumed if the bootloader passed no memory map: QEMU's default.
+const FALLBACK_MEMORY: u64 = 128 * 1024 * 1024;
+
+struct Frames {
+    /// One bit per frame from address 0, set if the frame is taken or is not
+    /// usable RAM.
+    bitmap: Vec<u64>,
+    /// Word of `bitmap` to start looking for a single free frame.
+    hint: usize,
+    free: usize,
+    /// Usable RAM reported by the memory map, in bytes.
+    total: u64,
+    usable: [(u64, u64); MAX_REGIONS],
+    usable_count: usize,
+    /// Ranges in use before the allocator is set up, like the boot info.
+    reserved: [(u64, u64); MAX_RESERVED],
+    reserved_count: usize,
+}
+
+static FRAMES: Mutex<Frames> = Mutex::new(Frames {
+    bitmap: Vec::new(),
+    hint: 0,
+    free: 0,
+    total: 0,
+    usable: [(0, 0); MAX_REGIONS],
+    usable_count: 0,
+    reserved: [(0, 0); MAX_RESERVED],
+    reserved_count: 0,
+});
+
+/// Amounts of physical memory in bytes, as shown by the `free` command.
+#[derive(Debug, Clone, Copy)]
+pub struct MemStats {
+    pub total: u64,
+    pub free: u64,
+}
+
+/// Record a usable RAM region of the memory map.  Must be called before
+/// `init`.
+pub fn add_region(base: u64, len: u64) {
+    without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        let i = frames.usable_count;
+
+        if i < MAX_REGIONS {
+            frames.usable[i] = (base, base.saturating_add(len));
+            frames.usable_count += 1;
+        }
+    });
+}
+
+/// Keep `len` bytes at `base` from being handed out, e.g. the Multiboot2
+/// information the bootloader left in usable RAM.  Must be called before
+/// `init`.
+pub fn reserve(base: u64, len: u64) {
+    without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        let i = frames.reserved_count;
+
+        if i < MAX_RESERVED {
+            frames.reserved[i] = (base, base.saturating_add(len));
+            frames.reserved_count += 1;
+        }
+    });
+}
+
+/// Build the bitmap from the recorded regions.  Needs the kernel heap.
+/// Returns false if no memory is left to hand out.
+pub fn init() -> bool {
+    without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+
+        if frames.usable_count == 0 {
+            rprint!("frames: no memory map, assuming 128 MiB\n");
+            frames.usable[0] = (0, FALLBACK_MEMORY);
+            frames.usable_count = 1;
+        }
+
+        let count = frames.usable_count;
+        let end = frames.usable[..count]
+            .iter()
+            .map(|&(_, end)| end.min(PHYS_LIMIT))
+            .max()
+            .unwrap_or(0);
+
+        let words = (end / FRAME_SIZE).div_ceil(64) as usize;
+        let mut bitmap = Vec::new();
+        if bitmap.try_reserve_exact(words).is_err() {
+            return false;
+        }
+        bitmap.resize(words, u64::MAX);
+        frames.bitmap = bitmap;
+
+        let mut total = 0;
+
+        for i in 0..count {
+            let (start, end) = frames.usable[i];
+            total += end - start;
+
+            // Whole frames only, above the fixed kernel layout.
+            let first = start.max(KHEAP_END).div_ceil(FRAME_SIZE);
+            let last = end.min(PHYS_LIMIT) / FRAME_SIZE;
+
+            for frame in first..last {
+                frames.set(frame as usize, false);
+            }
+        }
+
+        for i in 0..frames.reserved_count {
+            let (start, end) = frames.reserved[i];
+
+            for frame in start / FRAME_SIZE..end.div_ceil(FRAME_SIZE) {
+                frames.set(frame as usize, true);
+            }
+        }
+
+        frames.total = total;
+        frames.free = frames.bitmap.iter().map(|w| w.count_zeros() as usize).sum();
+
+        rprint!("frames: ");
+        rprintn!(frames.free as u64 * FRAME_SIZE / 1024);
+        rprint!(" KiB free\n");
+
+        frames.free > 0
+    })
+}
+
+/// Take a zeroed frame.  None if physical memory is exhausted.
+pub fn alloc() -> Option<u64> {
+    let frame = without_interrupts(|| FRAMES.lock().take_one())?;
+
+    unsafe {
+        core::ptr::write_bytes(frame as *mut u8, 0, FRAME_SIZE as usize);
+    }
+    Some(frame)
+}
+
+/// Take `count` zeroed frames in a row, starting at a multiple of `align`
+/// frames and ending below `limit`.  Returns the address of the first one.
+pub fn alloc_contiguous(count: usize, align: usize, limit: u64) -> Option<u64> {
+    let frame = without_interrupts(|| FRAMES.lock().take_run(count, align, limit))?;
+
+    unsafe {
+        core::ptr::write_bytes(frame as *mut u8, 0, count * FRAME_SIZE as usize);
+    }
+    Some(frame)
+}
+
+/// Take zeroed frames for a buffer of `size` bytes that devices can reach by
+/// DMA: contiguous and below DMA_LIMIT.
+pub fn alloc_dma(size: usize) -> Option<u64> {
+    alloc_contiguous(size.div_ceil(FRAME_SIZE as usize), 1, DMA_LIMIT)
+}
+
+/// Give back a frame from `alloc`.
+pub fn free(frame: u64) {
+    free_contiguous(frame, 1);
+}
+
+/// Give back `count` frames from `alloc_contiguous` or `alloc_dma`.
+pub fn free_contiguous(frame: u64, count: usize) {
+    without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        let first = (frame / FRAME_SIZE) as usize;
+
+        for i in first..first + count {
+            if i >= frames.bitmap.len() * 64 || !frames.get(i) {
+                rprint!("frames: bad free\n");
+                continue;
+            }
+
+            frames.set(i, false);
+            frames.free += 1;
+        }
+
+        frames.hint = frames.hint.min(first / 64);
+    });
+}
+
+pub fn stats() -> MemStats {
+    without_interrupts(|| {
+        let frames = FRAMES.lock();
+
+        MemStats {
+            total: frames.total,
+            free: frames.free as u64 * FRAME_SIZE,
+        }
+    })
+}
+
+impl Frames {
+    fn get(&self, frame: usize) -> bool {
+        self.bitmap
+            .get(frame / 64)
+            .is_none_or(|w| w & (1 << (frame % 64)) != 0)
+    }
+
+    fn set(&mut self, frame: usize, used: bool) {
+        if let Some(w) = self.bitmap.get_mut(frame / 64) {
+            if used {
+                *w |= 1 << (frame % 64);
+            } else {
+                *w &= !(1 << (frame % 64));
+            }
+        }
+    }
+
+    fn take_one(&mut self) -> Option<u64> {
+        let start = self.hint;
+        let len = self.bitmap.len();
+
+        let word = (start..len)
+            .chain(0..start)
+            .find(|&i| self.bitmap[i] != u64::MAX)?;
+
+        let frame = word * 64 + self.bitmap[word].trailing_ones() as usize;
+
+        self.set(frame, true);
+        self.free -= 1;
+        self.hint = word;
+
+        Some(frame as u64 * FRAME_SIZE)
+    }
+
+    fn take_run(&mut self, count: usize, align: usize, limit: u64) -> Option<u64> {
+        if count == 0 || align == 0 {
+            return None;
+        }
+
+        let end = (self.bitmap.len() * 64).min((limit / FRAME_SIZE) as usize);
+        let mut first = 0;
+
+        while first + count <= end {
+            match (first..first + count).rev().find(|&i| self.get(i)) {
+                // Restart after the taken frame, at the next aligned slot.
+                Some(taken) => first = (taken + 1).div_ceil(align) * align,
+                None => {
+                    for i in first..first + count {
+                        self.set(i, true);
+                    }
+                    self.free -= count;
+
+                    return Some(first as u64 * FRAME_SIZE);
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,149 @@
+/// Kernel heap — 0x0100_0000 to 0x01FF_FFFF (16 MiB, supervisor-only).
+///
+/// Backs the `alloc` crate (`Vec`, `Box`, ...) for kernel structures that
+/// grow at runtime, such as the scheduler's process table and its pool of
+/// kernel stacks.  The region is identity-mapped by the boot page tables and
+/// sits right below the frames handed out by the frame allocator.
+///
+/// Free memory is an address-ordered singly-linked list of blocks.
+/// Allocation is first-fit; freed blocks are merged with both neighbours.
+/// Every block is a multiple of BLOCK_ALIGN bytes and BLOCK_ALIGN-aligned, so
+/// a split never leaves a fragment too small to hold a FreeBlock header.
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub const KHEAP_START: u64 = 0x0100_0000;
+pub const KHEAP_END: u64 = 0x0200_0000; // exclusive (16 MiB region)
+
+const BLOCK_ALIGN: usize = 16;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct FreeList {
+    head: *mut FreeBlock,
+}
+
+// The list only ever points into the kernel heap region.
+unsafe impl Send for FreeList {}
+
+pub struct KernelHeap {
+    free: Mutex<FreeList>,
+}
+
+#[global_allocator]
+static ALLOCATOR: KernelHeap = KernelHeap {
+    free: Mutex::new(FreeList { head: null_mut() }),
+};
+
+/// Call once during kernel init, before anything touches `alloc`.
+/// Writes a single free block covering the whole region.
+pub fn init() {
+    without_interrupts(|| unsafe {
+        let block = KHEAP_START as *mut FreeBlock;
+        block.write(FreeBlock {
+            size: (KHEAP_END - KHEAP_START) as usize,
+            next: null_mut(),
+        });
+        ALLOCATOR.free.lock().head = block;
+    });
+    rprint!("kheap: 16 MiB kernel heap at 0x0100_0000\n");
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let size = block_size(&layout);
+        let align = layout.align().max(BLOCK_ALIGN);
+        // The scheduler frees process resources from the PIT interrupt, so the
+        // lock must never be held with interrupts enabled.
+        without_interrupts(|| self.free.lock().take(size, align))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let size = block_size(&layout);
+        without_interrupts(|| self.free.lock().give(ptr as usize, size))
+    }
+}
+
+impl FreeList {
+    /// Carve `size` bytes aligned to `align` out of the first block that fits.
+    unsafe fn take(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut link: *mut *mut FreeBlock = &mut self.head;
+
+        while !(*link).is_null() {
+            let block = *link;
+            let addr = block as usize;
+            let end = addr + (*block).size;
+            let start = align_up(addr, align);
+
+            if start + size <= end {
+                // Leave the tail (if any) free, in place of the taken block.
+                let tail = start + size;
+                let mut rest = (*block).next;
+                if tail < end {
+                    let t = tail as *mut FreeBlock;
+                    t.write(FreeBlock {
+                        size: end - tail,
+                        next: rest,
+                    });
+                    rest = t;
+                }
+
+                // Keep the alignment gap at the head of the block free too.
+                if start > addr {
+                    (*block).size = start - addr;
+                    (*block).next = rest;
+                } else {
+                    *link = rest;
+                }
+
+                return start as *mut u8;
+            }
+
+            link = &mut (*block).next;
+        }
+
+        null_mut() // out of memory
+    }
+
+    /// Return a block to the list, merging it with adjacent free blocks.
+    unsafe fn give(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut next = self.head;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = (*next).next;
+        }
+
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next });
+
+        if !next.is_null() && addr + size == next as usize {
+            (*block).size += (*next).size;
+            (*block).next = (*next).next;
+        }
+
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+}
+
+#[inline(always)]
+fn block_size(layout: &Layout) -> usize {
+    align_up(layout.size(), BLOCK_ALIGN).max(BLOCK_ALIGN)
+}
+
+#[inline(always)]
+fn align_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
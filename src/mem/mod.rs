@@ -1,5 +1,8 @@
-pub mod bump;
 pub mod c;
+pub mod frames;
 pub mod heap;
+pub mod kheap;
 pub mod pages;
+pub mod shm;
 pub mod uheap;
+pub mod vm;
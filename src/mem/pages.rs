@@ -1,14 +1,7 @@
-const PAGE_TABLE_MEMORY_SIZE: usize = 256 * 1024;
-
-#[repr(C, align(4096))]
-struct PageTablePool {
-    data: [u8; PAGE_TABLE_MEMORY_SIZE],
-}
-
-static mut PAGE_TABLE_POOL: PageTablePool = PageTablePool {
-    data: [0; PAGE_TABLE_MEMORY_SIZE],
+use crate::mem::{
+    frames::{self, FRAME_SIZE, PHYS_LIMIT},
+    kheap::{KHEAP_END, KHEAP_START},
 };
-static mut NEXT_FREE_PAGE: usize = 0;
 
 /// Physical address of the kernel's boot-time P4 table.  Saved once during
 /// early init so the scheduler can restore it when switching to a kernel process.
@@ -20,24 +13,64 @@ pub unsafe fn save_kernel_cr3() {
     KERNEL_CR3 = read_cr3() as u64;
 }
 
-/// Returns the physical base address of the 2 MiB frame reserved for userland
-/// slot `slot`.  Slots map to distinct non-overlapping frames starting at 16 MiB.
-pub fn user_frame_phys(slot: usize) -> u64 {
-    0x1000_000 + slot as u64 * 0x200_000
+/// Size of one shared memory frame (a single P2 huge page).
+pub const USER_FRAME_SIZE: u64 = 0x200_000;
+
+/// USER_FRAME_SIZE in 4 KiB frames.
+const USER_FRAME_PAGES: usize = (USER_FRAME_SIZE / FRAME_SIZE) as usize;
+
+/// Take a zeroed, 2 MiB aligned run of physical frames, to be mapped as one
+/// huge page.  None if no such run is free.
+pub fn alloc_user_frame() -> Option<u64> {
+    frames::alloc_contiguous(USER_FRAME_PAGES, USER_FRAME_PAGES, PHYS_LIMIT)
+}
+
+/// Return a frame obtained from `alloc_user_frame`.
+pub fn free_user_frame(frame: u64) {
+    frames::free_contiguous(frame, USER_FRAME_PAGES);
 }
 
-/// Build a per-process P4/P3/P2 hierarchy for userland slot `slot`.
+/// Shared memory window of every user address space: P3[8], 1 GiB of
+/// USER_FRAME_SIZE slots that `map_shared` fills with shared frames.  Nothing
+/// else lives there, so the kernel's identity map is never shadowed.
+pub const SHM_BASE: u64 = 0x2_0000_0000;
+pub const SHM_END: u64 = SHM_BASE + 0x4000_0000; // exclusive
+const SHM_P3_IDX: usize = 8;
+
+/// Entries of the first GiB's P2 that belong to the low user window
+/// (0x600_000–0x9FF_FFF) rather than to the kernel's identity map.
+const LOW_P2_IDX: [usize; 2] = [3, 4];
+
+/// P3 entries of the high user window (0x1_4000_0000–0x1_FFFF_FFFF), just
+/// above the 5 GiB identity map and below the shared memory window.
+const HIGH_P3_IDX: core::ops::Range<usize> = 5..8;
+
+const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// The kernel heap's 2 MiB pages, P2[8] to P2[15].
+const KHEAP_P2_IDX: usize = (KHEAP_START >> 21) as usize;
+const KHEAP_P2_COUNT: usize = ((KHEAP_END - KHEAP_START) >> 21) as usize;
+
+#[repr(C, align(4096))]
+struct Table([u64; 512]);
+
+/// P1 tables the kernel heap is mapped with once `split_kernel_heap` ran.
+/// They sit in the kernel image, so they are there before the frame
+/// allocator is, and every user page table shares them.
+static mut KHEAP_P1: [Table; KHEAP_P2_COUNT] = [const { Table([0; 512]) }; KHEAP_P2_COUNT];
+
+/// Build an empty per-process P4/P3/P2 hierarchy.
 ///
 /// The new tables share the kernel's identity-mapped entries for all addresses
-/// outside 0x600_000–0x7FF_FFF.  P2[3] is overridden to point at the slot's
-/// private 2 MiB physical frame so that every userland process sees its own
-/// code/data at virtual address 0x600_000 without interference.
+/// outside the user windows.  P2[3] and P2[4], which map 0x600_000–0x9FF_FFF
+/// in the kernel's tables, are left out, as is everything above the identity
+/// map; `map_user_page` fills both windows one 4 KiB page at a time.
 ///
 /// Returns the physical address of the new P4 (suitable for writing to CR3).
-pub unsafe fn create_user_page_table(slot: usize) -> u64 {
+pub unsafe fn create_user_page_table() -> u64 {
     let kernel_p4 = KERNEL_CR3 as *mut u64;
-    let kernel_p3 = (*kernel_p4 & 0x000f_ffff_ffff_f000) as *mut u64;
-    let kernel_p2 = (*kernel_p3 & 0x000f_ffff_ffff_f000) as *mut u64;
+    let kernel_p3 = (*kernel_p4 & ADDR_MASK) as *mut u64;
+    let kernel_p2 = (*kernel_p3 & ADDR_MASK) as *mut u64;
 
     let new_p4 = alloc_page() as *mut u64;
     let new_p3 = alloc_page() as *mut u64;
@@ -49,9 +82,9 @@ pub unsafe fn create_user_page_table(slot: usize) -> u64 {
     core::ptr::copy_nonoverlapping(kernel_p3, new_p3, 512);
     core::ptr::copy_nonoverlapping(kernel_p2, new_p2, 512);
 
-    // Give the slot its own private 2 MiB frame at vaddr 0x600_000.
-    let phys_frame = user_frame_phys(slot);
-    *new_p2.add(3) = phys_frame | PAGE_PRESENT | PAGE_WRITE | PAGE_USER | PAGE_PS;
+    for idx in LOW_P2_IDX {
+        *new_p2.add(idx) = 0;
+    }
 
     // Wire P3[0] → new_p2, P4[0] → new_p3.
     *new_p3 = new_p2 as u64 | PAGE_PRESENT | PAGE_WRITE | PAGE_USER;
@@ -60,6 +93,186 @@ pub unsafe fn create_user_page_table(slot: usize) -> u64 {
     new_p4 as u64
 }
 
+/// Map the kernel heap with 4 KiB pages instead of 2 MiB ones, so that
+/// `unmap_kernel_page` can leave guard pages in it.  Must run after
+/// `save_kernel_cr3` and before the first user page table is cloned, so that
+/// the clones point at the same P1 tables.
+pub unsafe fn split_kernel_heap() {
+    let p4 = KERNEL_CR3 as *mut u64;
+    let p3 = (*p4 & ADDR_MASK) as *mut u64;
+    let p2 = (*p3 & ADDR_MASK) as *mut u64;
+
+    #[expect(static_mut_refs)]
+    for (i, table) in KHEAP_P1.iter_mut().enumerate() {
+        let entry = p2.add(KHEAP_P2_IDX + i);
+        if *entry & PAGE_PRESENT == 0 || *entry & PAGE_PS == 0 {
+            continue;
+        }
+
+        let base = *entry & ADDR_MASK;
+        let flags = *entry & 0xFFF & !PAGE_PS;
+
+        for (j, pte) in table.0.iter_mut().enumerate() {
+            *pte = (base + j as u64 * FRAME_SIZE) | flags;
+        }
+        *entry = table.0.as_ptr() as u64 | (flags & (PAGE_PRESENT | PAGE_WRITE | PAGE_USER));
+    }
+
+    flush_tlb();
+}
+
+/// Unmap the 4 KiB page of the kernel heap at `virt` in every address space,
+/// leaving a hole that faults when touched.  Needs `split_kernel_heap`.
+pub unsafe fn unmap_kernel_page(virt: u64) {
+    if !(KHEAP_START..KHEAP_END).contains(&virt) {
+        return;
+    }
+
+    let idx = ((virt - KHEAP_START) / FRAME_SIZE) as usize;
+    KHEAP_P1[idx / 512].0[idx % 512] = 0;
+
+    x86_64::instructions::tlb::flush(x86_64::VirtAddr::new(virt));
+}
+
+/// Map the 4 KiB page at `virt`, in one of the user windows, to `frame` in
+/// the address space `cr3`, read-only unless `writable`.  Missing P2 and P1
+/// tables are allocated.  Returns false if physical memory is short or
+/// `virt` is covered by a huge page.
+pub unsafe fn map_user_page(cr3: u64, virt: u64, frame: u64, writable: bool) -> bool {
+    let p4 = (cr3 & ADDR_MASK) as *mut u64;
+    let p4e = *p4.add(((virt >> 39) & 0x1FF) as usize);
+    if p4e & PAGE_PRESENT == 0 {
+        return false;
+    }
+    let p3 = (p4e & ADDR_MASK) as *mut u64;
+
+    let Some(p2) = next_table(p3.add(((virt >> 30) & 0x1FF) as usize)) else {
+        return false;
+    };
+    let Some(p1) = next_table(p2.add(((virt >> 21) & 0x1FF) as usize)) else {
+        return false;
+    };
+
+    let mut flags = PAGE_PRESENT | PAGE_USER;
+    if writable {
+        flags |= PAGE_WRITE;
+    }
+    *p1.add(((virt >> 12) & 0x1FF) as usize) = frame | flags;
+
+    if read_cr3() as u64 == cr3 {
+        x86_64::instructions::tlb::flush(x86_64::VirtAddr::new(virt));
+    }
+
+    true
+}
+
+/// Remove the 4 KiB page at `virt` from the user table `cr3`.  Returns the
+/// frame it mapped for the caller to free, or None if nothing was mapped
+/// there.  The tables on the way are kept until the whole table goes.
+pub unsafe fn unmap_user_page(cr3: u64, virt: u64) -> Option<u64> {
+    let mut table = (cr3 & ADDR_MASK) as *mut u64;
+
+    for shift in [39, 30, 21] {
+        let entry = *table.add(((virt >> shift) & 0x1FF) as usize);
+        if entry & PAGE_PRESENT == 0 || entry & PAGE_PS != 0 {
+            return None;
+        }
+        table = (entry & ADDR_MASK) as *mut u64;
+    }
+
+    let entry = table.add(((virt >> 12) & 0x1FF) as usize);
+    if *entry & PAGE_PRESENT == 0 {
+        return None;
+    }
+
+    let frame = *entry & ADDR_MASK;
+    *entry = 0;
+
+    if read_cr3() as u64 == cr3 {
+        x86_64::instructions::tlb::flush(x86_64::VirtAddr::new(virt));
+    }
+
+    Some(frame)
+}
+
+/// Table an intermediate entry points to, allocated if it is not present
+/// yet.  None if the entry is a huge page or no frame is left.
+unsafe fn next_table(entry: *mut u64) -> Option<*mut u64> {
+    if *entry & PAGE_PRESENT == 0 {
+        *entry = frames::alloc()? | PAGE_PRESENT | PAGE_WRITE | PAGE_USER;
+    } else if *entry & PAGE_PS != 0 {
+        return None;
+    }
+
+    Some((*entry & ADDR_MASK) as *mut u64)
+}
+
+/// Free the frames a P1 table maps, then the table itself.
+unsafe fn free_p1(p1: *mut u64) {
+    for i in 0..512 {
+        let entry = *p1.add(i);
+        if entry & PAGE_PRESENT != 0 {
+            frames::free(entry & ADDR_MASK);
+        }
+    }
+
+    free_page(p1 as *mut u8);
+}
+
+/// Tear down a table built by `create_user_page_table`: return every page
+/// mapped in the user windows with their P1 and P2 tables, any VGA P1
+/// installed by `map_vram`, the shared memory P2 installed by `map_shared`,
+/// and the P2/P3/P4 pages themselves.  Shared frames belong to their region
+/// and are left alone.
+///
+/// Must not be called while `cr3` is still needed by a running process.
+pub unsafe fn free_user_page_table(cr3: u64) {
+    if cr3 == 0 || cr3 == KERNEL_CR3 {
+        return;
+    }
+
+    let p4 = cr3 as *mut u64;
+    let p3 = (*p4 & ADDR_MASK) as *mut u64;
+    let p2 = (*p3 & ADDR_MASK) as *mut u64;
+
+    for idx in LOW_P2_IDX {
+        let entry = *p2.add(idx);
+        if entry & PAGE_PRESENT != 0 && entry & PAGE_PS == 0 {
+            free_p1((entry & ADDR_MASK) as *mut u64);
+        }
+    }
+
+    for idx in HIGH_P3_IDX {
+        let entry = *p3.add(idx);
+        if entry & PAGE_PRESENT == 0 {
+            continue;
+        }
+
+        let high_p2 = (entry & ADDR_MASK) as *mut u64;
+        for i in 0..512 {
+            let p2e = *high_p2.add(i);
+            if p2e & PAGE_PRESENT != 0 {
+                free_p1((p2e & ADDR_MASK) as *mut u64);
+            }
+        }
+        free_page(high_p2 as *mut u8);
+    }
+
+    let vram = *p2.add(5);
+    if vram & PAGE_PRESENT != 0 && vram & PAGE_PS == 0 {
+        free_page((vram & ADDR_MASK) as *mut u8);
+    }
+
+    let shm = *p3.add(SHM_P3_IDX);
+    if shm & PAGE_PRESENT != 0 {
+        free_page((shm & ADDR_MASK) as *mut u8);
+    }
+
+    free_page(p2 as *mut u8);
+    free_page(p3 as *mut u8);
+    free_page(p4 as *mut u8);
+}
+
 /// Read the current CR3 value (physical address of the active PML4).
 #[inline]
 pub unsafe fn read_cr3() -> *mut u64 {
@@ -89,71 +302,52 @@ const PAGE_WRITE: u64 = 1 << 1;
 const PAGE_USER: u64 = 1 << 2;
 const PAGE_PS: u64 = 1 << 7; // huge page (2 MiB at P2 level)
 
-/// Walk the active 4-level page table and ensure every existing mapping that
-/// covers [virt_start, virt_end) has the User (U/S) bit set at all levels.
-///
-/// The kernel uses 2 MiB huge pages at the P2 level, so this only touches P4,
-/// P3, and P2 entries — no P1 tables to allocate.  Any entry that is not
-/// already present is left alone (the mapping must have been created first).
-pub unsafe fn ensure_user_pages(virt_start: u64, virt_end: u64) {
-    let p4 = read_cr3();
-
-    // Align start down and end up to 2 MiB boundaries.
-    let start_2m = virt_start & !0x1F_FFFF;
-    let end_2m = (virt_end + 0x1F_FFFF) & !0x1F_FFFF;
-
-    let mut addr = start_2m;
-    while addr < end_2m {
-        let p4_idx = ((addr >> 39) & 0x1FF) as usize;
-        let p3_idx = ((addr >> 30) & 0x1FF) as usize;
-        let p2_idx = ((addr >> 21) & 0x1FF) as usize;
+/// Translate `virt` through the page table whose P4 is at physical `cr3`,
+/// which need not be the active one.  Returns the physical address, or None
+/// if it is not mapped.  Handles 1 GiB, 2 MiB and 4 KiB pages.
+pub unsafe fn translate_in(cr3: u64, virt: u64) -> Option<u64> {
+    let p4 = (cr3 & ADDR_MASK) as *const u64;
 
-        // ── P4 ──────────────────────────────────────────────────────────────
-        let p4e = p4.add(p4_idx);
-        if *p4e & PAGE_PRESENT == 0 {
-            addr += 0x4000_0000; // skip entire 1 GiB
-            continue;
-        }
-        *p4e |= PAGE_USER | PAGE_WRITE;
-
-        let p3 = (*p4e & 0x000f_ffff_ffff_f000) as *mut u64;
-
-        // ── P3 ──────────────────────────────────────────────────────────────
-        let p3e = p3.add(p3_idx);
-        if *p3e & PAGE_PRESENT == 0 {
-            addr += 0x4000_0000; // skip 1 GiB block
-            continue;
-        }
-        // If P3 entry is itself a 1 GiB huge page, just set User and move on.
-        if *p3e & PAGE_PS != 0 {
-            *p3e |= PAGE_USER | PAGE_WRITE;
-            addr += 0x4000_0000;
-            continue;
-        }
-        *p3e |= PAGE_USER | PAGE_WRITE;
+    let p4e = *p4.add(((virt >> 39) & 0x1FF) as usize);
+    if p4e & PAGE_PRESENT == 0 {
+        return None;
+    }
 
-        let p2 = (*p3e & 0x000f_ffff_ffff_f000) as *mut u64;
+    let p3e = *((p4e & ADDR_MASK) as *const u64).add(((virt >> 30) & 0x1FF) as usize);
+    if p3e & PAGE_PRESENT == 0 {
+        return None;
+    }
+    if p3e & PAGE_PS != 0 {
+        return Some((p3e & ADDR_MASK & !0x3FFF_FFFF) + (virt & 0x3FFF_FFFF));
+    }
 
-        // ── P2 ──────────────────────────────────────────────────────────────
-        let p2e = p2.add(p2_idx);
-        if *p2e & PAGE_PRESENT != 0 {
-            *p2e |= PAGE_USER | PAGE_WRITE;
-        }
+    let p2e = *((p3e & ADDR_MASK) as *const u64).add(((virt >> 21) & 0x1FF) as usize);
+    if p2e & PAGE_PRESENT == 0 {
+        return None;
+    }
+    if p2e & PAGE_PS != 0 {
+        return Some((p2e & ADDR_MASK & !0x1F_FFFF) + (virt & 0x1F_FFFF));
+    }
 
-        addr += 0x20_0000; // next 2 MiB
+    let p1e = *((p2e & ADDR_MASK) as *const u64).add(((virt >> 12) & 0x1FF) as usize);
+    if p1e & PAGE_PRESENT == 0 {
+        return None;
     }
 
-    flush_tlb();
+    Some((p1e & ADDR_MASK) + (virt & 0xFFF))
 }
 
+/// Take a zeroed page for a page table from the frame allocator.
 unsafe fn alloc_page() -> *mut u8 {
-    if NEXT_FREE_PAGE + 0x1000 > PAGE_TABLE_MEMORY_SIZE {
-        panic!("Out of preallocated page table memory!");
+    match frames::alloc() {
+        Some(frame) => frame as *mut u8,
+        None => panic!("Out of physical memory for page tables!"),
     }
-    let addr = &mut PAGE_TABLE_POOL.data[NEXT_FREE_PAGE] as *mut u8;
-    NEXT_FREE_PAGE += 0x1000;
-    core::ptr::write_bytes(addr, 0, 0x1000);
-    addr
+}
+
+/// Give a page obtained from `alloc_page` back to the frame allocator.
+unsafe fn free_page(page: *mut u8) {
+    frames::free(page as u64);
 }
 
 /// Map physical VGA graphics RAM (0xA0000–0xAFFFF, 64 KiB) into the current
@@ -196,6 +390,59 @@ pub unsafe fn map_vram() -> u64 {
     VRAM_VIRT
 }
 
+/// Map `frames` (USER_FRAME_SIZE each) at the lowest free run of slots in the
+/// shared memory window of the user address space `cr3`, read-only unless
+/// `writable`.  The window's P2 table is allocated on first use.  Returns the
+/// virtual address of the mapping, or None if no run is long enough.
+pub unsafe fn map_shared(cr3: u64, frames: &[u64], writable: bool) -> Option<u64> {
+    let p4 = cr3 as *mut u64;
+    let p3 = (*p4 & 0x000f_ffff_ffff_f000) as *mut u64;
+    let p3e = p3.add(SHM_P3_IDX);
+
+    if *p3e & PAGE_PRESENT == 0 {
+        *p3e = alloc_page() as u64 | PAGE_PRESENT | PAGE_WRITE | PAGE_USER;
+    }
+    let p2 = (*p3e & 0x000f_ffff_ffff_f000) as *mut u64;
+
+    let count = frames.len();
+    if count == 0 || count > 512 {
+        return None;
+    }
+
+    let start = (0..=512 - count).find(|&i| (i..i + count).all(|j| *p2.add(j) & PAGE_PRESENT == 0))?;
+
+    let mut flags = PAGE_PRESENT | PAGE_USER | PAGE_PS;
+    if writable {
+        flags |= PAGE_WRITE;
+    }
+
+    for (j, frame) in frames.iter().enumerate() {
+        *p2.add(start + j) = frame | flags;
+    }
+    flush_tlb();
+
+    Some(SHM_BASE + start as u64 * USER_FRAME_SIZE)
+}
+
+/// Remove `count` slots mapped by `map_shared` at `virt` from the address
+/// space `cr3`.  The frames themselves are not freed.
+pub unsafe fn unmap_shared(cr3: u64, virt: u64, count: usize) {
+    let p4 = cr3 as *mut u64;
+    let p3 = (*p4 & 0x000f_ffff_ffff_f000) as *mut u64;
+    let p3e = *p3.add(SHM_P3_IDX);
+
+    if p3e & PAGE_PRESENT == 0 || !(SHM_BASE..SHM_END).contains(&virt) {
+        return;
+    }
+    let p2 = (p3e & 0x000f_ffff_ffff_f000) as *mut u64;
+
+    let start = ((virt - SHM_BASE) / USER_FRAME_SIZE) as usize;
+    for j in start..(start + count).min(512) {
+        *p2.add(j) = 0;
+    }
+    flush_tlb();
+}
+
 pub unsafe fn map_32mb(p4: *mut u64, phys_start: usize, virt_start: usize) {
     let p4_index = (virt_start >> 39) & 0x1FF;
     let p3_index = (virt_start >> 30) & 0x1FF;
@@ -0,0 +1,227 @@
+/// Shared memory regions — runs of 2 MiB user frames mapped into several
+/// address spaces at once.
+///
+/// A region is created with a size and an optional name.  The creator may map
+/// it writable; other processes must open it by name first, which grants them
+/// the access the creator chose, and map it into their shared memory window
+/// (`pages::SHM_BASE`) read-only or, if granted, writable.  Anonymous regions
+/// can only be mapped by their creator.  Every address space that created,
+/// opened or maps a region holds a reference to it; the frames return to the
+/// user frame pool once the last one unmaps the region or exits.
+use alloc::vec::Vec;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::mem::pages::{self, USER_FRAME_SIZE};
+use crate::task::service::NAME_LEN;
+
+/// Largest region, in bytes.
+pub const MAX_REGION_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Regions that can exist at once.
+const MAX_REGIONS: usize = 32;
+
+/// An address space holding a reference to a region.
+struct Attachment {
+    cr3: u64,
+    /// Where the region is mapped, or 0 if it is not mapped yet.
+    virt: u64,
+    /// Whether the address space may map the region writable.
+    writable: bool,
+}
+
+struct Region {
+    id: usize,
+    /// NUL-padded name; all zeroes for an anonymous region.
+    name: [u8; NAME_LEN],
+    frames: Vec<u64>,
+    /// Whether processes that open the region may map it writable.
+    shared_writable: bool,
+    users: Vec<Attachment>,
+}
+
+struct Regions {
+    next_id: usize,
+    list: Vec<Region>,
+}
+
+static REGIONS: Mutex<Regions> = Mutex::new(Regions {
+    next_id: 1,
+    list: Vec::new(),
+});
+
+/// Only user address spaces have a shared memory window.
+fn is_user_space(cr3: u64) -> bool {
+    cr3 != 0 && cr3 != unsafe { pages::KERNEL_CR3 }
+}
+
+/// Create a zeroed region of at least `size` bytes, rounded up to whole
+/// frames, held by the address space `cr3` until it maps and unmaps it or
+/// exits.  The creator may map it writable; processes that open it may only
+/// if `shared_writable` is set.  Returns the region ID, or None if the size
+/// or name is rejected, MAX_REGIONS exist already or physical memory is short.
+pub fn create(
+    cr3: u64,
+    size: u64,
+    name: Option<[u8; NAME_LEN]>,
+    shared_writable: bool,
+) -> Option<usize> {
+    if !is_user_space(cr3) || size == 0 || size > MAX_REGION_SIZE {
+        return None;
+    }
+
+    without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+
+        if regions.list.len() >= MAX_REGIONS
+            || name.is_some_and(|n| regions.list.iter().any(|r| r.name == n))
+        {
+            return None;
+        }
+
+        let count = size.div_ceil(USER_FRAME_SIZE) as usize;
+        let mut frames = Vec::new();
+        frames.try_reserve_exact(count).ok()?;
+
+        for _ in 0..count {
+            match pages::alloc_user_frame() {
+                Some(frame) => frames.push(frame),
+                None => {
+                    frames
+                        .iter()
+                        .for_each(|&frame| pages::free_user_frame(frame));
+                    return None;
+                }
+            }
+        }
+
+        let id = regions.next_id;
+        regions.next_id += 1;
+
+        regions.list.push(Region {
+            id,
+            name: name.unwrap_or([0; NAME_LEN]),
+            frames,
+            shared_writable,
+            users: alloc::vec![Attachment {
+                cr3,
+                virt: 0,
+                writable: true,
+            }],
+        });
+
+        Some(id)
+    })
+}
+
+/// Find the ID of the region called `name` and grant the address space `cr3`
+/// the access its creator chose, holding the region until it maps and unmaps
+/// it or exits.  Returns None if there is no such region.
+pub fn open(cr3: u64, name: [u8; NAME_LEN]) -> Option<usize> {
+    if !is_user_space(cr3) || name == [0; NAME_LEN] {
+        return None;
+    }
+
+    without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        let region = regions.list.iter_mut().find(|r| r.name == name)?;
+
+        if !region.users.iter().any(|u| u.cr3 == cr3) {
+            region.users.try_reserve(1).ok()?;
+            region.users.push(Attachment {
+                cr3,
+                virt: 0,
+                writable: region.shared_writable,
+            });
+        }
+
+        Some(region.id)
+    })
+}
+
+/// Map region `id` into the address space `cr3`, which must be the active
+/// one.  Returns the virtual address, or None if there is no such region,
+/// `cr3` neither created nor opened it, it may not be mapped writable there,
+/// it is mapped there already, or the window has no room for it.
+pub fn map(cr3: u64, id: usize, writable: bool) -> Option<u64> {
+    if !is_user_space(cr3) {
+        return None;
+    }
+
+    without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        let region = regions.list.iter_mut().find(|r| r.id == id)?;
+
+        let user = region.users.iter_mut().find(|u| u.cr3 == cr3)?;
+        if user.virt != 0 || (writable && !user.writable) {
+            return None;
+        }
+
+        user.virt = unsafe { pages::map_shared(cr3, &region.frames, writable) }?;
+
+        Some(user.virt)
+    })
+}
+
+/// Unmap the region mapped at `virt` in the address space `cr3`, which must
+/// be the active one, and drop its reference.  Returns false if no region is
+/// mapped there.
+pub fn unmap(cr3: u64, virt: u64) -> bool {
+    without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+
+        let Some((index, user)) = regions.list.iter().enumerate().find_map(|(i, r)| {
+            let user = r
+                .users
+                .iter()
+                .position(|u| u.cr3 == cr3 && u.virt == virt)?;
+            Some((i, user))
+        }) else {
+            return false;
+        };
+
+        let region = &mut regions.list[index];
+
+        unsafe {
+            pages::unmap_shared(cr3, virt, region.frames.len());
+        }
+
+        region.users.remove(user);
+        release_unused(&mut regions);
+
+        true
+    })
+}
+
+/// Drop every reference held by the address space `cr3`, which is being torn
+/// down.  Called before its page tables are freed.
+pub fn release(cr3: u64) {
+    if !is_user_space(cr3) {
+        return;
+    }
+
+    without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+
+        for region in regions.list.iter_mut() {
+            region.users.retain(|u| u.cr3 != cr3);
+        }
+
+        release_unused(&mut regions);
+    });
+}
+
+/// Free the frames of regions nobody holds any more.
+fn release_unused(regions: &mut Regions) {
+    regions.list.retain(|r| {
+        if !r.users.is_empty() {
+            return true;
+        }
+
+        for &frame in r.frames.iter() {
+            pages::free_user_frame(frame);
+        }
+
+        false
+    });
+}
@@ -1,63 +1,52 @@
-/// Userland heap allocator — 0xC00_000 to 0xFFF_FFF (4 MiB, P2[6–7]).
+/// Userland heap allocator — one heap per process, from the first page of the
+/// high user window above its image up to its program break.
 ///
-/// Memory is identity-mapped (physical == virtual) so the kernel can
-/// manipulate it directly while userland reads/writes allocated blocks
-/// through the same virtual addresses.  All processes share one heap;
-/// the spin lock serialises concurrent syscall-level access.
+/// The heap lives in the caller's own address space, so one process cannot
+/// reach another's blocks, and its pages go with the page tables when the
+/// process exits.  The kernel walks the blocks through the caller's mappings
+/// while serving the syscall; pages are filled with zeroes as they are first
+/// touched.  When no free block fits, the break is moved up with `vm::sbrk`
+/// and the new room is appended as a free block.  Interrupts stay off while
+/// a heap is walked, which serialises the threads of a process; there is no
+/// lock to leave held should a page fault kill the caller half-way.
+///
+/// A program that moves its break by hand with the brk/sbrk syscalls should
+/// not use these syscalls as well: blocks cut off by a lower break are lost.
 ///
 /// Block layout (in-band header, 8 bytes):
 ///   [u32 data_size][u32 flags]   flags bit 0: 1=free, 0=used
 /// Minimum data region per block: MIN_SPLIT bytes (prevents infinite splitting).
-use core::sync::atomic::{AtomicBool, Ordering};
-use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
 
-pub const HEAP_START: u64 = 0xC00_000;
-pub const HEAP_END: u64 = 0x1000_000; // exclusive (4 MiB region)
-const HEAP_SIZE: usize = (HEAP_END - HEAP_START) as usize;
+use crate::mem::{frames::FRAME_SIZE, pages, vm};
 
 const HDR: u64 = 8; // header size in bytes
 const FLAG_FREE: u32 = 1;
 const MIN_SPLIT: usize = 16; // min data size to bother splitting a block
+const GROW_MIN: u64 = 0x10000; // move the break by at least 64 KiB
+const MAX_BLOCK: usize = 1 << 30; // largest block handed out
 
-static LOCK: Mutex<()> = Mutex::new(());
-static READY: AtomicBool = AtomicBool::new(false);
-
-// Page-table flags (copied locally to avoid importing pages.rs internals)
-const PAGE_PRESENT: u64 = 1 << 0;
-const PAGE_WRITE: u64 = 1 << 1;
-const PAGE_USER: u64 = 1 << 2;
-const PAGE_PS: u64 = 1 << 7; // 2 MiB huge page at P2 level
-
-/// Call once during kernel init (after `save_kernel_cr3`).
-/// Maps P2[6] and P2[7] as USER+WRITE 2 MiB huge pages, then writes
-/// the initial free block covering the whole 4 MiB heap.
-pub fn init() {
-    unsafe {
-        map_heap_pages();
-        write_hdr(HEAP_START, (HEAP_SIZE - HDR as usize) as u32, FLAG_FREE);
-    }
-    READY.store(true, Ordering::Release);
-    rprint!("uheap: 4 MiB userland heap at 0xC00_000\n");
+/// The heap of the calling process: `start` up to the break at `end`.
+struct Heap {
+    cr3: u64,
+    start: u64,
+    end: u64,
 }
 
-/// Allocate `size` bytes.  Returns the data-region virtual address (inside
-/// [HEAP_START+8, HEAP_END)), or 0 on failure.  Data is zeroed.
+/// Allocate `size` bytes.  Returns the data-region virtual address, or 0 on
+/// failure.  Data is zeroed.
 pub fn malloc(size: usize) -> u64 {
-    if !READY.load(Ordering::Acquire) || size == 0 {
+    if size == 0 || size > MAX_BLOCK {
         return 0;
     }
     let size = align8(size);
-    let _g = LOCK.lock();
-    unsafe { alloc_inner(size) }
+    with_heap(|heap| unsafe { heap.alloc(size) })
 }
 
 /// Reallocate `ptr` to `new_size` bytes.
 /// `ptr == 0` behaves like `malloc(new_size)`.
 /// `new_size == 0` frees `ptr` and returns 0.
 pub fn realloc(ptr: u64, new_size: usize) -> u64 {
-    if !READY.load(Ordering::Acquire) {
-        return 0;
-    }
     if ptr == 0 {
         return malloc(new_size);
     }
@@ -65,161 +54,190 @@ pub fn realloc(ptr: u64, new_size: usize) -> u64 {
         free(ptr);
         return 0;
     }
+    if new_size > MAX_BLOCK {
+        return 0;
+    }
     let new_size = align8(new_size);
-    let _g = LOCK.lock();
-    unsafe { realloc_inner(ptr, new_size) }
+    with_heap(|heap| unsafe { heap.realloc(ptr, new_size) })
 }
 
-/// Free a block previously returned by `malloc` or `realloc`.
+/// Free a block previously returned by `malloc` or `realloc`.  Anything else
+/// is ignored.
 pub fn free(ptr: u64) {
-    if !READY.load(Ordering::Acquire) {
-        return;
-    }
-    if ptr < HEAP_START + HDR || ptr >= HEAP_END {
-        return;
-    }
-    let _g = LOCK.lock();
-    unsafe {
-        set_free(ptr - HDR);
-        coalesce();
-    }
+    with_heap(|heap| unsafe {
+        if let Some(hdr_addr) = heap.find(ptr) {
+            set_free(hdr_addr);
+            heap.coalesce();
+        }
+        0
+    });
 }
 
-// ── inner (lock already held) ────────────────────────────────────────────────
+/// Run `f` on the heap of the calling process with interrupts off.  Returns
+/// 0 if the caller has no address space of its own.
+fn with_heap(f: impl FnOnce(&mut Heap) -> u64) -> u64 {
+    without_interrupts(|| {
+        let cr3 = unsafe { pages::read_cr3() as u64 };
+        let Some((start, end)) = vm::heap(cr3) else {
+            return 0;
+        };
 
-unsafe fn alloc_inner(size: usize) -> u64 {
-    let mut addr = HEAP_START;
-    while addr + HDR <= HEAP_END {
-        let (blk, flags) = read_hdr(addr);
-        if flags & FLAG_FREE != 0 && blk as usize >= size {
-            let remainder = blk as usize - size;
-            if remainder >= HDR as usize + MIN_SPLIT {
-                // Split: carve `size` bytes off the front, leave remainder free.
-                let next = addr + HDR + size as u64;
-                write_hdr(next, (remainder - HDR as usize) as u32, FLAG_FREE);
-                write_hdr(addr, size as u32, 0);
-            } else {
-                // Use the whole block (no split).
-                write_hdr(addr, blk, 0);
-            }
-            let data = addr + HDR;
-            core::ptr::write_bytes(data as *mut u8, 0, size);
-            return data;
+        f(&mut Heap { cr3, start, end })
+    })
+}
+
+// ── inner (interrupts off) ───────────────────────────────────────────────────
+
+impl Heap {
+    /// Header of the block at `addr`, or None past the last block or if the
+    /// header does not describe a block inside the heap.
+    unsafe fn block(&self, addr: u64) -> Option<(u32, u32)> {
+        if addr + HDR > self.end {
+            return None;
         }
-        if blk == 0 {
-            break; // corrupted or end sentinel
+        let (size, flags) = read_hdr(addr);
+        if size == 0 || addr + HDR + size as u64 > self.end {
+            return None; // corrupted, or cut off by a lower break
         }
-        addr += HDR + blk as u64;
+        Some((size, flags))
     }
-    0 // out of memory
-}
 
-unsafe fn realloc_inner(ptr: u64, new_size: usize) -> u64 {
-    let hdr_addr = ptr - HDR;
-    let (old_size, _) = read_hdr(hdr_addr);
-    let old_size = old_size as usize;
-
-    // Shrink or same size — optionally split off the tail.
-    if new_size <= old_size {
-        let remainder = old_size - new_size;
-        if remainder >= HDR as usize + MIN_SPLIT {
-            write_hdr(hdr_addr, new_size as u32, 0);
-            write_hdr(ptr + new_size as u64, (remainder - HDR as usize) as u32, FLAG_FREE);
-            coalesce();
+    /// Header address of the used block whose data starts at `ptr`.
+    unsafe fn find(&self, ptr: u64) -> Option<u64> {
+        let mut addr = self.start;
+        while let Some((size, flags)) = self.block(addr) {
+            if addr + HDR == ptr {
+                return (flags & FLAG_FREE == 0).then_some(addr);
+            }
+            if addr + HDR > ptr {
+                return None;
+            }
+            addr += HDR + size as u64;
+        }
+        None
+    }
+
+    unsafe fn alloc(&mut self, size: usize) -> u64 {
+        let data = self.alloc_fit(size);
+        if data != 0 || !self.grow(size) {
+            return data;
         }
-        return ptr;
+        self.alloc_fit(size)
     }
 
-    // Try in-place expansion: absorb the immediately adjacent free block.
-    let next_addr = ptr + old_size as u64;
-    if next_addr + HDR <= HEAP_END {
-        let (next_size, next_flags) = read_hdr(next_addr);
-        if next_flags & FLAG_FREE != 0 {
-            let combined = old_size + HDR as usize + next_size as usize;
-            if combined >= new_size {
-                let remainder = combined - new_size;
+    unsafe fn alloc_fit(&self, size: usize) -> u64 {
+        let mut addr = self.start;
+        while let Some((blk, flags)) = self.block(addr) {
+            if flags & FLAG_FREE != 0 && blk as usize >= size {
+                let remainder = blk as usize - size;
                 if remainder >= HDR as usize + MIN_SPLIT {
-                    write_hdr(hdr_addr, new_size as u32, 0);
-                    write_hdr(ptr + new_size as u64, (remainder - HDR as usize) as u32, FLAG_FREE);
+                    // Split: carve `size` bytes off the front, leave remainder free.
+                    let next = addr + HDR + size as u64;
+                    write_hdr(next, (remainder - HDR as usize) as u32, FLAG_FREE);
+                    write_hdr(addr, size as u32, 0);
                 } else {
-                    write_hdr(hdr_addr, combined as u32, 0);
+                    // Use the whole block (no split).
+                    write_hdr(addr, blk, 0);
                 }
-                return ptr;
+                let data = addr + HDR;
+                core::ptr::write_bytes(data as *mut u8, 0, size);
+                return data;
             }
+            addr += HDR + blk as u64;
         }
+        0 // out of memory
     }
 
-    // Fall back: allocate a new block, copy data, free the old block.
-    let new_ptr = alloc_inner(new_size);
-    if new_ptr == 0 {
-        return 0;
-    }
-    core::ptr::copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8, old_size);
-    set_free(hdr_addr);
-    coalesce();
-    new_ptr
-}
+    /// Move the break up far enough for a `size`-byte block and append the
+    /// new room as a free block, merged with a free block before it.
+    unsafe fn grow(&mut self, size: usize) -> bool {
+        let block = align8(self.end as usize) as u64;
+        let new_end = (block + HDR + size as u64)
+            .max(self.end + GROW_MIN)
+            .next_multiple_of(FRAME_SIZE);
 
-/// Linear-scan coalescing: merge pairs of adjacent free blocks.
-unsafe fn coalesce() {
-    let mut addr = HEAP_START;
-    while addr + HDR <= HEAP_END {
-        let (size, flags) = read_hdr(addr);
-        if size == 0 {
-            break;
-        }
-        let next = addr + HDR + size as u64;
-        if flags & FLAG_FREE != 0 && next + HDR <= HEAP_END {
-            let (next_size, next_flags) = read_hdr(next);
-            if next_flags & FLAG_FREE != 0 {
-                let merged = size as usize + HDR as usize + next_size as usize;
-                write_hdr(addr, merged as u32, FLAG_FREE);
-                continue; // re-examine from the same addr
-            }
+        if vm::sbrk(self.cr3, (new_end - self.end) as i64).is_none() {
+            return false;
         }
-        addr += HDR + size as u64;
+
+        self.end = new_end;
+        write_hdr(block, (new_end - block - HDR) as u32, FLAG_FREE);
+        self.coalesce();
+        true
     }
-}
 
-// ── page-table helpers ───────────────────────────────────────────────────────
+    unsafe fn realloc(&mut self, ptr: u64, new_size: usize) -> u64 {
+        let Some(hdr_addr) = self.find(ptr) else {
+            return 0;
+        };
+        let (old_size, _) = read_hdr(hdr_addr);
+        let old_size = old_size as usize;
 
-unsafe fn map_heap_pages() {
-    let p4 = crate::mem::pages::read_cr3();
-    if (*p4) & PAGE_PRESENT == 0 {
-        return;
-    }
-    let p3 = ((*p4) & 0x000f_ffff_ffff_f000) as *mut u64;
-    if (*p3) & PAGE_PRESENT == 0 {
-        return;
-    }
-    let p2 = ((*p3) & 0x000f_ffff_ffff_f000) as *mut u64;
+        // Shrink or same size — optionally split off the tail.
+        if new_size <= old_size {
+            let remainder = old_size - new_size;
+            if remainder >= HDR as usize + MIN_SPLIT {
+                write_hdr(hdr_addr, new_size as u32, 0);
+                write_hdr(
+                    ptr + new_size as u64,
+                    (remainder - HDR as usize) as u32,
+                    FLAG_FREE,
+                );
+                self.coalesce();
+            }
+            return ptr;
+        }
 
-    let huge = PAGE_PRESENT | PAGE_WRITE | PAGE_USER | PAGE_PS;
+        // Try in-place expansion: absorb the immediately adjacent free block.
+        let next_addr = ptr + old_size as u64;
+        if let Some((next_size, next_flags)) = self.block(next_addr) {
+            if next_flags & FLAG_FREE != 0 {
+                let combined = old_size + HDR as usize + next_size as usize;
+                if combined >= new_size {
+                    let remainder = combined - new_size;
+                    if remainder >= HDR as usize + MIN_SPLIT {
+                        write_hdr(hdr_addr, new_size as u32, 0);
+                        write_hdr(
+                            ptr + new_size as u64,
+                            (remainder - HDR as usize) as u32,
+                            FLAG_FREE,
+                        );
+                    } else {
+                        write_hdr(hdr_addr, combined as u32, 0);
+                    }
+                    return ptr;
+                }
+            }
+        }
 
-    // P2[6] → physical 0xC00_000 (virtual 0xC00_000–0xDFF_FFF)
-    let e6 = p2.add(6);
-    if (*e6) & PAGE_PRESENT == 0 {
-        *e6 = 0xC00_000 | huge;
-    } else {
-        *e6 |= PAGE_USER | PAGE_WRITE;
+        // Fall back: allocate a new block, copy data, free the old block.
+        let new_ptr = self.alloc(new_size);
+        if new_ptr == 0 {
+            return 0;
+        }
+        core::ptr::copy_nonoverlapping(ptr as *const u8, new_ptr as *mut u8, old_size);
+        set_free(hdr_addr);
+        self.coalesce();
+        new_ptr
     }
 
-    // P2[7] → physical 0xE00_000 (virtual 0xE00_000–0xFFF_FFF)
-    let e7 = p2.add(7);
-    if (*e7) & PAGE_PRESENT == 0 {
-        *e7 = 0xE00_000 | huge;
-    } else {
-        *e7 |= PAGE_USER | PAGE_WRITE;
+    /// Linear-scan coalescing: merge pairs of adjacent free blocks.
+    unsafe fn coalesce(&self) {
+        let mut addr = self.start;
+        while let Some((size, flags)) = self.block(addr) {
+            let next = addr + HDR + size as u64;
+            if flags & FLAG_FREE != 0 {
+                if let Some((next_size, next_flags)) = self.block(next) {
+                    if next_flags & FLAG_FREE != 0 {
+                        let merged = size as usize + HDR as usize + next_size as usize;
+                        write_hdr(addr, merged as u32, FLAG_FREE);
+                        continue; // re-examine from the same addr
+                    }
+                }
+            }
+            addr = next;
+        }
     }
-
-    // TLB flush (reload CR3 with itself)
-    let _cr3: u64;
-    core::arch::asm!(
-        "mov {0}, cr3",
-        "mov cr3, {0}",
-        out(reg) _cr3,
-        options(nostack, preserves_flags),
-    );
 }
 
 // ── header I/O ───────────────────────────────────────────────────────────────
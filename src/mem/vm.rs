@@ -0,0 +1,668 @@
+//! User address spaces.  Every user process has its own page tables, built
+//! from 4 KiB pages that are only filled when first touched: the page-fault
+//! handler looks the faulting address up in the areas of the active address
+//! space, takes a frame and fills it from the ELF image or with zeroes.  An
+//! address outside every area is a segmentation fault.
+//!
+//! Above its image, each process has a heap that `brk`/`sbrk` move the end
+//! of, and mappings it asks for with `mmap`.  The heap and anonymous
+//! mappings are zero-filled on demand like the stacks; file mappings are
+//! filled from the file, one page at a time, with the address spaces
+//! unlocked while the disk is read.  All of them go with the page tables when
+//! the process exits.
+//!
+//! User memory lives in two windows: the low one at 0x600_000, where the
+//! programs linked at the traditional load address go, and the high one, the
+//! 3 GiB between the kernel's identity map and the shared memory window.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{
+    fs::file::{self, File},
+    mem::{
+        frames::{self, FRAME_SIZE, PHYS_LIMIT},
+        pages::{self, SHM_BASE},
+    },
+    task::process::GUARD_SIZE,
+};
+
+/// Low user window, P2[3] and P2[4] of the first GiB.  The rest of that GiB
+/// is the kernel's identity map.
+pub const LOW_START: u64 = 0x600_000;
+pub const LOW_END: u64 = 0xA00_000; // exclusive
+
+/// High user window, P3[5] to P3[7].
+pub const HIGH_START: u64 = PHYS_LIMIT;
+pub const HIGH_END: u64 = SHM_BASE; // exclusive
+
+/// Page-fault error code bits.
+const PF_PRESENT: u64 = 1 << 0;
+const PF_WRITE: u64 = 1 << 1;
+
+/// A PT_LOAD segment of the image: `file_len` bytes at `offset` in the image
+/// belong at `virt`, the rest up to `mem_len` is zeroed.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    pub virt: u64,
+    pub offset: u64,
+    pub file_len: u64,
+    pub mem_len: u64,
+    pub writable: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// Filled from the segments of the image.
+    Image,
+    /// Zero-filled thread stacks, growing down as they are touched.
+    Stack,
+    /// The zero-filled heap up to the program break.
+    Heap,
+    /// A zero-filled mapping made by `map_anon`.
+    Anon,
+    /// A private copy of `file` from byte `offset` on, which belongs at the
+    /// start of the area.  Bytes past the end of the file read as zero.
+    File { file: File, offset: u64 },
+}
+
+impl Kind {
+    /// Made by `map_anon` or `map_file`, so `unmap` may remove it.
+    fn is_mapping(&self) -> bool {
+        matches!(self, Kind::Anon | Kind::File { .. })
+    }
+}
+
+/// A page-aligned range of an address space that faults are resolved in.
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    start: u64,
+    end: u64,
+    writable: bool,
+    kind: Kind,
+}
+
+struct Space {
+    cr3: u64,
+    /// The ELF file the image areas are filled from.
+    image: Vec<u8>,
+    segments: Vec<Segment>,
+    /// Sorted by address, never overlapping.  Holds exactly one heap area,
+    /// empty while the break is at `heap_start`.
+    areas: Vec<Area>,
+    heap_start: u64,
+    brk: u64,
+}
+
+static SPACES: Mutex<Vec<Space>> = Mutex::new(Vec::new());
+
+/// Bumped with SPACES locked whenever areas go away, so that a file page read
+/// with the lock dropped is only mapped if its area may still be there.
+static UNMAPS: AtomicU64 = AtomicU64::new(0);
+
+/// True if `addr` lies in one of the user windows.
+pub fn in_user_window(addr: u64) -> bool {
+    (LOW_START..LOW_END).contains(&addr) || (HIGH_START..HIGH_END).contains(&addr)
+}
+
+/// Set up the areas of the fresh address space `cr3`: the page-aligned
+/// extent of each segment, merged where segments share pages, and the thread
+/// stacks in `stacks`, which are kept apart by unmapped guard pages.  The
+/// segments must lie in the user windows; `image` is kept to fill them from
+/// until the address space is released.  The heap starts empty at the first
+/// page of the high window above the image.
+/// Returns false if the kernel heap is short.
+pub fn create(cr3: u64, image: Vec<u8>, segments: &[Segment], stacks: &[Range<u64>]) -> bool {
+    let mut areas: Vec<Area> = Vec::new();
+    let mut list = Vec::new();
+
+    if areas
+        .try_reserve(segments.len() + stacks.len() + 1)
+        .is_err()
+        || list.try_reserve_exact(segments.len()).is_err()
+    {
+        return false;
+    }
+    list.extend_from_slice(segments);
+
+    for seg in segments {
+        let mut area = Area {
+            start: seg.virt & !(FRAME_SIZE - 1),
+            end: (seg.virt + seg.mem_len).div_ceil(FRAME_SIZE) * FRAME_SIZE,
+            writable: seg.writable,
+            kind: Kind::Image,
+        };
+
+        // A page shared by two segments takes the rights of both.
+        areas.retain(|a| {
+            if a.start < area.end && area.start < a.end {
+                area.start = area.start.min(a.start);
+                area.end = area.end.max(a.end);
+                area.writable |= a.writable;
+                return false;
+            }
+            true
+        });
+        areas.push(area);
+    }
+
+    let heap_start = areas
+        .iter()
+        .map(|a| a.end)
+        .filter(|&end| end > HIGH_START)
+        .max()
+        .unwrap_or(HIGH_START);
+
+    areas.push(Area {
+        start: heap_start,
+        end: heap_start,
+        writable: true,
+        kind: Kind::Heap,
+    });
+    areas.extend(stacks.iter().map(|stack| Area {
+        start: stack.start,
+        end: stack.end,
+        writable: true,
+        kind: Kind::Stack,
+    }));
+    areas.sort_unstable_by_key(|a| a.start);
+
+    without_interrupts(|| {
+        let mut spaces = SPACES.lock();
+        if spaces.try_reserve(1).is_err() {
+            return false;
+        }
+
+        spaces.push(Space {
+            cr3,
+            image,
+            segments: list,
+            areas,
+            heap_start,
+            brk: heap_start,
+        });
+        true
+    })
+}
+
+/// Forget the address space `cr3`, which is being torn down.  Called before
+/// its page tables are freed.
+pub fn release(cr3: u64) {
+    without_interrupts(|| {
+        SPACES.lock().retain(|s| s.cr3 != cr3);
+        UNMAPS.fetch_add(1, Ordering::Relaxed);
+    });
+}
+
+/// Resolve a page fault at `addr` with the `error_code` the CPU pushed.
+/// Returns true if a page was mapped there and the faulting instruction can
+/// be retried, false if the access was a segmentation fault.
+///
+/// Runs with interrupts off, from user mode or from the kernel touching user
+/// memory on behalf of a syscall.
+pub unsafe fn handle_fault(addr: u64, error_code: u64) -> bool {
+    if error_code & PF_PRESENT != 0 || !in_user_window(addr) {
+        return false;
+    }
+
+    fault_in(pages::read_cr3() as u64, addr, error_code & PF_WRITE != 0).is_some()
+}
+
+/// Physical address backing `virt` in the address space `cr3`, mapping its
+/// page first if it belongs to an area but was not touched yet.  Outside the
+/// areas, this is a plain page-table walk.
+pub unsafe fn resolve_in(cr3: u64, virt: u64) -> Option<u64> {
+    if let Some(phys) = pages::translate_in(cr3, virt) {
+        return Some(phys);
+    }
+
+    without_interrupts(|| fault_in(cr3, virt, false)).map(|frame| frame + (virt & (FRAME_SIZE - 1)))
+}
+
+/// Map the page holding `addr` in the address space `cr3` if it belongs to an
+/// area, and to a writable one if `write`.  Returns the frame backing it.
+///
+/// File pages are read with SPACES unlocked: a floppy read must not stall
+/// every other address space, and the filesystem has a lock of its own.  The
+/// page is mapped afterwards unless an area went away meanwhile, in which case
+/// the lookup starts over.  Runs with interrupts off.
+unsafe fn fault_in(cr3: u64, addr: u64, write: bool) -> Option<u64> {
+    loop {
+        let (area, unmaps) = {
+            let spaces = SPACES.lock();
+            let space = spaces.iter().find(|s| s.cr3 == cr3)?;
+            let area = space.area_at(addr)?;
+
+            if write && !area.writable {
+                return None;
+            }
+            if !matches!(area.kind, Kind::File { .. }) {
+                return space.populate(area, addr, None);
+            }
+            if let Some(phys) = pages::translate_in(cr3, addr & !(FRAME_SIZE - 1)) {
+                return Some(phys);
+            }
+
+            (area, UNMAPS.load(Ordering::Relaxed))
+        };
+
+        let frame = read_page(area, addr)?;
+
+        let spaces = SPACES.lock();
+        if UNMAPS.load(Ordering::Relaxed) != unmaps {
+            drop(spaces);
+            frames::free(frame);
+            continue;
+        }
+
+        return match spaces.iter().find(|s| s.cr3 == cr3) {
+            Some(space) => space.populate(area, addr, Some(frame)),
+            None => {
+                frames::free(frame);
+                None
+            }
+        };
+    }
+}
+
+/// Read the page of the file area `area` holding `addr` into a fresh frame.
+/// Returns None if physical memory is short or the file cannot be read.
+unsafe fn read_page(area: Area, addr: u64) -> Option<u64> {
+    let Kind::File { file, offset } = area.kind else {
+        return None;
+    };
+
+    let page = addr & !(FRAME_SIZE - 1);
+    let frame = frames::alloc()?;
+    let buf = core::slice::from_raw_parts_mut(frame as *mut u8, FRAME_SIZE as usize);
+
+    if file::read_at(&file, offset + page - area.start, buf).is_err() {
+        frames::free(frame);
+        return None;
+    }
+
+    Some(frame)
+}
+
+/// `resolve_in` for the active address space.
+pub unsafe fn resolve(virt: u64) -> Option<u64> {
+    resolve_in(pages::read_cr3() as u64, virt)
+}
+
+/// Write `data` at `virt` in the address space `cr3`, which need not be the
+/// active one.  Returns false if part of the range is outside its areas or
+/// physical memory is short.
+pub unsafe fn copy_to(cr3: u64, virt: u64, data: &[u8]) -> bool {
+    let mut done = 0;
+
+    while done < data.len() {
+        let addr = virt + done as u64;
+        let Some(phys) = resolve_in(cr3, addr) else {
+            return false;
+        };
+
+        let n = (FRAME_SIZE - (addr & (FRAME_SIZE - 1))).min((data.len() - done) as u64) as usize;
+        core::ptr::copy_nonoverlapping(data[done..].as_ptr(), phys as *mut u8, n);
+        done += n;
+    }
+
+    true
+}
+
+/// True if `addr` lies in the guard page right below or above a thread
+/// stack of the active address space, so a fault there is a stack overflow.
+pub fn is_stack_guard(addr: u64) -> bool {
+    let cr3 = unsafe { pages::read_cr3() as u64 };
+
+    without_interrupts(|| {
+        let spaces = SPACES.lock();
+        let Some(space) = spaces.iter().find(|s| s.cr3 == cr3) else {
+            return false;
+        };
+
+        space.area_at(addr).is_none()
+            && space
+                .areas
+                .iter()
+                .any(|a| matches!(a.kind, Kind::Stack) && a.reach().contains(&addr))
+    })
+}
+
+/// True if the `len` bytes at `ptr` lie in areas of the active address
+/// space, so the kernel may touch them on behalf of a syscall.
+pub fn is_user_range(ptr: u64, len: u64) -> bool {
+    let Some(end) = ptr.checked_add(len) else {
+        return false;
+    };
+    let cr3 = unsafe { pages::read_cr3() as u64 };
+
+    without_interrupts(|| {
+        let spaces = SPACES.lock();
+        let Some(space) = spaces.iter().find(|s| s.cr3 == cr3) else {
+            return false;
+        };
+
+        // Adjacent areas are walked through one after another.
+        let mut addr = ptr;
+        loop {
+            let Some(area) = space.area_at(addr) else {
+                return false;
+            };
+            if end <= area.end {
+                return true;
+            }
+            addr = area.end;
+        }
+    })
+}
+
+/// Bounds of the heap of the address space `cr3`: its start and the current
+/// program break.
+pub fn heap(cr3: u64) -> Option<(u64, u64)> {
+    without_interrupts(|| {
+        let spaces = SPACES.lock();
+        let space = spaces.iter().find(|s| s.cr3 == cr3)?;
+        Some((space.heap_start, space.brk))
+    })
+}
+
+/// Move the program break of the address space `cr3` to `new`; 0 only asks
+/// for it.  Growing fails if the heap would run into another area, shrinking
+/// returns the pages above the new break.  Returns the break in effect, or
+/// None if it could not be moved.
+pub fn brk(cr3: u64, new: u64) -> Option<u64> {
+    without_interrupts(|| {
+        let mut spaces = SPACES.lock();
+        let space = spaces.iter_mut().find(|s| s.cr3 == cr3)?;
+
+        if new != 0 {
+            unsafe { space.set_brk(new)? };
+        }
+        Some(space.brk)
+    })
+}
+
+/// Move the program break of the address space `cr3` by `incr` bytes.
+/// Returns the old break, where the new memory starts, or None if it could
+/// not be moved.
+pub fn sbrk(cr3: u64, incr: i64) -> Option<u64> {
+    without_interrupts(|| {
+        let mut spaces = SPACES.lock();
+        let space = spaces.iter_mut().find(|s| s.cr3 == cr3)?;
+
+        let old = space.brk;
+        unsafe { space.set_brk(old.checked_add_signed(incr)?)? };
+        Some(old)
+    })
+}
+
+/// Reserve `len` bytes of zero-filled memory in the address space `cr3`,
+/// placed in the highest gap of the high window below the stacks.  Returns
+/// the page-aligned address, or None if no gap is large enough.
+pub fn map_anon(cr3: u64, len: u64, writable: bool) -> Option<u64> {
+    map(cr3, len, writable, Kind::Anon)
+}
+
+/// Map `len` bytes of `file` from the page-aligned `offset` in the address
+/// space `cr3`, placed like `map_anon`.  Pages are read from the file when
+/// first touched; a writable mapping is a private copy, whose changes never
+/// reach the file.  Returns the address, or None if `offset` is not aligned
+/// or no gap is large enough.
+pub fn map_file(cr3: u64, len: u64, writable: bool, file: File, offset: u64) -> Option<u64> {
+    if offset & (FRAME_SIZE - 1) != 0 {
+        return None;
+    }
+
+    map(cr3, len, writable, Kind::File { file, offset })
+}
+
+fn map(cr3: u64, len: u64, writable: bool, kind: Kind) -> Option<u64> {
+    let len = len.checked_next_multiple_of(FRAME_SIZE)?;
+    if len == 0 {
+        return None;
+    }
+
+    without_interrupts(|| {
+        let mut spaces = SPACES.lock();
+        let space = spaces.iter_mut().find(|s| s.cr3 == cr3)?;
+
+        // Searching down from the stacks leaves the room above the heap
+        // free for the break for as long as possible.
+        let mut top = HIGH_END;
+        let mut start = None;
+
+        for area in space.areas.iter().rev() {
+            let reach = area.reach();
+            if top.saturating_sub(reach.end.max(HIGH_START)) >= len {
+                start = Some(top - len);
+                break;
+            }
+            top = top.min(reach.start);
+            if top <= HIGH_START {
+                break;
+            }
+        }
+
+        if start.is_none() && top > HIGH_START && top - HIGH_START >= len {
+            start = Some(top - len);
+        }
+
+        let start = start?;
+        space.areas.try_reserve(1).ok()?;
+        space.insert(Area {
+            start,
+            end: start + len,
+            writable,
+            kind,
+        });
+
+        Some(start)
+    })
+}
+
+/// Remove the pages in `len` bytes from `addr` from the mappings of the
+/// address space `cr3`, returning their frames.  A mapping may be cut in
+/// parts.  Returns false if the range is not page-aligned or covers anything
+/// but mappings made by `map_anon` or `map_file`.
+pub fn unmap(cr3: u64, addr: u64, len: u64) -> bool {
+    let Some(end) = addr
+        .checked_add(len)
+        .and_then(|end| end.checked_next_multiple_of(FRAME_SIZE))
+    else {
+        return false;
+    };
+    if addr & (FRAME_SIZE - 1) != 0 || len == 0 {
+        return false;
+    }
+
+    without_interrupts(|| {
+        let mut spaces = SPACES.lock();
+        let Some(space) = spaces.iter_mut().find(|s| s.cr3 == cr3) else {
+            return false;
+        };
+
+        // The whole range must be covered by adjacent mappings.
+        let mut at = addr;
+        while at < end {
+            match space.area_at(at) {
+                Some(area) if area.kind.is_mapping() => at = area.end,
+                _ => return false,
+            }
+        }
+
+        // Cutting the middle out of a mapping leaves one more area.
+        if space.areas.try_reserve(1).is_err() {
+            return false;
+        }
+
+        let mut tail = None;
+        space.areas.retain_mut(|a| {
+            if !a.kind.is_mapping() || a.end <= addr || end <= a.start {
+                return true;
+            }
+            if a.start < addr && end < a.end {
+                tail = Some(a.from(end));
+            }
+            if a.start < addr {
+                a.end = addr;
+                return true;
+            }
+            if end < a.end {
+                *a = a.from(end);
+                return true;
+            }
+            false
+        });
+        if let Some(tail) = tail {
+            space.insert(tail);
+        }
+        UNMAPS.fetch_add(1, Ordering::Relaxed);
+
+        unsafe { space.drop_pages(addr, end) };
+        true
+    })
+}
+
+impl Area {
+    /// The addresses the area keeps others out of: a stack also claims the
+    /// guard pages below and above it, which are never mapped.
+    fn reach(&self) -> Range<u64> {
+        match self.kind {
+            Kind::Stack => self.start - GUARD_SIZE..self.end + GUARD_SIZE,
+            _ => self.start..self.end,
+        }
+    }
+
+    /// The part of the area from `start` on.
+    fn from(self, start: u64) -> Area {
+        let kind = match self.kind {
+            Kind::File { file, offset } => Kind::File {
+                file,
+                offset: offset + start - self.start,
+            },
+            kind => kind,
+        };
+
+        Area {
+            start,
+            kind,
+            ..self
+        }
+    }
+}
+
+impl Space {
+    fn area_at(&self, addr: u64) -> Option<Area> {
+        self.areas
+            .iter()
+            .find(|a| (a.start..a.end).contains(&addr))
+            .copied()
+    }
+
+    /// Put `area` in its place among the others.  Room must be reserved.
+    fn insert(&mut self, area: Area) {
+        let at = self.areas.partition_point(|a| a.start < area.start);
+        self.areas.insert(at, area);
+    }
+
+    /// Move the break to `new`, resizing the heap area to the pages below
+    /// it.  None if `new` is below the heap or the heap would overlap
+    /// another area or leave the high window.
+    unsafe fn set_brk(&mut self, new: u64) -> Option<()> {
+        if new < self.heap_start || new > HIGH_END {
+            return None;
+        }
+
+        let end = new.next_multiple_of(FRAME_SIZE);
+        let idx = self
+            .areas
+            .iter()
+            .position(|a| matches!(a.kind, Kind::Heap))?;
+        let old_end = self.areas[idx].end;
+
+        if end > old_end
+            && self
+                .areas
+                .iter()
+                .any(|a| !matches!(a.kind, Kind::Heap) && a.reach().start < end && old_end < a.end)
+        {
+            return None;
+        }
+
+        self.areas[idx].end = end;
+        if end < old_end {
+            self.drop_pages(end, old_end);
+        }
+
+        self.brk = new;
+        Some(())
+    }
+
+    /// Unmap the pages touched so far between `start` and `end` and return
+    /// their frames.
+    unsafe fn drop_pages(&self, start: u64, end: u64) {
+        for page in (start..end).step_by(FRAME_SIZE as usize) {
+            if let Some(frame) = pages::unmap_user_page(self.cr3, page) {
+                frames::free(frame);
+            }
+        }
+    }
+
+    /// Take a frame for the page of `area` holding `addr`, fill it and map
+    /// it.  A file page comes as the frame `read` by `read_page`, which is
+    /// freed if the page is already mapped.  Returns the frame, or None if
+    /// physical memory is short.
+    unsafe fn populate(&self, area: Area, addr: u64, read: Option<u64>) -> Option<u64> {
+        let page = addr & !(FRAME_SIZE - 1);
+
+        // Another thread may have touched the page since the TLB was filled.
+        if let Some(phys) = pages::translate_in(self.cr3, page) {
+            if let Some(frame) = read {
+                frames::free(frame);
+            }
+            return Some(phys);
+        }
+
+        let frame = match read {
+            Some(frame) => frame,
+            None => {
+                let frame = frames::alloc()?;
+                if let Kind::Image = area.kind {
+                    self.fill(frame, page);
+                }
+                frame
+            }
+        };
+
+        if !pages::map_user_page(self.cr3, page, frame, area.writable) {
+            frames::free(frame);
+            return None;
+        }
+
+        Some(frame)
+    }
+
+    /// Copy the file bytes of every segment overlapping `page` into the
+    /// zeroed `frame`.
+    unsafe fn fill(&self, frame: u64, page: u64) {
+        for seg in self.segments.iter() {
+            let start = seg.virt.max(page);
+            let end = (seg.virt + seg.file_len).min(page + FRAME_SIZE);
+
+            if start >= end {
+                continue;
+            }
+
+            let src = self
+                .image
+                .as_ptr()
+                .add((seg.offset + start - seg.virt) as usize);
+            let dst = (frame + start - page) as *mut u8;
+
+            core::ptr::copy_nonoverlapping(src, dst, (end - start) as usize);
+        }
+    }
+}
@@ -18,8 +18,6 @@ use crate::task::scheduler;
 const NO_PID: usize = 0xff;
 const MAX_PORT_BINDINGS: usize = 16;
 
-static mut NET_FRAME_BUF: [u8; 2048] = [0u8; 2048];
-
 /// PID of the global Ethernet driver (handles ARP, ICMP, and unbound TCP ports).
 static mut NET_DRV_PID: usize = NO_PID;
 
@@ -88,11 +86,6 @@ pub unsafe fn fill_port_bindings(n_ports: &mut u8, ports: &mut [u16; 16]) {
     *n_ports = count;
 }
 
-/// Copy a frame from NET_FRAME_BUF to a userland pointer.
-pub unsafe fn copy_frame_out(dst: *mut u8, len: usize) {
-    core::ptr::copy_nonoverlapping(NET_FRAME_BUF.as_ptr(), dst, len);
-}
-
 /// Extract the TCP destination port from a raw Ethernet frame.
 /// Returns None if the frame is not IPv4/TCP or is too short.
 fn tcp_dest_port(frame: &[u8]) -> Option<u16> {
@@ -136,15 +129,16 @@ pub unsafe fn loopback_deliver(frame: &[u8], len: usize) {
     if NET_DRV_PID == NO_PID {
         return;
     }
-    if len > NET_FRAME_BUF.len() {
+    if len > frame.len() {
         return;
     }
-    core::ptr::copy_nonoverlapping(frame.as_ptr(), NET_FRAME_BUF.as_mut_ptr(), len);
-    let dest_pid = tcp_dest_port(&NET_FRAME_BUF[..len])
+    let dest_pid = tcp_dest_port(&frame[..len])
         .and_then(|port| lookup_port(port))
         .unwrap_or(NET_DRV_PID);
-    let msg = Message::new(len, 0, dest_pid, NET_FRAME_BUF.as_ptr() as u64);
-    scheduler::push_msg(dest_pid, msg);
+    /* Each message carries its own copy of the frame. */
+    if let Some(msg) = Message::copy_from(0, 0, frame.as_ptr(), len) {
+        scheduler::push_msg(dest_pid, msg);
+    }
 }
 
 /// Called from scheduler_schedule() on every timer tick.
@@ -166,15 +160,14 @@ pub unsafe fn poll_and_deliver() {
         rprintn!(len as u64);
         rprint!("\n");
 
-        core::ptr::copy_nonoverlapping(tmp.as_ptr(), NET_FRAME_BUF.as_mut_ptr(), len);
-
         /* Route TCP frames to the registered port-specific service; all other traffic
          * (ARP, ICMP, unregistered ports) goes to the global Ethernet driver. */
         let dest_pid = tcp_dest_port(&tmp[..len])
             .and_then(|port| lookup_port(port))
             .unwrap_or(NET_DRV_PID);
 
-        let msg = Message::new(len, 0, dest_pid, NET_FRAME_BUF.as_ptr() as u64);
-        scheduler::push_msg(dest_pid, msg);
+        if let Some(msg) = Message::copy_from(0, 0, tmp.as_ptr(), len) {
+            scheduler::push_msg(dest_pid, msg);
+        }
     }
 }
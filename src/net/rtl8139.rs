@@ -1,4 +1,5 @@
 use crate::input::port;
+use crate::mem::frames;
 use crate::net::pci;
 
 pub const PCI_VENDOR_ID_REALTEK: u16 = 0x10EC;
@@ -7,11 +8,16 @@ pub const PCI_DEVICE_ID_RTL8139: u16 = 0x8139;
 pub static mut RTL8139_IO_BASE: u16 = 0xC000; // overwritten by rtl8139_init() from PCI BAR0
 const NUM_TX_BUFFERS: usize = 4;
 
-static mut RX_BUFFER: [u8; 8192 + 16 + 1500] = [0; 8192 + 16 + 1500];
+/// 8 KiB ring plus the 16 bytes and one frame the card may write past its end.
+const RX_BUFFER_SIZE: usize = 8192 + 16 + 1500;
+const TX_BUFFER_SIZE: usize = 2048;
+
+/// Physical addresses of the DMA buffers, taken from the frame allocator on
+/// the first rtl8139_init() and kept (0 = not allocated yet).
+static mut RX_BUFFER: u64 = 0;
 static mut RX_OFFSET: usize = 0;
 
-//#[repr(align(4))]
-static mut TX_BUFFERS: [[u8; 2048]; NUM_TX_BUFFERS] = [[0; 2048]; NUM_TX_BUFFERS];
+static mut TX_BUFFERS: u64 = 0;
 static mut TX_INDEX: usize = 0;
 
 pub fn receive_frame(buf: &mut [u8]) -> Option<usize> {
@@ -29,8 +35,12 @@ pub fn receive_frame(buf: &mut [u8]) -> Option<usize> {
         let offset = RX_OFFSET & 0x1FFF;
         //let rx_buf = &RX_BUFFER[offset..];
 
-        #[expect(static_mut_refs)]
-        if let Some(rx_buf) = RX_BUFFER.get(offset..) {
+        if RX_BUFFER == 0 {
+            return None;
+        }
+        let ring = core::slice::from_raw_parts(RX_BUFFER as *const u8, RX_BUFFER_SIZE);
+
+        if let Some(rx_buf) = ring.get(offset..) {
             if rx_buf.len() < 4 {
                 return None;
             }
@@ -68,13 +78,20 @@ pub fn send_frame(data: &[u8], len: usize) -> Result<(), &'static str> {
     const ETH_MIN: usize = 60;
     let send_len = if len < ETH_MIN { ETH_MIN } else { len };
 
-    if send_len > 2048 {
+    if send_len > TX_BUFFER_SIZE {
         return Err("Frame too large");
     }
 
     unsafe {
+        if TX_BUFFERS == 0 {
+            return Err("NIC not initialized");
+        }
+
         let tx_idx = TX_INDEX;
-        let buf = &mut TX_BUFFERS[tx_idx];
+        let buf = core::slice::from_raw_parts_mut(
+            (TX_BUFFERS + (tx_idx * TX_BUFFER_SIZE) as u64) as *mut u8,
+            TX_BUFFER_SIZE,
+        );
         buf[..len].copy_from_slice(&data[..len]);
         if len < ETH_MIN {
             // zero-pad to minimum frame size
@@ -121,6 +138,20 @@ pub fn rtl8139_init() {
 
     let io_base = unsafe { RTL8139_IO_BASE };
 
+    // The card reaches its buffers by DMA with 32-bit addresses.
+    unsafe {
+        if RX_BUFFER == 0 {
+            RX_BUFFER = frames::alloc_dma(RX_BUFFER_SIZE).unwrap_or(0);
+        }
+        if TX_BUFFERS == 0 {
+            TX_BUFFERS = frames::alloc_dma(TX_BUFFER_SIZE * NUM_TX_BUFFERS).unwrap_or(0);
+        }
+        if RX_BUFFER == 0 || TX_BUFFERS == 0 {
+            rprint!("rtl8139: no memory for DMA buffers\n");
+            return;
+        }
+    }
+
     // Reset ring-buffer read pointer so re-launch starts clean
     unsafe { RX_OFFSET = 0; }
 
@@ -132,7 +163,7 @@ pub fn rtl8139_init() {
     while port::read_u8(io_base + 0x37) & 0x10 != 0 {}
 
     // Set receive buffer address
-    let rx_buf_addr = &raw const RX_BUFFER as u32;
+    let rx_buf_addr = unsafe { RX_BUFFER as u32 };
     port::write_u32(io_base + 0x30, rx_buf_addr);
 
     // Enable RX and TX
@@ -2,30 +2,54 @@ use crate::input::port;
 //use x86_64;
 
 pub const COM1: u16 = 0x3F8;
+pub const COM2: u16 = 0x2F8;
 
 pub fn init() {
-    port::write(COM1 + 1, 0x00);    // Disable interrupts
-    port::write(COM1 + 3, 0x80);    // Enable DLAB
-    port::write(COM1, 0x03);        // Set divisor to 3 (38400 baud)
-    port::write(COM1 + 1, 0x00);    // High byte divisor
-    port::write(COM1 + 3, 0x03);    // 8 bits, no parity, one stop bit
-    port::write(COM1 + 2, 0xC7);    // Enable FIFO, clear them, with 14-byte threshold
-    port::write(COM1 + 4, 0x0B);    // IRQs enabled, RTS/DSR set
+    init_port(COM1);
 }
 
 /// Check if a byte is available from UART
 pub fn ready() -> bool {
-    (port::read(COM1 + 5) & 1) != 0
+    ready_on(COM1)
 }
 
 /// Read a byte from UART
 pub fn read() -> u8 {
-    port::read(COM1)
+    read_from(COM1)
 }
 
 /// Write a byte to UART
 pub fn write(b: u8) {
-    while (port::read(COM1 + 5) & 0x20) == 0 {}
-    port::write(COM1, b);
+    write_to(COM1, b);
+}
+
+/// Set up the UART at I/O base `com`, such as COM1 or COM2
+pub fn init_port(com: u16) {
+    port::write(com + 1, 0x00);    // Disable interrupts
+    port::write(com + 3, 0x80);    // Enable DLAB
+    port::write(com, 0x03);        // Set divisor to 3 (38400 baud)
+    port::write(com + 1, 0x00);    // High byte divisor
+    port::write(com + 3, 0x03);    // 8 bits, no parity, one stop bit
+    port::write(com + 2, 0xC7);    // Enable FIFO, clear them, with 14-byte threshold
+    port::write(com + 4, 0x0B);    // IRQs enabled, RTS/DSR set
+}
+
+/// Check that a UART answers at `com` through its scratch register
+pub fn present(com: u16) -> bool {
+    port::write(com + 7, 0xAE);
+    port::read(com + 7) == 0xAE
+}
+
+pub fn ready_on(com: u16) -> bool {
+    (port::read(com + 5) & 1) != 0
+}
+
+pub fn read_from(com: u16) -> u8 {
+    port::read(com)
+}
+
+pub fn write_to(com: u16, b: u8) {
+    while (port::read(com + 5) & 0x20) == 0 {}
+    port::write(com, b);
 }
 
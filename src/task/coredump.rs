@@ -0,0 +1,423 @@
+use core::{mem::size_of, ptr::copy_nonoverlapping, slice};
+
+use crate::{
+    fs::fat12::{block::Floppy, fs::Filesystem},
+    input::elf::{
+        thread_stack, Elf64Ehdr, Elf64Phdr, EM_X86_64, PT_LOAD, USER_STACK_BASE, USER_STACK_TOP,
+    },
+    mem::vm,
+    task::{
+        context::TrapFrame,
+        process::THREAD_STACK_SIZE,
+        scheduler::{self, CoreLayout},
+    },
+};
+
+/// Directory in the FAT12 root holding the core files, one per program name.
+const CORE_DIR: [u8; 11] = *b"CORE       ";
+
+const ET_CORE: u16 = 4;
+const PT_NOTE: u32 = 4;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+/// Type of the "R2" note holding the exception vector, error code and CR2.
+const NT_R2_FAULT: u32 = 1;
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Bytes below the faulting RSP dumped too: the red zone of the SysV ABI.
+const RED_ZONE: u64 = 128;
+
+/// Headers and notes are built here before being written out.
+const HEADER_SIZE: usize = 1024;
+
+#[repr(C)]
+struct Elf64Nhdr {
+    n_namesz: u32,
+    n_descsz: u32,
+    n_type: u32,
+}
+
+/// `struct elf_prstatus` of Linux on x86-64, as gdb expects it.
+#[repr(C)]
+struct PrStatus {
+    si_signo: i32,
+    si_code: i32,
+    si_errno: i32,
+    pr_cursig: i16,
+    _pad0: u16,
+    pr_sigpend: u64,
+    pr_sighold: u64,
+    pr_pid: i32,
+    pr_ppid: i32,
+    pr_pgrp: i32,
+    pr_sid: i32,
+    /// User, system and children's times; not accounted.
+    pr_times: [u64; 8],
+    /// `struct user_regs_struct`.
+    pr_reg: [u64; 27],
+    pr_fpvalid: i32,
+    _pad1: u32,
+}
+
+/// `struct elf_prpsinfo` of Linux on x86-64.
+#[repr(C)]
+struct PrPsInfo {
+    pr_state: u8,
+    pr_sname: u8,
+    pr_zomb: u8,
+    pr_nice: u8,
+    _pad0: u32,
+    pr_flag: u64,
+    pr_uid: u32,
+    pr_gid: u32,
+    pr_pid: i32,
+    pr_ppid: i32,
+    pr_pgrp: i32,
+    pr_sid: i32,
+    pr_fname: [u8; 16],
+    pr_psargs: [u8; 80],
+}
+
+/// A range of user memory dumped as a PT_LOAD segment.
+#[derive(Clone, Copy)]
+struct Segment {
+    start: u64,
+    end: u64,
+    flags: u32,
+}
+
+/// Write an ELF core file of the running user process, killed by `sig`, to
+/// `/mnt/fat/CORE/<NAME>.COR`, replacing the one of an earlier run.  It holds
+/// the registers in `frame`, the exception details and the memory of the
+/// process image and of the faulting thread's stack, so it can be opened with
+/// gdb next to the program's ELF.  Returns false if no complete file could be
+/// written.
+///
+/// Called from the exception handlers with interrupts off, in the address
+/// space of the process.
+pub fn write(frame: &TrapFrame, cr2: u64, sig: u8) -> bool {
+    let Some(layout) = scheduler::core_layout() else {
+        return false;
+    };
+
+    let mut segments = [Segment {
+        start: 0,
+        end: 0,
+        flags: 0,
+    }; 2];
+    let mut count = 0;
+
+    if layout.image_start < layout.image_end {
+        segments[count] = Segment {
+            start: layout.image_start,
+            end: layout.image_end,
+            flags: PF_R | PF_W | PF_X,
+        };
+        count += 1;
+    }
+
+    if let Some(stack) = stack_segment(&layout, frame.rsp) {
+        segments[count] = stack;
+        count += 1;
+    }
+
+    let segments = &segments[..count];
+
+    let mut header = [0u8; HEADER_SIZE];
+    let Some(header_len) = build_header(&mut header, &layout, segments, frame, cr2, sig) else {
+        return false;
+    };
+
+    let floppy = Floppy::init();
+    let Ok(fs) = Filesystem::new(&floppy) else {
+        return false;
+    };
+
+    let dir = match fs.find_entry(0, &CORE_DIR) {
+        Some(entry) => entry,
+        None => {
+            fs.create_subdirectory(&CORE_DIR, 0);
+
+            match fs.find_entry(0, &CORE_DIR) {
+                Some(entry) => entry,
+                None => return false,
+            }
+        }
+    };
+    if dir.attr & 0x10 == 0 {
+        return false;
+    }
+
+    let name = file_name(&layout.name);
+
+    if !fs.truncate_file(dir.start_cluster, &name) {
+        fs.create_file(dir.start_cluster, &name);
+    }
+
+    if fs.write_at(dir.start_cluster, &name, 0, &header[..header_len]) != Some(header_len) {
+        return false;
+    }
+
+    let mut offset = PAGE_SIZE;
+
+    // Page by page through the physical frames: the pages of the process
+    // not touched yet are filled first, and a fault here would land on the
+    // stack of the one being handled.
+    for seg in segments {
+        for page in (seg.start..seg.end).step_by(PAGE_SIZE as usize) {
+            let data = match unsafe { vm::resolve(page) } {
+                Some(phys) => unsafe {
+                    slice::from_raw_parts(phys as *const u8, PAGE_SIZE as usize)
+                },
+                None => &[0u8; PAGE_SIZE as usize],
+            };
+
+            if fs.write_at(dir.start_cluster, &name, offset as u32, data) != Some(data.len()) {
+                return false;
+            }
+
+            offset += PAGE_SIZE;
+        }
+    }
+
+    true
+}
+
+/// The pages of the thread stack in use at `rsp`, up to the top of its slot;
+/// an overflowed stack is cut at its guard page.  None if `rsp` is not in
+/// the user stack frame.
+fn stack_segment(layout: &CoreLayout, rsp: u64) -> Option<Segment> {
+    if !(USER_STACK_BASE..USER_STACK_TOP).contains(&rsp) {
+        return None;
+    }
+
+    let slot = USER_STACK_TOP.saturating_sub(layout.stack_top) / THREAD_STACK_SIZE;
+    let stack = thread_stack(slot);
+    let start = (rsp.saturating_sub(RED_ZONE) & !(PAGE_SIZE - 1)).max(stack.start);
+    let end = stack.end;
+
+    if start >= end {
+        return None;
+    }
+
+    Some(Segment {
+        start,
+        end,
+        flags: PF_R | PF_W,
+    })
+}
+
+/// `<NAME>.COR`, NAME being the process name up to its extension.
+fn file_name(process_name: &[u8; 16]) -> [u8; 11] {
+    let mut name = [b' '; 11];
+
+    let base = process_name
+        .split(|&b| b == b'.' || b == b' ' || b == 0)
+        .next()
+        .filter(|base| !base.is_empty())
+        .unwrap_or(b"CORE");
+
+    for (dst, src) in name.iter_mut().zip(base.iter().take(8)) {
+        *dst = src.to_ascii_uppercase();
+    }
+    name[8..].copy_from_slice(b"COR");
+
+    name
+}
+
+/// Lay out the ELF header, the program headers and the notes in `buf`; the
+/// segments follow from the next page on.  Returns the length used.
+fn build_header(
+    buf: &mut [u8],
+    layout: &CoreLayout,
+    segments: &[Segment],
+    frame: &TrapFrame,
+    cr2: u64,
+    sig: u8,
+) -> Option<usize> {
+    let phnum = segments.len() + 1;
+    let notes_offset = size_of::<Elf64Ehdr>() + phnum * size_of::<Elf64Phdr>();
+
+    let mut e_ident = [0u8; 16];
+    e_ident[..7].copy_from_slice(b"\x7FELF\x02\x01\x01");
+
+    let ehdr = Elf64Ehdr {
+        e_ident,
+        e_type: ET_CORE,
+        e_machine: EM_X86_64,
+        e_version: 1,
+        e_entry: 0,
+        e_phoff: size_of::<Elf64Ehdr>() as u64,
+        e_shoff: 0,
+        e_flags: 0,
+        e_ehsize: size_of::<Elf64Ehdr>() as u16,
+        e_phentsize: size_of::<Elf64Phdr>() as u16,
+        e_phnum: phnum as u16,
+        e_shentsize: 0,
+        e_shnum: 0,
+        e_shstrndx: 0,
+    };
+
+    let reg = [
+        frame.r15,
+        frame.r14,
+        frame.r13,
+        frame.r12,
+        frame.rbp,
+        frame.rbx,
+        frame.r11,
+        frame.r10,
+        frame.r9,
+        frame.r8,
+        frame.rax,
+        frame.rcx,
+        frame.rdx,
+        frame.rsi,
+        frame.rdi,
+        u64::MAX, // orig_rax: not in a syscall
+        frame.rip,
+        frame.cs,
+        frame.rflags,
+        frame.rsp,
+        frame.ss,
+        0, // fs_base
+        0, // gs_base
+        0, // ds
+        0, // es
+        0, // fs
+        0, // gs
+    ];
+
+    let status = PrStatus {
+        si_signo: sig as i32,
+        si_code: 0,
+        si_errno: 0,
+        pr_cursig: sig as i16,
+        _pad0: 0,
+        pr_sigpend: 0,
+        pr_sighold: 0,
+        pr_pid: layout.pid as i32,
+        pr_ppid: layout.parent as i32,
+        pr_pgrp: 0,
+        pr_sid: 0,
+        pr_times: [0; 8],
+        pr_reg: reg,
+        pr_fpvalid: 0,
+        _pad1: 0,
+    };
+
+    let mut fname = [0u8; 16];
+    let mut psargs = [0u8; 80];
+    let name_len = layout
+        .name
+        .iter()
+        .position(|&b| b == b' ' || b == 0)
+        .unwrap_or(layout.name.len());
+
+    fname[..name_len].copy_from_slice(&layout.name[..name_len]);
+    psargs[..name_len].copy_from_slice(&layout.name[..name_len]);
+
+    let info = PrPsInfo {
+        pr_state: 0,
+        pr_sname: b'R',
+        pr_zomb: 0,
+        pr_nice: 0,
+        _pad0: 0,
+        pr_flag: 0,
+        pr_uid: 0,
+        pr_gid: 0,
+        pr_pid: layout.pid as i32,
+        pr_ppid: layout.parent as i32,
+        pr_pgrp: 0,
+        pr_sid: 0,
+        pr_fname: fname,
+        pr_psargs: psargs,
+    };
+
+    let fault = [frame.vector, frame.error_code, cr2];
+
+    // Notes first, so their size is known for the PT_NOTE header.
+    let mut off = notes_offset;
+    put_note(buf, &mut off, b"CORE\0", NT_PRSTATUS, &status)?;
+    put_note(buf, &mut off, b"CORE\0", NT_PRPSINFO, &info)?;
+    put_note(buf, &mut off, b"R2\0", NT_R2_FAULT, &fault)?;
+    let len = off;
+
+    off = 0;
+    put(buf, &mut off, &ehdr)?;
+    put(
+        buf,
+        &mut off,
+        &Elf64Phdr {
+            p_type: PT_NOTE,
+            p_flags: 0,
+            p_offset: notes_offset as u64,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: (len - notes_offset) as u64,
+            p_memsz: 0,
+            p_align: 4,
+        },
+    )?;
+
+    let mut file_offset = PAGE_SIZE;
+
+    for seg in segments {
+        put(
+            buf,
+            &mut off,
+            &Elf64Phdr {
+                p_type: PT_LOAD,
+                p_flags: seg.flags,
+                p_offset: file_offset,
+                p_vaddr: seg.start,
+                p_paddr: 0,
+                p_filesz: seg.end - seg.start,
+                p_memsz: seg.end - seg.start,
+                p_align: PAGE_SIZE,
+            },
+        )?;
+
+        file_offset += seg.end - seg.start;
+    }
+
+    Some(len)
+}
+
+/// Append an ELF note; its name and descriptor are padded to 4 bytes.
+fn put_note<T>(buf: &mut [u8], off: &mut usize, name: &[u8], kind: u32, desc: &T) -> Option<()> {
+    let nhdr = Elf64Nhdr {
+        n_namesz: name.len() as u32,
+        n_descsz: size_of::<T>() as u32,
+        n_type: kind,
+    };
+
+    put(buf, off, &nhdr)?;
+
+    buf.get_mut(*off..*off + name.len())?.copy_from_slice(name);
+    *off += name.len().div_ceil(4) * 4;
+
+    put(buf, off, desc)?;
+    *off = off.div_ceil(4) * 4;
+
+    (*off <= buf.len()).then_some(())
+}
+
+/// Copy the bytes of `value` to `buf` at `off`.  Only used with structs that
+/// have no implicit padding.
+fn put<T>(buf: &mut [u8], off: &mut usize, value: &T) -> Option<()> {
+    let dst = buf.get_mut(*off..*off + size_of::<T>())?;
+
+    unsafe {
+        copy_nonoverlapping(value as *const T as *const u8, dst.as_mut_ptr(), dst.len());
+    }
+    *off += size_of::<T>();
+
+    Some(())
+}
@@ -1,8 +1,12 @@
 pub mod context;
+pub mod coredump;
 pub mod pipe;
+pub mod poll;
 pub mod process;
 pub mod queue;
 pub mod scheduler;
+pub mod service;
+pub mod signal;
 
 /*pub struct Task {
     pub regs: context::Context,
@@ -1,47 +1,87 @@
-const MAX_BUFFER_SIZE: usize = 14096;
+use alloc::vec::Vec;
 
-#[derive(Copy,Clone)]
-#[repr(C, packed)]
+/// Bytes a pipe can hold before its writers block.
+pub const PIPE_SIZE: usize = 4096;
+
+/// Pipes that can exist at once across the system.
+pub const MAX_PIPES: usize = 32;
+
+/// A byte ring buffer connecting the standard output of one process to the
+/// standard input of another.  The scheduler owns all pipes and counts the
+/// processes attached to either end: readers see EOF once the last writer is
+/// gone, writers get BrokenPipe once the last reader is.
 pub struct Pipe {
-    buffer: [u8; MAX_BUFFER_SIZE],
-    id: u64,
+    buffer: Vec<u8>,
+    pub id: u64,
     pub read_pos: usize,
     pub write_pos: usize,
+    /// Bytes buffered and not read yet.
+    pub len: usize,
+    /// Processes with this pipe as standard input.
+    pub readers: usize,
+    /// Processes with this pipe as standard output.
+    pub writers: usize,
 }
 
 impl Pipe {
-    pub fn new(id: u64) -> Self {
-        Pipe{
-            buffer: [0u8; MAX_BUFFER_SIZE],
+    /// Returns None if the kernel heap cannot hold the buffer.
+    pub fn new(id: u64) -> Option<Self> {
+        let mut buffer = Vec::new();
+        buffer.try_reserve_exact(PIPE_SIZE).ok()?;
+        buffer.resize(PIPE_SIZE, 0);
+
+        Some(Pipe {
+            buffer,
             id,
             read_pos: 0,
             write_pos: 0,
-        }
+            len: 0,
+            readers: 0,
+            writers: 0,
+        })
     }
 
-    pub fn read(&mut self) -> u8 {
-        if self.read_pos == self.write_pos {
-            return 0x00;
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Move up to `out.len()` buffered bytes into `out`; returns how many.
+    pub fn read(&mut self, out: &mut [u8]) -> usize {
+        let mut count = 0;
+
+        for b in out.iter_mut() {
+            if self.len == 0 {
+                break;
+            }
+
+            *b = self.buffer[self.read_pos];
+
+            self.read_pos += 1;
+            self.read_pos %= PIPE_SIZE;
+            self.len -= 1;
+            count += 1;
         }
 
-        let output = self.buffer[self.read_pos];
+        count
+    }
 
-        self.read_pos += 1;
-        self.read_pos %= MAX_BUFFER_SIZE;
+    /// Buffer as many bytes of `data` as fit; returns how many.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let mut count = 0;
 
-        /*if self.read_pos < self.write_pos {
-            if let Some(buf) = self.buffer.get(self.read_pos..self.write_pos) {
-                output.copy_from_slice(buf);
+        for &b in data {
+            if self.len == PIPE_SIZE {
+                break;
             }
-        }*/
 
-        output
-    }
+            self.buffer[self.write_pos] = b;
 
-    pub fn write(&mut self, ch: u8) {
-        self.buffer[self.write_pos] = ch;
+            self.write_pos += 1;
+            self.write_pos %= PIPE_SIZE;
+            self.len += 1;
+            count += 1;
+        }
 
-        self.write_pos += 1;
-        self.write_pos %= MAX_BUFFER_SIZE;
+        count
     }
 }
@@ -0,0 +1,89 @@
+use crate::{
+    input::{irq, keyboard, mouse},
+    net::serial,
+};
+
+/// Sources a single poll call may wait on.
+pub const MAX_POLL_SOURCES: usize = 16;
+
+/// Source kinds.  The ID of a port source is the port ID, that of a file
+/// descriptor source the descriptor; the other kinds ignore it.
+pub const POLL_PORT: u64 = 0;
+pub const POLL_KEYBOARD: u64 = 1;
+pub const POLL_MOUSE: u64 = 2;
+pub const POLL_SERIAL: u64 = 3;
+pub const POLL_FD: u64 = 4;
+
+/// Data can be read without blocking.
+pub const POLL_IN: u64 = 0x01;
+/// Data can be written without blocking.
+pub const POLL_OUT: u64 = 0x02;
+/// The other end of a pipe is gone.  Reported even if not asked for.
+pub const POLL_HUP: u64 = 0x04;
+/// The source does not exist or cannot be used.  Reported even if not asked for.
+pub const POLL_ERR: u64 = 0x08;
+
+/// One event source of syscall 0x5C.  The caller sets `kind`, `id` and the
+/// `events` it waits for; the kernel sets `revents` to those that happened.
+#[derive(Clone, Copy)]
+#[repr(C, packed)]
+pub struct PollSource {
+    pub kind: u64,
+    pub id: u64,
+    pub events: u64,
+    pub revents: u64,
+}
+
+impl PollSource {
+    pub const EMPTY: PollSource = PollSource {
+        kind: 0,
+        id: 0,
+        events: 0,
+        revents: 0,
+    };
+}
+
+pub fn is_valid_kind(kind: u64) -> bool {
+    kind <= POLL_FD
+}
+
+/// Bit of `kind` in `Process::polling` and in the masks passed to
+/// `scheduler::wake_pollers`.
+pub fn kind_bit(kind: u64) -> u64 {
+    1 << kind
+}
+
+/// Readiness of the device sources, which need no scheduler state: the
+/// keyboard pipe and mouse subscriptions of `pid` and the COM1 receiver.
+pub fn device_events(kind: u64, pid: usize) -> u64 {
+    match kind {
+        POLL_KEYBOARD => unsafe {
+            #[expect(static_mut_refs)]
+            match irq::RECEPTORS.iter().find(|s| s.pid == pid) {
+                Some(s) if s.available() > 0 => POLL_IN,
+                Some(_) => 0,
+                None => POLL_ERR,
+            }
+        },
+        POLL_MOUSE => unsafe {
+            #[expect(static_mut_refs)]
+            match mouse::MOUSE_RECEPTORS.iter().find(|s| s.pid == pid) {
+                Some(s) if s.available() > 0 => POLL_IN,
+                Some(_) => 0,
+                None => POLL_ERR,
+            }
+        },
+        POLL_SERIAL if serial::ready() => POLL_IN | POLL_OUT,
+        POLL_SERIAL => POLL_OUT,
+        _ => POLL_ERR,
+    }
+}
+
+/// Readiness of standard input when it is not a pipe: a key is waiting.
+pub fn console_events() -> u64 {
+    if keyboard::scancode_pending() {
+        POLL_IN
+    } else {
+        0
+    }
+}
@@ -1,6 +1,21 @@
+use alloc::{
+    alloc::{alloc_zeroed, Layout},
+    vec,
+    vec::Vec,
+};
+
+use crate::abi::strace::Trace;
+use crate::fs::file::File;
+use crate::mem::{
+    frames::FRAME_SIZE,
+    kheap::{KHEAP_END, KHEAP_START},
+    pages,
+};
 use crate::task::{
     context::Context,
-    queue::{Message, Queue},
+    queue::{PendingCall, Queue},
+    service::NAME_LEN,
+    signal::{SigAction, NSIG},
 };
 
 #[derive(Debug, Clone, Copy)]
@@ -16,20 +31,26 @@ pub enum Status {
     Ready,
     Running,
     Blocked,
-    Crashed,
     Idle,
     Dead,
 }
 
+/// An IPC port.  Port IDs are unique across the system and never reused.
 #[derive(Debug)]
-#[repr(C)]
 pub struct Port {
     pub id: usize,
-    pub block_msg: Option<Message>,
-    pub bind: bool,
     pub queue: Queue,
 }
 
+impl Port {
+    pub fn new(id: usize) -> Port {
+        Port {
+            id,
+            queue: Queue::new(),
+        }
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct Process {
@@ -39,67 +60,224 @@ pub struct Process {
     pub status: Status,
     pub last_rsp: u64,
     pub kernel_stack: &'static [u8; STACK_SIZE],
-    pub ports: [Port; 1],
+    /// IPC ports owned by this process.  The first one is its default port,
+    /// addressed by PID for the legacy send/receive syscalls and network
+    /// delivery; the scheduler gives it an ID in `new_process`.
+    pub ports: Vec<Port>,
     pub stack_top: u64,
     /// Physical address of this process's P4 page table, or 0 for kernel processes
     /// (which reuse the boot-time KERNEL_CR3).
     pub cr3: u64,
     /// PIT tick at which a sleeping process should be woken (0 = not sleeping).
     pub sleep_until: u64,
+    /// Base priority, 0 (highest) to PRIORITY_LEVELS - 1.  The process enters
+    /// the feedback queue at this level and is boosted back to it periodically.
+    pub priority: u8,
+    /// Current feedback queue level; never better than `priority`.
+    pub level: u8,
+    /// PIT ticks consumed at `level` so far.
+    pub quantum_used: u8,
+    /// PID of the process that spawned this one (0xff = none).  For a thread,
+    /// the PID of its process.
+    pub parent: usize,
+    /// PID of the process whose address space this runs in: its own PID for
+    /// a process, the main thread's for a thread.
+    pub group: usize,
+    /// Code passed to syscall 0x00, reported to the parent once reaped.
+    pub exit_code: u64,
+    /// Child this process is blocked waiting for (0xff = any child).
+    pub wait_for: Option<usize>,
+    /// Signals sent but not delivered yet, one bit per signal number.
+    pub pending: u32,
+    /// Signals held back from delivery, such as the one whose handler runs.
+    pub mask: u32,
+    /// Action taken for each signal number.  Only the main thread's are
+    /// used; the other threads of the process share them.
+    pub actions: [SigAction; NSIG],
+    /// PIT tick at which SIGALRM is sent (0 = no alarm).
+    pub alarm_at: u64,
+    /// Physical address of the futex word this process is blocked on (0 = none).
+    pub futex: u64,
+    /// PIT ticks this process was running when the timer fired.
+    pub cpu_ticks: u64,
+    /// PIT ticks this process spent Blocked.
+    pub blocked_ticks: u64,
+    /// Number of times the scheduler switched to this process.
+    pub switches: u64,
+    /// Port this process is blocked receiving on.
+    pub recv_port: Option<usize>,
+    /// Call sent by this process and not answered yet.
+    pub call: Option<PendingCall>,
+    /// Reply to `call`, kept until the caller runs again.
+    pub reply: Option<Vec<u8>>,
+    /// Service name this process is blocked waiting to be registered.
+    pub service_wait: Option<[u8; NAME_LEN]>,
+    /// Pipe read by syscall 0x19, or None for the keyboard.
+    pub stdin: Option<u64>,
+    /// Pipe written by syscall 0x10, or None for the screen.
+    pub stdout: Option<u64>,
+    /// Pipe this process is blocked reading from or writing to.
+    pub pipe_wait: Option<u64>,
+    /// Kinds of the sources this process is blocked polling, one bit per
+    /// kind (0 = not polling).
+    pub polling: u64,
+    /// Open files indexed by descriptor, starting with the console streams.
+    /// Threads use the table of their process.
+    pub files: Vec<Option<File>>,
+    /// Page-aligned extent of the loaded ELF segments, written to the core
+    /// file if the process faults (0..0 for kernel processes and threads).
+    pub image_start: u64,
+    pub image_end: u64,
+    /// Where this process's syscalls are logged, None if it is not traced.
+    pub trace: Option<Trace>,
 }
 
-const STACK_SIZE: usize = 32768;
-pub const MAX_PROCESSES: usize = 10;
-
-static mut KSTACK_POOL: [[u8; STACK_SIZE]; MAX_PROCESSES] = [
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-    [0; STACK_SIZE],
-];
+pub const STACK_SIZE: usize = 32768;
+
+/// Unmapped bytes around every stack: below and above each kernel stack, and
+/// at the bottom of each thread stack slot.  Touching them is a stack
+/// overflow rather than a write into the neighbouring stack.
+pub const GUARD_SIZE: u64 = 0x1000;
+
+/// Threads per process, including the main thread.  Each one gets a slot of
+/// THREAD_STACK_SIZE bytes of the process's user stack area; the main thread
+/// runs on the topmost slot.
+pub const MAX_THREADS: u64 = 8;
+
+/// Only the pages of a slot a thread touches are backed by memory.  Its
+/// lowest GUARD_SIZE bytes are a guard and never are.
+pub const THREAD_STACK_SIZE: u64 = 0x80_0000;
+
+/// Ports per process, including the default port.
+pub const MAX_PORTS: usize = 8;
+
+/// Number of feedback queue levels.
+pub const PRIORITY_LEVELS: u8 = 8;
+
+/// Priority given to processes that do not ask for one.
+pub const DEFAULT_PRIORITY: u8 = 4;
+
+/// Exit code reported for a process that lost its user stack, like a shell
+/// reports SIGSEGV.
+pub const EXIT_CRASHED: u64 = 139;
+
+/// Length of a time slice at the given level, in PIT ticks.  Better levels get
+/// shorter slices, so interactive processes are picked often but briefly.
+pub fn quantum(level: u8) -> u8 {
+    level + 1
+}
+
+/// Carve a fresh, zeroed kernel stack out of the kernel heap, between two
+/// guard pages that are unmapped for good.  The scheduler keeps stacks of
+/// exited processes in its own pool and only calls this when that pool is
+/// empty, so stacks are never returned to the heap.
+pub fn alloc_kernel_stack() -> Option<&'static [u8; STACK_SIZE]> {
+    let guard = GUARD_SIZE as usize;
+    let layout = Layout::from_size_align(guard + STACK_SIZE + guard, guard).ok()?;
+
+    unsafe {
+        let base = alloc_zeroed(layout);
+        if base.is_null() {
+            return None;
+        }
+
+        let top = base as u64 + GUARD_SIZE + STACK_SIZE as u64;
+        for off in (0..GUARD_SIZE).step_by(FRAME_SIZE as usize) {
+            pages::unmap_kernel_page(base as u64 + off);
+            pages::unmap_kernel_page(top + off);
+        }
+
+        (base.add(guard) as *const [u8; STACK_SIZE]).as_ref()
+    }
+}
+
+/// True if `addr` lies in a guard page of a kernel stack.  Those are the only
+/// holes in the kernel heap.
+pub fn is_kernel_stack_guard(addr: u64) -> bool {
+    (KHEAP_START..KHEAP_END).contains(&addr)
+        && unsafe { pages::translate_in(pages::read_cr3() as u64, addr).is_none() }
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Ready => "Ready",
+            Status::Running => "Running",
+            Status::Blocked => "Blocked",
+            Status::Idle => "Idle",
+            Status::Dead => "Dead",
+        }
+    }
+}
+
+/// What a new process is started with, as handed to `new_process`.
+#[derive(Debug, Clone, Copy)]
+pub struct SpawnOptions {
+    pub name: [u8; 16],
+    pub mode: Mode,
+    /// Entry point, entered through the initial iretq frame.
+    pub entry: u64,
+    /// User stack top; unused by kernel processes, which run on their
+    /// kernel stack.
+    pub stack_top: u64,
+    /// Page tables of the process, or 0 for the kernel's.
+    pub cr3: u64,
+    pub priority: u8,
+    /// Page-aligned extent of the loaded ELF segments (0..0 if none), set
+    /// before the process can first be scheduled.
+    pub image_start: u64,
+    pub image_end: u64,
+}
 
 impl Process {
-    /// `slot` is the index in the scheduler's `processes` array (0–4).  It is
-    /// used to select a kernel stack from `KSTACK_POOL` so that each occupied
-    /// slot always owns a distinct stack regardless of how many times that slot
-    /// has been recycled (i.e. regardless of the monotonically-increasing PID).
+    /// `kernel_stack` is taken from the scheduler's kernel-stack pool and is
+    /// owned by this process until it is reaped.
     pub fn new(
         id: usize,
-        slot: usize,
-        name_slice: [u8; 16],
-        mode: Mode,
-        _entry_point: u64,
-        process_stack_top: u64,
-        cr3: u64,
+        kernel_stack: &'static [u8; STACK_SIZE],
+        parent: usize,
+        opts: &SpawnOptions,
     ) -> Process {
-        let mut name: [u8; 16] = [0; 16];
-
-        if let Some(slice) = name.get_mut(..name_slice.len()) {
-            (*slice)[..name_slice.len()].copy_from_slice(&name_slice);
-        }
+        let priority = opts.priority.min(PRIORITY_LEVELS - 1);
 
         Process {
             id,
-            name,
-            mode,
+            name: opts.name,
+            mode: opts.mode,
             status: Status::Ready,
             last_rsp: 0,
-            stack_top: process_stack_top,
-            kernel_stack: unsafe { &KSTACK_POOL[slot % MAX_PROCESSES] },
-            cr3,
+            stack_top: opts.stack_top,
+            kernel_stack,
+            cr3: opts.cr3,
             sleep_until: 0,
-            ports: [Port {
-                id: 0,
-                block_msg: None,
-                bind: false,
-                queue: Queue::new(),
-            }],
+            priority,
+            level: priority,
+            quantum_used: 0,
+            parent,
+            group: id,
+            exit_code: 0,
+            wait_for: None,
+            pending: 0,
+            mask: 0,
+            actions: [SigAction::DEFAULT; NSIG],
+            alarm_at: 0,
+            futex: 0,
+            cpu_ticks: 0,
+            blocked_ticks: 0,
+            switches: 0,
+            ports: vec![Port::new(0)],
+            recv_port: None,
+            call: None,
+            reply: None,
+            service_wait: None,
+            stdin: None,
+            stdout: None,
+            pipe_wait: None,
+            polling: 0,
+            files: vec![Some(File::STDIN), Some(File::STDOUT), Some(File::STDERR)],
+            image_start: opts.image_start,
+            image_end: opts.image_end,
+            trace: None,
         }
     }
 
@@ -1,25 +1,57 @@
+use alloc::vec::Vec;
+
 const QUEUE_MSG_COUNT_MAX: usize = 10;
 
-#[derive(Debug, Clone, Copy)]
+/// Largest payload a message can carry; fits a full Ethernet frame.
+pub const MAX_MSG_SIZE: usize = 4096;
+
+#[derive(Debug, Clone)]
 pub struct Message {
+    /// Port the message was sent to.
     pub port_id: usize,
     pub src_pid: usize,
-    pub dst_pid: usize,
-    pub buf_addr: u64,
+    /// Reply token of a call, 0 for a plain send.
+    pub token: u64,
+    /// Payload, copied out of the sender at send time and owned by the kernel
+    /// until the message is received.
+    pub data: Vec<u8>,
 }
 
 impl Message {
-    pub fn new(port_id: usize, src_pid: usize, dst_pid: usize, buf_addr: u64) -> Self {
+    pub fn new(port_id: usize, src_pid: usize, data: Vec<u8>) -> Self {
         Self {
             port_id,
             src_pid,
-            dst_pid,
-            buf_addr,
+            token: 0,
+            data,
         }
     }
+
+    /// Copy `len` bytes at `src` into a new message.  None if `len` is over
+    /// MAX_MSG_SIZE or the kernel heap is exhausted.
+    pub unsafe fn copy_from(port_id: usize, src_pid: usize, src: *const u8, len: usize) -> Option<Self> {
+        if len > MAX_MSG_SIZE {
+            return None;
+        }
+
+        let mut data = Vec::new();
+        data.try_reserve_exact(len).ok()?;
+        data.extend_from_slice(core::slice::from_raw_parts(src, len));
+
+        Some(Self::new(port_id, src_pid, data))
+    }
 }
 
+/// A call waiting for its reply.
 #[derive(Debug, Clone, Copy)]
+pub struct PendingCall {
+    pub token: u64,
+    /// PID of the process owning the port the call was sent to; only its
+    /// threads may reply.
+    pub server: usize,
+}
+
+#[derive(Debug)]
 pub struct Queue {
     buffer: [Option<Message>; QUEUE_MSG_COUNT_MAX],
     head: usize,
@@ -30,13 +62,17 @@ pub struct Queue {
 impl Queue {
     pub fn new() -> Queue {
         Queue {
-            buffer: [None; QUEUE_MSG_COUNT_MAX],
+            buffer: [const { None }; QUEUE_MSG_COUNT_MAX],
             head: 0,
             tail: 0,
             msg_count: 0,
         }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.msg_count == 0
+    }
+
     pub fn push(&mut self, msg: Message) -> bool {
         if self.msg_count == QUEUE_MSG_COUNT_MAX {
             // The queue is full, or blocked
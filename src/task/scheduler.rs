@@ -1,21 +1,163 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
 use spin::Mutex;
 
 use super::{
-    process::{Mode, Process, Status, MAX_PROCESSES},
-    queue::Message,
+    context::InterruptFrame,
+    pipe::{Pipe, MAX_PIPES, PIPE_SIZE},
+    poll::{
+        self, PollSource, POLL_ERR, POLL_FD, POLL_HUP, POLL_IN, POLL_OUT, POLL_PORT, POLL_SERIAL,
+    },
+    process::{
+        self, Mode, Port, Process, SpawnOptions, Status, EXIT_CRASHED, MAX_PORTS, MAX_THREADS,
+        PRIORITY_LEVELS, STACK_SIZE, THREAD_STACK_SIZE,
+    },
+    queue::{Message, PendingCall},
+    service::{Service, MAX_SERVICES, NAME_LEN},
+    signal::{self, SigAction, SignalFrame, SIGALRM, SIGCHLD, SIGKILL, SIG_DFL, SIG_IGN},
 };
+use crate::abi::strace::Trace;
+use crate::fs::file::{File, Node, MAX_FILES, O_READ, O_WRITE};
+use crate::input::elf::{thread_stack, USER_STACK_BASE, USER_STACK_TOP};
 
 static SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
 
-/// PID of the kernel shell process.  Set once when the shell is first spawned
-/// and read by exception handlers so they wake the right process on crash.
+/// Poll source kinds whose pollers an IRQ handler could not wake because the
+/// scheduler was locked; the next PIT tick wakes them instead.
+static POLL_WAKE: AtomicU64 = AtomicU64::new(0);
+
+/// PID of the kernel shell process.  Set once when the shell is first spawned.
 static mut SHELL_PID: usize = 0;
 
+/// Reserved as "current process" / "no process" by the free functions below,
+/// so it is never handed out as a real PID.
+const RESERVED_PID: usize = 0xff;
+
+/// Every BOOST_INTERVAL PIT ticks (1 s) all processes are moved back to their
+/// base priority level, so CPU-bound processes sunk to the bottom of the
+/// feedback queue cannot be starved forever.
+const BOOST_INTERVAL: u64 = 100;
+
+/// Exit statuses nobody has waited for yet.  The oldest one is dropped once
+/// the list is full, so parents that never wait cannot exhaust the heap.
+const MAX_EXIT_RECORDS: usize = 32;
+
+/// Code segment selector of ring 3; a saved frame with it returns to user code.
+pub const USER_CS: u64 = 0x23;
+
+/// Stack segment selector of ring 3.  SYSRET loads it from STAR as the entry
+/// right below USER_CS, see init::cpu.
+pub const USER_SS: u64 = 0x1b;
+
+/// Bytes below a user RSP that leaf functions may use without moving RSP
+/// (the SysV red zone); signal frames are pushed below it.
+const RED_ZONE: u64 = 128;
+
+/// RFLAGS bits sigreturn lets user code restore: the status flags, TF and DF.
+pub const RFLAGS_USER: u64 = 0xDD5;
+
+/// CPU accounting of one process, as shown by `top`.
+#[derive(Debug, Clone, Copy)]
+pub struct TaskStats {
+    pub pid: usize,
+    pub name: [u8; 16],
+    pub status: Status,
+    pub cpu_ticks: u64,
+    pub blocked_ticks: u64,
+    pub switches: u64,
+}
+
+/// What the core file of a user process is made of.  For a thread, the name
+/// and image are those of its process.
+#[derive(Debug, Clone, Copy)]
+pub struct CoreLayout {
+    pub pid: usize,
+    pub parent: usize,
+    pub name: [u8; 16],
+    pub image_start: u64,
+    pub image_end: u64,
+    pub stack_top: u64,
+}
+
+/// A scheduler entry as the GDB stub reports it, one thread per entry.
+#[derive(Debug, Clone, Copy)]
+pub struct DebugThread {
+    pub pid: usize,
+    pub name: [u8; 16],
+    pub status: Status,
+    /// Page table of the entry, 0 for kernel processes.
+    pub cr3: u64,
+    /// Saved InterruptFrame of the entry, unless it is the running one.
+    pub last_rsp: u64,
+    pub running: bool,
+}
+
+impl DebugThread {
+    pub const EMPTY: DebugThread = DebugThread {
+        pid: 0,
+        name: [0; 16],
+        status: Status::Dead,
+        cr3: 0,
+        last_rsp: 0,
+        running: false,
+    };
+}
+
+/// Outcome of a futex wait.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FutexWait {
+    /// Woken by `futex_wake`, or early by a signal.
+    Woken,
+    /// The futex word no longer held the expected value; nothing was waited for.
+    Mismatch,
+    TimedOut,
+}
+
+/// Why an IPC operation failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpcError {
+    /// No such port, or the port belongs to another process.
+    NoPort,
+    /// The port already holds as many messages as it can queue.
+    QueueFull,
+    /// The server exited, or dropped the call, without replying.
+    PeerGone,
+    /// A signal is waiting to be handled.
+    Interrupted,
+}
+
+/// Exit status of a reaped process, kept until its parent collects it.  For a
+/// thread, `parent` is its process and the record is collected by a join.
+struct ExitRecord {
+    pid: usize,
+    parent: usize,
+    code: u64,
+    thread: bool,
+}
+
 #[repr(C)]
 pub struct Scheduler {
-    processes: [Option<Process>; MAX_PROCESSES],
-    current_pid: usize,
+    /// Process table.  Grows on demand; a slot freed by a reaped process is
+    /// reused by the next `new_process`, so slot indices are not PIDs.
+    processes: Vec<Option<Process>>,
+    /// Kernel stacks of reaped processes, reused before the heap is asked for more.
+    kstack_pool: Vec<&'static [u8; STACK_SIZE]>,
+    /// Slot index of the running process.
+    current: usize,
     next_free_pid: usize,
+    /// PIT tick of the last priority boost.
+    last_boost: u64,
+    exited: Vec<ExitRecord>,
+    /// ID of the next IPC port created; port IDs are never reused.
+    next_port_id: usize,
+    /// Reply token of the next call.
+    next_token: u64,
+    /// Name service registry.
+    services: Vec<Service>,
+    /// Pipes with a process attached to at least one end.
+    pipes: Vec<Pipe>,
+    /// ID of the next pipe created; pipe IDs are never reused.
+    next_pipe_id: u64,
 }
 
 extern "C" {
@@ -25,61 +167,75 @@ extern "C" {
 impl Scheduler {
     const fn new() -> Self {
         Self {
-            processes: [None, None, None, None, None, None, None, None, None, None],
-            current_pid: 0,
+            processes: Vec::new(),
+            kstack_pool: Vec::new(),
+            current: 0,
             next_free_pid: 0,
+            last_boost: 0,
+            exited: Vec::new(),
+            next_port_id: 1,
+            next_token: 1,
+            services: Vec::new(),
+            pipes: Vec::new(),
+            next_pipe_id: 1,
         }
     }
 
     pub unsafe fn schedule(&mut self, old: *mut u64) -> *mut u64 {
-        // Wake any processes whose timed sleep has expired.
-        let now = crate::time::acpi::get_tick_count();
-        for slot in self.processes.iter_mut() {
-            if let Some(proc) = slot {
-                if proc.sleep_until != 0
-                    && proc.status == Status::Blocked
-                    && now >= proc.sleep_until
-                {
-                    proc.status = Status::Ready;
-                    proc.sleep_until = 0;
-                }
-            }
-        }
+        // Charge the elapsed tick before anyone is woken, so a process that
+        // went to sleep is seen as blocked rather than as still running.
+        let expired = self.charge_current();
 
-        let mut next = self.current_pid;
-        let start = next;
+        // Wake any processes whose timed sleep has expired, and raise due alarms.
+        let now = crate::time::acpi::get_tick_count();
+        for slot in 0..self.processes.len() {
+            let Some(proc) = self.processes[slot].as_mut() else {
+                continue;
+            };
 
-        loop {
-            next = (next + 1) % self.processes.len();
+            if proc.status == Status::Blocked {
+                proc.blocked_ticks += 1;
+            }
 
-            if next == start {
-                return old;
+            if proc.sleep_until != 0
+                && proc.status == Status::Blocked
+                && now >= proc.sleep_until
+            {
+                proc.status = Status::Ready;
+                proc.sleep_until = 0;
             }
 
-            let next_proc = self.processes[next].as_ref();
+            if proc.alarm_at != 0 && now >= proc.alarm_at {
+                proc.alarm_at = 0;
 
-            if next_proc.is_none() {
-                continue;
+                let pid = proc.id;
+                self.signal(pid, SIGALRM);
             }
+        }
 
-            if let Some(proc) = next_proc {
-                if proc.status == Status::Dead {
-                    self.processes[next].take();
-                    continue;
-                }
+        // Serial input raises no IRQ, so its pollers are checked every tick.
+        let mut wake = POLL_WAKE.swap(0, Ordering::Acquire);
+        if crate::net::serial::ready() {
+            wake |= poll::kind_bit(POLL_SERIAL);
+        }
+        if wake != 0 {
+            self.wake_pollers(wake);
+        }
 
-                if matches!(
-                    proc.status,
-                    Status::Blocked | Status::Crashed | Status::Idle
-                ) {
-                    continue;
-                }
+        if self.processes.is_empty() {
+            return old;
+        }
 
-                break;
-            }
+        if now.wrapping_sub(self.last_boost) >= BOOST_INTERVAL {
+            self.boost(now);
         }
 
-        let curr_proc = self.processes[self.current_pid].as_mut().unwrap();
+        let next = match self.pick_next(expired) {
+            Some(next) if next != self.current => next,
+            _ => return old,
+        };
+
+        let curr_proc = self.processes[self.current].as_mut().unwrap();
 
         // Save the current process' context
         //self.processes[self.current_pid].as_mut().unwrap().context = *old;
@@ -89,21 +245,24 @@ impl Scheduler {
 
         if !matches!(
             curr_proc.status,
-            Status::Blocked | Status::Crashed | Status::Dead | Status::Idle
+            Status::Blocked | Status::Dead | Status::Idle
         ) {
             // Mark the current process as runnable, so it can be picked by the scheduler again
             curr_proc.status = Status::Ready;
         }
 
-        // Do not wait for the next cycle to clean a dead process
+        // Do not wait for the next cycle to clean a dead process.  Its kernel
+        // stack and page tables stay intact until we leave them below: the
+        // lock is held and interrupts are off, so nobody can reuse them.
         if curr_proc.status == Status::Dead {
-            self.processes[self.current_pid] = None;
+            self.reap(self.current);
         }
 
         let next_proc = self.processes[next].as_mut().unwrap();
 
         next_proc.status = Status::Running;
-        self.current_pid = next;
+        next_proc.switches += 1;
+        self.current = next;
 
         // Prepare a custom kernel stack for the next process
         let kern_stack = &next_proc.kernel_stack;
@@ -119,7 +278,7 @@ impl Scheduler {
         rprint!(", CS: ");
         rprintn!(*((next_proc.last_rsp + 128) as *const u64)); // should be CS
         rprint!(", NEXT PID: ");
-        rprintn!(next);
+        rprintn!(next_proc.id);
         rprint!(", RFLAGS: ");
         rprintn!(*((next_proc.last_rsp + 136) as *const u64)); // should be RFLAGS
         rprint!("\n");
@@ -144,182 +303,1487 @@ impl Scheduler {
             }
         }
 
-        next_proc.last_rsp as *mut u64
+        next_proc.last_rsp as *mut u64
+    }
+
+    /// Charge one PIT tick to the running process.  A process that used up its
+    /// quantum sinks one level; one that blocked before that rises one level
+    /// back towards its base priority, which favours I/O-bound processes.
+    /// Returns true when the running process should give up the CPU.
+    fn charge_current(&mut self) -> bool {
+        let Some(Some(proc)) = self.processes.get_mut(self.current) else {
+            return true;
+        };
+
+        match proc.status {
+            Status::Running | Status::Ready => {
+                proc.cpu_ticks += 1;
+                proc.quantum_used += 1;
+
+                if proc.quantum_used < process::quantum(proc.level) {
+                    return false;
+                }
+
+                proc.level = (proc.level + 1).min(PRIORITY_LEVELS - 1);
+                proc.quantum_used = 0;
+            }
+            Status::Blocked => {
+                if proc.level > proc.priority {
+                    proc.level -= 1;
+                }
+                proc.quantum_used = 0;
+            }
+            _ => {}
+        }
+
+        true
+    }
+
+    /// Move every process back to its base priority level.
+    fn boost(&mut self, now: u64) {
+        for proc in self.processes.iter_mut().flatten() {
+            proc.level = proc.priority;
+            proc.quantum_used = 0;
+        }
+
+        self.last_boost = now;
+    }
+
+    /// Choose the slot to run next: the runnable process on the best (lowest)
+    /// level, round-robin among equals starting after the current slot.  The
+    /// current process wins ties until its quantum expires.  Dead processes
+    /// met on the way are reaped.
+    fn pick_next(&mut self, expired: bool) -> Option<usize> {
+        let len = self.processes.len();
+        let mut best: Option<(usize, u8)> = None;
+
+        for i in 1..len {
+            let slot = (self.current + i) % len;
+
+            let Some(proc) = self.processes[slot].as_ref() else {
+                continue;
+            };
+
+            if proc.status == Status::Dead {
+                self.reap(slot);
+                continue;
+            }
+
+            if !matches!(proc.status, Status::Ready | Status::Running) {
+                continue;
+            }
+
+            if best.is_none_or(|(_, level)| proc.level < level) {
+                best = Some((slot, proc.level));
+            }
+        }
+
+        if let Some(Some(curr)) = self.processes.get(self.current) {
+            if matches!(curr.status, Status::Ready | Status::Running)
+                && best.is_none_or(|(_, level)| {
+                    curr.level < level || (curr.level == level && !expired)
+                })
+            {
+                return Some(self.current);
+            }
+        }
+
+        best.map(|(slot, _)| slot)
+    }
+
+    /// Drop the process in `slot` and return its kernel stack, page tables
+    /// and user frames to their pools.  The exit code is kept for the parent,
+    /// which is woken if it is waiting for this process and sent SIGCHLD.
+    fn reap(&mut self, slot: usize) {
+        let Some(mut proc) = self.processes[slot].take() else {
+            return;
+        };
+
+        self.kstack_pool.push(proc.kernel_stack);
+
+        self.services
+            .retain(|s| !proc.ports.iter().any(|p| p.id == s.port));
+
+        // Calls queued on its ports, or received by its process and not
+        // answered yet, can no longer be replied to.
+        for port in proc.ports.iter_mut() {
+            self.drop_messages(port);
+        }
+
+        if proc.group == proc.id {
+            for caller in self.processes.iter_mut().flatten() {
+                if caller.call.is_some_and(|c| c.server == proc.id) {
+                    Self::fail_call(caller);
+                }
+            }
+        }
+
+        self.detach_stdio(proc.stdin, proc.stdout);
+
+        // Threads share the page tables of their process; the last one out
+        // frees them, after letting go of its shared memory regions and of
+        // the areas its pages were filled from.
+        if !self.processes.iter().flatten().any(|p| p.cr3 == proc.cr3) {
+            crate::mem::shm::release(proc.cr3);
+            crate::mem::vm::release(proc.cr3);
+
+            unsafe {
+                crate::mem::pages::free_user_page_table(proc.cr3);
+            }
+        }
+
+        if proc.group != proc.id {
+            self.reap_thread(&proc);
+            return;
+        }
+
+        // Statuses of its own children can no longer be collected.
+        self.exited.retain(|r| r.parent != proc.id);
+
+        if proc.parent == RESERVED_PID {
+            return;
+        }
+
+        let Some(parent) = self.get_mut(proc.parent) else {
+            return;
+        };
+
+        if parent.status == Status::Blocked
+            && parent.wait_for.is_some_and(|w| w == RESERVED_PID || w == proc.id)
+        {
+            parent.status = Status::Ready;
+            parent.wait_for = None;
+        }
+
+        self.record_exit(ExitRecord {
+            pid: proc.id,
+            parent: proc.parent,
+            code: proc.exit_code,
+            thread: false,
+        });
+
+        self.signal(proc.parent, SIGCHLD);
+    }
+
+    /// Keep the exit code of a reaped thread for a join by any thread of its
+    /// process, and wake the threads already joining it.
+    fn reap_thread(&mut self, thread: &Process) {
+        if self.slot_of(thread.group).is_none() {
+            return;
+        }
+
+        for proc in self.processes.iter_mut().flatten() {
+            if proc.group == thread.group
+                && proc.status == Status::Blocked
+                && proc.wait_for == Some(thread.id)
+            {
+                proc.status = Status::Ready;
+                proc.wait_for = None;
+            }
+        }
+
+        self.record_exit(ExitRecord {
+            pid: thread.id,
+            parent: thread.group,
+            code: thread.exit_code,
+            thread: true,
+        });
+    }
+
+    fn record_exit(&mut self, record: ExitRecord) {
+        if self.exited.len() >= MAX_EXIT_RECORDS {
+            self.exited.remove(0);
+        }
+
+        self.exited.push(record);
+    }
+
+    /// Collect the exit status of a child of the running process: `child`, or
+    /// any child for None.  If the child is still running, the caller is
+    /// blocked until it is reaped and Ok(None) is returned.  Err if there is
+    /// no such child, or if a signal is waiting to be handled.
+    fn try_wait(&mut self, child: Option<usize>) -> Result<Option<(usize, u64)>, ()> {
+        let parent = self.get_current_pid();
+        let matches = |pid: usize| child.is_none_or(|c| c == pid);
+
+        if let Some(proc) = self.get_mut(RESERVED_PID) {
+            proc.wait_for = None;
+        }
+
+        if let Some(pos) = self
+            .exited
+            .iter()
+            .position(|r| r.parent == parent && !r.thread && matches(r.pid))
+        {
+            let record = self.exited.remove(pos);
+            return Ok(Some((record.pid, record.code)));
+        }
+
+        let interrupted = self
+            .get_mut(RESERVED_PID)
+            .is_some_and(|proc| proc.pending & !proc.mask != 0);
+
+        let running = self
+            .processes
+            .iter()
+            .flatten()
+            .any(|p| p.parent == parent && p.group == p.id && matches(p.id));
+
+        if !running || interrupted {
+            return Err(());
+        }
+
+        if let Some(proc) = self.get_mut(RESERVED_PID) {
+            proc.status = Status::Blocked;
+            proc.wait_for = Some(child.unwrap_or(RESERVED_PID));
+        }
+
+        Ok(None)
+    }
+
+    /// Collect the exit code of thread `tid` of the running process, blocking
+    /// the caller like `try_wait` while it runs.  Err if there is no such
+    /// thread, it is the caller itself, or a signal is waiting to be handled.
+    fn try_join(&mut self, tid: usize) -> Result<Option<u64>, ()> {
+        let caller = self.get_mut(RESERVED_PID).ok_or(())?;
+        caller.wait_for = None;
+
+        let (id, group) = (caller.id, caller.group);
+        let interrupted = caller.pending & !caller.mask != 0;
+
+        if let Some(pos) = self
+            .exited
+            .iter()
+            .position(|r| r.thread && r.parent == group && r.pid == tid)
+        {
+            return Ok(Some(self.exited.remove(pos).code));
+        }
+
+        let running = self
+            .processes
+            .iter()
+            .flatten()
+            .any(|p| p.id == tid && p.group == group && p.id != group);
+
+        if !running || tid == id || interrupted {
+            return Err(());
+        }
+
+        if let Some(caller) = self.get_mut(RESERVED_PID) {
+            caller.status = Status::Blocked;
+            caller.wait_for = Some(tid);
+        }
+
+        Ok(None)
+    }
+
+    /// Block the running process on the futex at physical address `key`, but
+    /// only if `word` still holds `expected`; checked under the scheduler lock,
+    /// so a wake between the check and blocking cannot be lost.  A non-zero
+    /// `deadline` is a PIT tick at which the wait times out.  `queued` tells
+    /// the first attempt from the ones after the process was woken.
+    fn try_futex_wait(
+        &mut self,
+        key: u64,
+        word: *const u32,
+        expected: u32,
+        deadline: u64,
+        queued: &mut bool,
+    ) -> Result<Option<FutexWait>, ()> {
+        let now = crate::time::acpi::get_tick_count();
+        let proc = self.get_mut(RESERVED_PID).ok_or(())?;
+
+        if !*queued {
+            if unsafe { word.read_volatile() } != expected {
+                return Ok(Some(FutexWait::Mismatch));
+            }
+
+            proc.futex = key;
+            proc.sleep_until = deadline;
+            proc.status = Status::Blocked;
+            *queued = true;
+
+            return Ok(None);
+        }
+
+        // Halted by an unrelated interrupt, still blocked.
+        if proc.status == Status::Blocked {
+            return Ok(None);
+        }
+
+        // futex_wake clears `futex`; the sleep timeout and signals do not.
+        if proc.futex == 0 {
+            return Ok(Some(FutexWait::Woken));
+        }
+
+        proc.futex = 0;
+        proc.sleep_until = 0;
+
+        if deadline != 0 && now >= deadline {
+            Ok(Some(FutexWait::TimedOut))
+        } else {
+            Ok(Some(FutexWait::Woken))
+        }
+    }
+
+    /// Wake up to `count` processes blocked on the futex at `key`, in table
+    /// order.  Returns how many were woken.
+    pub fn futex_wake(&mut self, key: u64, count: usize) -> usize {
+        let mut woken = 0;
+
+        for proc in self.processes.iter_mut().flatten() {
+            if woken >= count {
+                break;
+            }
+
+            if proc.futex == key && proc.status == Status::Blocked {
+                proc.futex = 0;
+                proc.sleep_until = 0;
+                proc.status = Status::Ready;
+                woken += 1;
+            }
+        }
+
+        woken
+    }
+
+    /// Find the table slot holding `pid`.  `RESERVED_PID` resolves to the
+    /// running process.
+    fn slot_of(&self, pid: usize) -> Option<usize> {
+        if pid == RESERVED_PID {
+            return self.processes.get(self.current)?.as_ref().map(|_| self.current);
+        }
+
+        self.processes
+            .iter()
+            .position(|p| p.as_ref().is_some_and(|p| p.id == pid))
+    }
+
+    fn get_mut(&mut self, pid: usize) -> Option<&mut Process> {
+        let slot = self.slot_of(pid)?;
+        self.processes[slot].as_mut()
+    }
+
+    /// Queue `msg` on the default port of `pid`, for the legacy PID-addressed
+    /// syscalls and network delivery.
+    pub fn push_msg(&mut self, pid: usize, msg: Message) {
+        let Some(port) = self.get_mut(pid).and_then(|p| p.ports.first()).map(|p| p.id) else {
+            return;
+        };
+
+        let _ = self.send(port, msg);
+    }
+
+    pub unsafe fn pop_msg(&mut self, pid: usize) -> Option<Message> {
+        self.get_mut(pid)?.ports.first_mut()?.queue.pop()
+    }
+
+    /// Find the table slot of the process owning `port`, and the port's index
+    /// in its port list.
+    fn find_port(&self, port: usize) -> Option<(usize, usize)> {
+        self.processes.iter().enumerate().find_map(|(slot, proc)| {
+            let index = proc.as_ref()?.ports.iter().position(|p| p.id == port)?;
+            Some((slot, index))
+        })
+    }
+
+    /// Create a port owned by the process of `pid`, shared by all its threads.
+    /// Returns the port ID, or None if the process has MAX_PORTS already.
+    pub fn port_create(&mut self, pid: usize) -> Option<usize> {
+        let group = self.get_mut(pid)?.group;
+        let id = self.next_port_id;
+        let owner = self.get_mut(group)?;
+
+        if owner.ports.len() >= MAX_PORTS || owner.ports.try_reserve(1).is_err() {
+            return None;
+        }
+
+        owner.ports.push(Port::new(id));
+        self.next_port_id += 1;
+
+        Some(id)
+    }
+
+    /// Destroy `port` of the process of `pid`.  Queued messages are dropped,
+    /// their callers fail, and threads receiving on it are woken.  The
+    /// default port cannot be destroyed.
+    pub fn port_destroy(&mut self, pid: usize, port: usize) -> bool {
+        let Some(group) = self.get_mut(pid).map(|p| p.group) else {
+            return false;
+        };
+
+        let Some(owner) = self.get_mut(group) else {
+            return false;
+        };
+
+        let Some(index) = owner.ports.iter().position(|p| p.id == port) else {
+            return false;
+        };
+
+        if index == 0 {
+            return false;
+        }
+
+        let mut removed = owner.ports.remove(index);
+        self.drop_messages(&mut removed);
+        self.services.retain(|s| s.port != port);
+
+        for proc in self.processes.iter_mut().flatten() {
+            if proc.recv_port == Some(port) {
+                proc.recv_port = None;
+                proc.status = Status::Ready;
+            }
+        }
+
+        self.wake_pollers(poll::kind_bit(POLL_PORT));
+
+        true
+    }
+
+    /// Queue `msg` on `port` and wake a thread of its owner receiving on it.
+    pub fn send(&mut self, port: usize, mut msg: Message) -> Result<(), IpcError> {
+        let (slot, index) = self.find_port(port).ok_or(IpcError::NoPort)?;
+        let owner = self.processes[slot].as_mut().ok_or(IpcError::NoPort)?;
+
+        msg.port_id = port;
+
+        if !owner.ports[index].queue.push(msg) {
+            return Err(IpcError::QueueFull);
+        }
+
+        let group = owner.group;
+
+        if let Some(receiver) = self
+            .processes
+            .iter_mut()
+            .flatten()
+            .find(|p| p.group == group && p.recv_port == Some(port))
+        {
+            receiver.recv_port = None;
+            receiver.status = Status::Ready;
+        }
+
+        self.wake_pollers(poll::kind_bit(POLL_PORT));
+
+        Ok(())
+    }
+
+    /// Take the next message from `port`, which must belong to the process of
+    /// the running thread.  With the queue empty, the caller is blocked until
+    /// a message arrives and Ok(None) is returned.
+    fn try_receive(&mut self, port: usize) -> Result<Option<Message>, IpcError> {
+        let caller = self.get_mut(RESERVED_PID).ok_or(IpcError::NoPort)?;
+        caller.recv_port = None;
+
+        let group = caller.group;
+        let interrupted = caller.pending & !caller.mask != 0;
+
+        let (slot, index) = self.find_port(port).ok_or(IpcError::NoPort)?;
+        let owner = self.processes[slot].as_mut().ok_or(IpcError::NoPort)?;
+
+        if owner.group != group {
+            return Err(IpcError::NoPort);
+        }
+
+        if let Some(msg) = owner.ports[index].queue.pop() {
+            return Ok(Some(msg));
+        }
+
+        if interrupted {
+            return Err(IpcError::Interrupted);
+        }
+
+        if let Some(caller) = self.get_mut(RESERVED_PID) {
+            caller.recv_port = Some(port);
+            caller.status = Status::Blocked;
+        }
+
+        Ok(None)
+    }
+
+    /// Send `request` to `port` as a call and block the caller until it is
+    /// answered.  The first attempt takes `request`; later ones collect the
+    /// reply.  Signals do not interrupt a call: the caller keeps waiting
+    /// until the server replies or goes away.
+    fn try_call(
+        &mut self,
+        port: usize,
+        request: &mut Option<Message>,
+    ) -> Result<Option<Vec<u8>>, IpcError> {
+        if let Some(mut msg) = request.take() {
+            let (slot, _) = self.find_port(port).ok_or(IpcError::NoPort)?;
+            let server = self.processes[slot].as_ref().ok_or(IpcError::NoPort)?.group;
+
+            let token = self.next_token;
+            self.next_token += 1;
+
+            msg.token = token;
+            self.send(port, msg)?;
+
+            let caller = self.get_mut(RESERVED_PID).ok_or(IpcError::PeerGone)?;
+            caller.call = Some(PendingCall { token, server });
+            caller.reply = None;
+            caller.status = Status::Blocked;
+
+            return Ok(None);
+        }
+
+        let caller = self.get_mut(RESERVED_PID).ok_or(IpcError::PeerGone)?;
+
+        if let Some(reply) = caller.reply.take() {
+            return Ok(Some(reply));
+        }
+
+        if caller.call.is_none() {
+            return Err(IpcError::PeerGone);
+        }
+
+        caller.status = Status::Blocked;
+
+        Ok(None)
+    }
+
+    /// Answer the call identified by `token` with `data`.  Only threads of the
+    /// process the call was sent to may reply, and only once.
+    pub fn reply(&mut self, pid: usize, token: u64, data: Vec<u8>) -> Result<(), IpcError> {
+        let group = self.get_mut(pid).ok_or(IpcError::NoPort)?.group;
+
+        let caller = self
+            .processes
+            .iter_mut()
+            .flatten()
+            .find(|p| p.call.is_some_and(|c| c.token == token && c.server == group))
+            .ok_or(IpcError::NoPort)?;
+
+        caller.call = None;
+        caller.reply = Some(data);
+        caller.status = Status::Ready;
+
+        Ok(())
+    }
+
+    /// Register `name` for `port`, which must belong to the process of `pid`,
+    /// and wake processes waiting for the name.  Returns false if the name is
+    /// taken or the registry is full.
+    pub fn register_service(&mut self, pid: usize, name: [u8; NAME_LEN], port: usize) -> bool {
+        let Some(group) = self.get_mut(pid).map(|p| p.group) else {
+            return false;
+        };
+
+        let owned = self
+            .find_port(port)
+            .and_then(|(slot, _)| self.processes[slot].as_ref())
+            .is_some_and(|owner| owner.group == group);
+
+        if !owned
+            || self.services.len() >= MAX_SERVICES
+            || self.services.iter().any(|s| s.name == name)
+            || self.services.try_reserve(1).is_err()
+        {
+            return false;
+        }
+
+        self.services.push(Service {
+            name,
+            port,
+            pid: group,
+        });
+
+        for proc in self.processes.iter_mut().flatten() {
+            if proc.service_wait == Some(name) {
+                proc.service_wait = None;
+                proc.sleep_until = 0;
+                proc.status = Status::Ready;
+            }
+        }
+
+        true
+    }
+
+    /// Remove `name` from the registry, if the process of `pid` registered it.
+    pub fn unregister_service(&mut self, pid: usize, name: [u8; NAME_LEN]) -> bool {
+        let Some(group) = self.get_mut(pid).map(|p| p.group) else {
+            return false;
+        };
+
+        let Some(pos) = self
+            .services
+            .iter()
+            .position(|s| s.name == name && s.pid == group)
+        else {
+            return false;
+        };
+
+        self.services.remove(pos);
+
+        true
+    }
+
+    /// Look up the port registered for `name`.  If there is none and `wait`
+    /// is set, the caller is blocked until the name is registered and Ok(None)
+    /// is returned; a non-zero `deadline` is the PIT tick at which to give up.
+    /// Err once the lookup has failed, timed out or is interrupted by a signal.
+    fn try_lookup(
+        &mut self,
+        name: [u8; NAME_LEN],
+        wait: bool,
+        deadline: u64,
+        queued: &mut bool,
+    ) -> Result<Option<usize>, ()> {
+        let now = crate::time::acpi::get_tick_count();
+        let found = self.services.iter().find(|s| s.name == name).map(|s| s.port);
+        let proc = self.get_mut(RESERVED_PID).ok_or(())?;
+
+        if *queued {
+            // Halted by an unrelated interrupt, still blocked.
+            if proc.status == Status::Blocked && found.is_none() {
+                return Ok(None);
+            }
+
+            proc.service_wait = None;
+            proc.sleep_until = 0;
+        }
+
+        if found.is_some() {
+            return Ok(found);
+        }
+
+        let interrupted = proc.pending & !proc.mask != 0;
+        let expired = deadline != 0 && now >= deadline;
+
+        if !wait || interrupted || (*queued && expired) {
+            return Err(());
+        }
+
+        proc.service_wait = Some(name);
+        proc.sleep_until = deadline;
+        proc.status = Status::Blocked;
+        *queued = true;
+
+        Ok(None)
+    }
+
+    /// Empty `port`, failing the callers of any calls still queued on it.
+    fn drop_messages(&mut self, port: &mut Port) {
+        while let Some(msg) = port.queue.pop() {
+            if msg.token == 0 {
+                continue;
+            }
+
+            if let Some(caller) = self
+                .processes
+                .iter_mut()
+                .flatten()
+                .find(|p| p.call.is_some_and(|c| c.token == msg.token))
+            {
+                Self::fail_call(caller);
+            }
+        }
+    }
+
+    /// End the pending call of `caller` without a reply.
+    fn fail_call(caller: &mut Process) {
+        caller.call = None;
+
+        if caller.status == Status::Blocked {
+            caller.status = Status::Ready;
+        }
+    }
+
+    /// Create a pipe and make it the standard output of `pid` in place of the
+    /// current one.  Returns the pipe ID, or None if there are MAX_PIPES
+    /// pipes already or the heap is exhausted.
+    pub fn pipe_open(&mut self, pid: usize) -> Option<u64> {
+        let stdin = self.get_mut(pid)?.stdin;
+
+        if self.pipes.len() >= MAX_PIPES {
+            return None;
+        }
+
+        let id = self.next_pipe_id;
+        self.pipes.push(Pipe::new(id)?);
+        self.next_pipe_id += 1;
+
+        self.set_stdio(pid, stdin, Some(id));
+
+        Some(id)
+    }
+
+    /// Point the standard input and output of `pid` at the given pipes (None
+    /// for the keyboard and screen).  Processes started by `pid` inherit them.
+    pub fn set_stdio(&mut self, pid: usize, stdin: Option<u64>, stdout: Option<u64>) -> bool {
+        let exists = |id: Option<u64>| id.is_none_or(|id| self.pipes.iter().any(|p| p.id == id));
+
+        if !exists(stdin) || !exists(stdout) || self.get_mut(pid).is_none() {
+            return false;
+        }
+
+        // Attach to the new ends first, so a pipe kept across the switch is
+        // not freed in between.
+        self.attach_stdio(stdin, stdout);
+
+        let Some(proc) = self.get_mut(pid) else {
+            return false;
+        };
+
+        let old_stdin = core::mem::replace(&mut proc.stdin, stdin);
+        let old_stdout = core::mem::replace(&mut proc.stdout, stdout);
+
+        self.detach_stdio(old_stdin, old_stdout);
+
+        true
+    }
+
+    fn attach_stdio(&mut self, stdin: Option<u64>, stdout: Option<u64>) {
+        for pipe in self.pipes.iter_mut() {
+            if stdin == Some(pipe.id) {
+                pipe.readers += 1;
+            }
+            if stdout == Some(pipe.id) {
+                pipe.writers += 1;
+            }
+        }
+    }
+
+    /// Let go of the given pipe ends.  Readers blocked on a pipe whose last
+    /// writer left are woken to see EOF, writers whose last reader left to
+    /// see BrokenPipe.  A pipe nobody is attached to is freed.
+    fn detach_stdio(&mut self, stdin: Option<u64>, stdout: Option<u64>) {
+        let mut closed = Vec::new();
+
+        for pipe in self.pipes.iter_mut() {
+            if stdin == Some(pipe.id) {
+                pipe.readers -= 1;
+
+                if pipe.readers == 0 {
+                    closed.push(pipe.id);
+                }
+            }
+            if stdout == Some(pipe.id) {
+                pipe.writers -= 1;
+
+                if pipe.writers == 0 {
+                    closed.push(pipe.id);
+                }
+            }
+        }
+
+        for id in closed {
+            self.wake_pipe(id);
+        }
+
+        self.pipes.retain(|p| p.readers > 0 || p.writers > 0);
+    }
+
+    /// Make every process blocked on pipe `id` Ready to try again, and
+    /// every process polling a descriptor, which may be that pipe.
+    fn wake_pipe(&mut self, id: u64) {
+        for proc in self.processes.iter_mut().flatten() {
+            if proc.pipe_wait == Some(id) {
+                proc.pipe_wait = None;
+                proc.status = Status::Ready;
+            }
+        }
+
+        self.wake_pollers(poll::kind_bit(POLL_FD));
+    }
+
+    /// Read from the standard input pipe of the running process into `buf`.
+    /// Ok(Some(0)) is EOF: the pipe is empty and has no writers left.  With
+    /// the pipe empty but still written to, the caller is blocked until data
+    /// arrives and Ok(None) is returned.
+    fn try_pipe_read(&mut self, buf: &mut [u8]) -> Result<Option<usize>, IpcError> {
+        let caller = self.get_mut(RESERVED_PID).ok_or(IpcError::NoPort)?;
+        caller.pipe_wait = None;
+
+        let id = caller.stdin.ok_or(IpcError::NoPort)?;
+        let interrupted = caller.pending & !caller.mask != 0;
+
+        let pipe = self
+            .pipes
+            .iter_mut()
+            .find(|p| p.id == id)
+            .ok_or(IpcError::NoPort)?;
+
+        if !pipe.is_empty() {
+            let count = pipe.read(buf);
+            self.wake_pipe(id);
+
+            return Ok(Some(count));
+        }
+
+        if pipe.writers == 0 {
+            return Ok(Some(0));
+        }
+
+        if interrupted {
+            return Err(IpcError::Interrupted);
+        }
+
+        if let Some(caller) = self.get_mut(RESERVED_PID) {
+            caller.pipe_wait = Some(id);
+            caller.status = Status::Blocked;
+        }
+
+        Ok(None)
+    }
+
+    /// Write `data` to the standard output pipe of the running process,
+    /// `written` bytes of it having gone in by earlier attempts.  While the
+    /// pipe is full, the caller is blocked until it is read from and Ok(None)
+    /// is returned.  Fails with PeerGone once the pipe has no readers.
+    fn try_pipe_write(&mut self, data: &[u8], written: &mut usize) -> Result<Option<()>, IpcError> {
+        let caller = self.get_mut(RESERVED_PID).ok_or(IpcError::NoPort)?;
+        caller.pipe_wait = None;
+
+        let id = caller.stdout.ok_or(IpcError::NoPort)?;
+        let interrupted = caller.pending & !caller.mask != 0;
+
+        let pipe = self
+            .pipes
+            .iter_mut()
+            .find(|p| p.id == id)
+            .ok_or(IpcError::NoPort)?;
+
+        if pipe.readers == 0 {
+            return Err(IpcError::PeerGone);
+        }
+
+        let count = pipe.write(data.get(*written..).unwrap_or(&[]));
+        *written += count;
+
+        if count > 0 {
+            self.wake_pipe(id);
+        }
+
+        if *written >= data.len() {
+            return Ok(Some(()));
+        }
+
+        if interrupted {
+            return Err(IpcError::Interrupted);
+        }
+
+        if let Some(caller) = self.get_mut(RESERVED_PID) {
+            caller.pipe_wait = Some(id);
+            caller.status = Status::Blocked;
+        }
+
+        Ok(None)
+    }
+
+    /// Make processes polling a source of the kinds in `mask` Ready, so they
+    /// check their sources again.
+    fn wake_pollers(&mut self, mask: u64) {
+        for proc in self.processes.iter_mut().flatten() {
+            if proc.polling & mask != 0 {
+                proc.polling = 0;
+                proc.status = Status::Ready;
+            }
+        }
+    }
+
+    /// Events of pipe `id` for the reading or the writing end.
+    fn pipe_events(&self, id: u64, write: bool) -> u64 {
+        let Some(pipe) = self.pipes.iter().find(|p| p.id == id) else {
+            return POLL_ERR;
+        };
+
+        match write {
+            false if !pipe.is_empty() => POLL_IN,
+            false if pipe.writers == 0 => POLL_HUP,
+            true if pipe.readers == 0 => POLL_ERR,
+            true if pipe.len < PIPE_SIZE => POLL_OUT,
+            _ => 0,
+        }
+    }
+
+    /// Events of a poll source of the running process, as POLL_* bits.
+    fn poll_events(&mut self, kind: u64, id: u64) -> u64 {
+        let Some(caller) = self.get_mut(RESERVED_PID) else {
+            return POLL_ERR;
+        };
+
+        let (pid, group, stdin, stdout) = (caller.id, caller.group, caller.stdin, caller.stdout);
+
+        match kind {
+            POLL_PORT => match self.find_port(id as usize) {
+                Some((slot, index)) => match self.processes[slot].as_ref() {
+                    Some(owner) if owner.group == group => {
+                        if owner.ports[index].queue.is_empty() {
+                            0
+                        } else {
+                            POLL_IN
+                        }
+                    }
+                    _ => POLL_ERR,
+                },
+                None => POLL_ERR,
+            },
+            POLL_FD => match self.fd_get(pid, id as usize) {
+                Some(file) => match file.node {
+                    Node::Stdin => match stdin {
+                        Some(pipe) => self.pipe_events(pipe, false),
+                        None => poll::console_events(),
+                    },
+                    Node::Stdout => match stdout {
+                        Some(pipe) => self.pipe_events(pipe, true),
+                        None => POLL_OUT,
+                    },
+                    Node::Stderr => POLL_OUT,
+                    _ => {
+                        let mut events = 0;
+                        if file.flags & O_READ != 0 {
+                            events |= POLL_IN;
+                        }
+                        if file.flags & O_WRITE != 0 {
+                            events |= POLL_OUT;
+                        }
+                        events
+                    }
+                },
+                None => POLL_ERR,
+            },
+            _ => poll::device_events(kind, pid),
+        }
+    }
+
+    /// Set `revents` of each of `sources` and return how many have any.  With
+    /// none ready and `wait` set, the caller is blocked until an event may
+    /// have happened and Ok(None) is returned; a non-zero `deadline` is the
+    /// PIT tick at which Ok(Some(0)) is returned instead.  Fails with
+    /// Interrupted if a signal arrives first.
+    fn try_poll(
+        &mut self,
+        sources: &mut [PollSource],
+        wait: bool,
+        deadline: u64,
+        queued: &mut bool,
+    ) -> Result<Option<usize>, IpcError> {
+        let now = crate::time::acpi::get_tick_count();
+        let mut ready = 0;
+        let mut kinds = 0;
+
+        for source in sources.iter_mut() {
+            let events = self.poll_events(source.kind, source.id);
+
+            source.revents = events & (source.events | POLL_HUP | POLL_ERR);
+            if source.revents != 0 {
+                ready += 1;
+            }
+
+            kinds |= poll::kind_bit(source.kind);
+        }
+
+        let proc = self.get_mut(RESERVED_PID).ok_or(IpcError::NoPort)?;
+
+        if *queued {
+            // Halted by an unrelated interrupt, still blocked.
+            if proc.status == Status::Blocked && ready == 0 {
+                return Ok(None);
+            }
+
+            // An event seen before its wakeup was delivered.
+            if proc.status == Status::Blocked {
+                proc.status = Status::Running;
+            }
+
+            proc.polling = 0;
+            proc.sleep_until = 0;
+        }
+
+        if ready > 0 || !wait {
+            return Ok(Some(ready));
+        }
+
+        if proc.pending & !proc.mask != 0 {
+            return Err(IpcError::Interrupted);
+        }
+
+        if *queued && deadline != 0 && now >= deadline {
+            return Ok(Some(0));
+        }
+
+        proc.polling = kinds;
+        proc.sleep_until = deadline;
+        proc.status = Status::Blocked;
+        *queued = true;
+
+        Ok(None)
+    }
+
+    /// Descriptor table of the process `pid` belongs to.
+    fn files_mut(&mut self, pid: usize) -> Option<&mut Vec<Option<File>>> {
+        let group = self.get_mut(pid)?.group;
+
+        Some(&mut self.get_mut(group)?.files)
+    }
+
+    /// Store `file` under the lowest free descriptor of the process of `pid`.
+    /// Returns None if it has MAX_FILES open already.
+    pub fn fd_install(&mut self, pid: usize, file: File) -> Option<usize> {
+        let files = self.files_mut(pid)?;
+
+        if let Some(fd) = files.iter().position(|f| f.is_none()) {
+            files[fd] = Some(file);
+            return Some(fd);
+        }
+
+        if files.len() >= MAX_FILES {
+            return None;
+        }
+
+        files.push(Some(file));
+        Some(files.len() - 1)
+    }
+
+    /// Copy of open file `fd` of the process of `pid`.  File I/O works on the
+    /// copy, so the scheduler is not held across disk access; `fd_update`
+    /// stores the new position afterwards.
+    pub fn fd_get(&mut self, pid: usize, fd: usize) -> Option<File> {
+        *self.files_mut(pid)?.get(fd)?
+    }
+
+    /// Replace open file `fd`, unless it has been closed meanwhile.
+    pub fn fd_update(&mut self, pid: usize, fd: usize, file: File) {
+        if let Some(Some(slot)) = self.files_mut(pid).and_then(|f| f.get_mut(fd)) {
+            *slot = file;
+        }
+    }
+
+    pub fn fd_close(&mut self, pid: usize, fd: usize) -> bool {
+        match self.files_mut(pid).and_then(|f| f.get_mut(fd)) {
+            Some(slot) => slot.take().is_some(),
+            None => false,
+        }
+    }
+
+    /// Standard input and output of the running process.
+    pub fn stdio(&self) -> (Option<u64>, Option<u64>) {
+        match self.processes.get(self.current) {
+            Some(Some(proc)) => (proc.stdin, proc.stdout),
+            _ => (None, None),
+        }
+    }
+
+    /// Where the syscalls of the running process are logged.
+    pub fn trace(&self) -> Option<Trace> {
+        match self.processes.get(self.current) {
+            Some(Some(proc)) => proc.trace,
+            _ => None,
+        }
+    }
+
+    /// Trace the syscalls of `pid` and of its threads, or stop with None.
+    /// Returns false if there is no such process.
+    pub fn set_trace(&mut self, pid: usize, trace: Option<Trace>) -> bool {
+        let mut found = false;
+
+        for proc in self.processes.iter_mut().flatten() {
+            if proc.id == pid || proc.group == pid {
+                proc.trace = trace;
+                found |= proc.id == pid;
+            }
+        }
+
+        found
+    }
+
+    pub fn set_status(&mut self, pid: usize, status: Status) {
+        if let Some(proc) = self.get_mut(pid) {
+            proc.status = status;
+        }
+    }
+
+    /// Set the base priority of `pid` and restart it at that level.
+    /// Returns false if there is no such process.
+    pub fn set_priority(&mut self, pid: usize, priority: u8) -> bool {
+        let Some(proc) = self.get_mut(pid) else {
+            return false;
+        };
+
+        proc.priority = priority.min(PRIORITY_LEVELS - 1);
+        proc.level = proc.priority;
+        proc.quantum_used = 0;
+
+        true
+    }
+
+    pub fn get_priority(&self, pid: usize) -> Option<u8> {
+        let slot = self.slot_of(pid)?;
+        self.processes[slot].as_ref().map(|p| p.priority)
+    }
+
+    /// Mark `pid` as Dead with the given exit code; it is reaped on the
+    /// next tick.  Ending the main thread of a process ends all its threads.
+    pub fn exit(&mut self, pid: usize, code: u64) {
+        let Some(proc) = self.get_mut(pid) else {
+            return;
+        };
+
+        proc.exit_code = code;
+        proc.status = Status::Dead;
+
+        let id = proc.id;
+
+        if proc.group != id {
+            return;
+        }
+
+        for thread in self.processes.iter_mut().flatten() {
+            if thread.group == id && thread.id != id {
+                thread.exit_code = code;
+                thread.status = Status::Dead;
+            }
+        }
+    }
+
+    /// End the whole process `pid` belongs to, whichever of its threads it is.
+    pub fn exit_process(&mut self, pid: usize, code: u64) {
+        if let Some(group) = self.get_mut(pid).map(|p| p.group) {
+            self.exit(group, code);
+        }
+    }
+
+    /// Send `sig` to `pid`.  The default action and ignored signals are dealt
+    /// with here; a signal with a handler is left pending until the process
+    /// returns to ring 3, and wakes the process if it is blocked.  Kernel
+    /// processes always take the default action.  Returns false if there is
+    /// no such process or signal.
+    pub fn signal(&mut self, pid: usize, sig: u8) -> bool {
+        if !signal::is_valid(sig) {
+            return false;
+        }
+
+        let Some((mode, group)) = self.get_mut(pid).map(|p| (p.mode, p.group)) else {
+            return false;
+        };
+
+        let handler = match mode {
+            Mode::User if sig != SIGKILL => self.action(group, sig).handler,
+            _ => SIG_DFL,
+        };
+
+        let Some(proc) = self.get_mut(pid) else {
+            return false;
+        };
+
+        match handler {
+            SIG_IGN => {}
+            SIG_DFL => {
+                if signal::default_terminates(sig) {
+                    let id = proc.id;
+                    self.exit_process(id, signal::exit_code(sig));
+                }
+            }
+            _ => {
+                proc.pending |= signal::bit(sig);
+
+                if proc.status == Status::Blocked && proc.mask & signal::bit(sig) == 0 {
+                    proc.status = Status::Ready;
+                    proc.sleep_until = 0;
+                    proc.wait_for = None;
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Install the action for `sig` in the process `pid` belongs to, for all
+    /// its threads.  SIGKILL cannot be caught or ignored.  A pending signal
+    /// switched to the default or ignore action is dropped.
+    pub fn set_action(&mut self, pid: usize, sig: u8, action: SigAction) -> bool {
+        if !signal::is_valid(sig) || sig == SIGKILL {
+            return false;
+        }
+
+        let Some(group) = self.get_mut(pid).map(|p| p.group) else {
+            return false;
+        };
+        let Some(main) = self.get_mut(group) else {
+            return false;
+        };
+
+        main.actions[sig as usize] = action;
+
+        if matches!(action.handler, SIG_DFL | SIG_IGN) {
+            for proc in self.processes.iter_mut().flatten() {
+                if proc.group == group {
+                    proc.pending &= !signal::bit(sig);
+                }
+            }
+        }
+
+        true
     }
 
-    fn check_pid(&self, pid: usize) -> bool {
-        if pid >= self.processes.len() {
-            return false;
-        }
+    /// The action for `sig` of the process whose main thread is `group`.
+    /// The threads of a process share the actions kept by its main thread.
+    fn action(&mut self, group: usize, sig: u8) -> SigAction {
+        self.get_mut(group)
+            .map_or(SigAction::DEFAULT, |main| main.actions[sig as usize])
+    }
 
-        if self.processes[pid].is_some() {
-            return true;
-        }
+    /// Send SIGALRM to `pid` at PIT tick `at` (0 cancels).  Returns the tick
+    /// of the alarm it replaces, or 0.
+    pub fn set_alarm(&mut self, pid: usize, at: u64) -> u64 {
+        let Some(proc) = self.get_mut(pid) else {
+            return 0;
+        };
 
-        false
+        core::mem::replace(&mut proc.alarm_at, at)
     }
 
-    pub fn push_msg(&mut self, pid: usize, msg: Message) {
-        if !self.check_pid(pid) {
+    /// If the running process is about to return to ring 3 through `frame`
+    /// with a signal pending, push a SignalFrame on its user stack and point
+    /// `frame` at the handler instead.  The signal stays masked until
+    /// sigreturn.  Must run in the address space of the running process.
+    unsafe fn deliver_signal(&mut self, frame: *mut InterruptFrame) {
+        let Some(Some(proc)) = self.processes.get_mut(self.current) else {
             return;
-        }
+        };
 
-        self.processes[pid].as_mut().unwrap().ports[0]
-            .queue
-            .push(msg);
+        let frame = &mut *frame;
 
-        // Wake the target process to fetch new message
-        self.set_status(pid, Status::Ready);
-    }
+        if !matches!(proc.mode, Mode::User) || frame.cs != USER_CS {
+            return;
+        }
 
-    pub unsafe fn pop_msg(&mut self, pid: usize) -> Option<Message> {
-        if !self.check_pid(pid) {
-            return None;
+        let ready = proc.pending & !proc.mask;
+        if ready == 0 {
+            return;
         }
 
-        self.processes[pid].as_mut().unwrap().ports[0].queue.pop()
+        let sig = ready.trailing_zeros() as u8;
+        proc.pending &= !signal::bit(sig);
+
+        let group = proc.group;
+        let action = self.action(group, sig);
+        let Some(Some(proc)) = self.processes.get_mut(self.current) else {
+            return;
+        };
+        let size = core::mem::size_of::<SignalFrame>() as u64;
+
+        // Skip the red zone and align so that RSP + 8 is 16-byte aligned on
+        // entry to the handler, as if `restorer` had called it.  A frame
+        // that would reach into a guard page is an overflowed stack.
+        let sp = frame
+            .rsp
+            .checked_sub(RED_ZONE + size)
+            .map(|sp| (sp & !0xF) - 8)
+            .filter(|&sp| sp >= USER_STACK_BASE && frame.rsp <= USER_STACK_TOP)
+            .filter(|&sp| crate::mem::vm::is_user_range(sp, size));
+
+        let Some(sp) = sp else {
+            // No room for the frame: the user stack is gone, so is the process.
+            let id = proc.id;
+            self.exit_process(id, EXIT_CRASHED);
+            return;
+        };
+
+        (sp as *mut SignalFrame).write(SignalFrame {
+            restorer: action.restorer,
+            signo: sig as u64,
+            mask: proc.mask as u64,
+            regs: *frame,
+        });
+
+        proc.mask |= signal::bit(sig);
+
+        frame.rip = action.handler;
+        frame.rdi = sig as u64;
+        frame.rsp = sp;
     }
 
-    pub fn set_status(&mut self, mut pid: usize, status: Status) {
-        if pid == 0xff {
-            pid = self.current_pid;
-        }
+    /// Resume the code a signal handler interrupted.  `frame` is the syscall
+    /// frame of the running process; its user RSP points just past the
+    /// `restorer` slot of the SignalFrame.  Returns the restored RAX, or None
+    /// if the caller is not a user process or there is no valid SignalFrame
+    /// there; the process is then ended as if by a segmentation fault.
+    unsafe fn sigreturn(&mut self, frame: *mut InterruptFrame) -> Option<u64> {
+        let proc = self.processes.get_mut(self.current)?.as_mut()?;
+        let frame = &mut *frame;
 
-        if !self.check_pid(pid) {
-            return;
+        if !matches!(proc.mode, Mode::User) || frame.cs != USER_CS {
+            return None;
         }
 
-        self.processes[pid].as_mut().unwrap().status = status;
-    }
+        let addr = frame.rsp.wrapping_sub(8);
+        let size = core::mem::size_of::<SignalFrame>() as u64;
+
+        let saved = (addr >= USER_STACK_BASE
+            && addr + size <= USER_STACK_TOP
+            && crate::mem::vm::is_user_range(addr, size))
+        .then(|| (addr as *const SignalFrame).read())
+        // A frame the process scribbled over must not send iretq or sysretq
+        // to a non-canonical address, which would fault in ring 0.
+        .filter(|saved| {
+            crate::mem::vm::in_user_window(saved.regs.rip)
+                && crate::mem::vm::in_user_window(saved.regs.rsp)
+        });
+
+        let Some(saved) = saved else {
+            let id = proc.id;
+            self.exit_process(id, EXIT_CRASHED);
+            return None;
+        };
+        let mut regs = saved.regs;
 
-    pub fn kill(&mut self, pid: usize) {
-        self.set_status(pid, Status::Dead);
+        // Never let user code pick its segments, IOPL or interrupt flag.
+        regs.cs = frame.cs;
+        regs.ss = frame.ss;
+        regs.rflags = (regs.rflags & RFLAGS_USER) | 0x202;
 
-        rprint!("KILL PID ");
-        rprintn!(pid);
-        rprint!("\n\n");
+        *frame = regs;
+        proc.mask = saved.mask as u32;
+
+        Some(regs.rax)
     }
 
-    pub fn block(&mut self, pid: usize, msg: Message) {
-        if !self.check_pid(pid) {
+    /// Block `pid` until a message arrives on its default port.
+    pub fn block(&mut self, pid: usize) {
+        let Some(proc) = self.get_mut(pid) else {
             return;
-        }
+        };
 
-        self.set_status(pid, Status::Blocked);
-        self.processes[pid].as_mut().unwrap().ports[0].block_msg = Some(msg);
+        proc.recv_port = proc.ports.first().map(|p| p.id);
+        proc.status = Status::Blocked;
     }
 
-    pub fn sleep_process(&mut self, slot: usize, until_tick: u64) {
-        if !self.check_pid(slot) {
+    pub fn sleep_process(&mut self, pid: usize, until_tick: u64) {
+        let Some(proc) = self.get_mut(pid) else {
             return;
-        }
-        let proc = self.processes[slot].as_mut().unwrap();
+        };
         proc.sleep_until = until_tick;
         proc.status = Status::Blocked;
     }
 
+    /// Print the process table, each process followed by its threads.
     pub unsafe fn list_processes(&self) {
-        print!("SLOT PID NAME                M STATUS\n");
+        print!("SLOT PID NAME                M PRI STATUS\n");
 
         let mut slot = 0;
 
-        for process in self.processes.iter() {
-            if process.is_none() {
+        for proc in self.processes.iter().flatten() {
+            // Threads are printed under their process, unless it is gone.
+            if proc.group != proc.id && self.slot_of(proc.group).is_some() {
                 continue;
             }
 
-            if let Some(proc) = process {
-                printn!(slot);
-                slot += 1;
-                print!("    ");
+            Self::print_process(slot, proc, false);
+            slot += 1;
 
-                printn!(proc.id);
-                print!("   ");
-                printb!(&proc.name);
-                print!("   ");
-
-                match proc.mode {
-                    Mode::Kernel => {
-                        print!(" K");
-                    }
-                    Mode::User => {
-                        print!(" U");
-                    }
-                    _ => {
-                        print!(" ?");
-                    }
+            for thread in self.processes.iter().flatten() {
+                if thread.group == proc.id && thread.id != proc.id {
+                    Self::print_process(slot, thread, true);
+                    slot += 1;
                 }
+            }
+        }
+    }
 
-                match proc.status {
-                    Status::Ready => {
-                        print!(" (Ready)");
-                    }
-                    Status::Running => {
-                        print!(" (Running)");
-                    }
-                    Status::Idle => {
-                        print!(" (Idle)");
-                    }
-                    Status::Blocked => {
-                        print!(" (Blocked)");
-                    }
-                    Status::Crashed => {
-                        print!(" (Crashed)");
-                    }
-                    Status::Dead => {
-                        print!(" (Dead)");
-                    }
-                }
+    unsafe fn print_process(slot: usize, proc: &Process, thread: bool) {
+        printn!(slot);
+        print!("    ");
+
+        printn!(proc.id);
+        print!("   ");
+        if thread {
+            print!("`- ");
+        }
+        printb!(&proc.name);
+        print!("   ");
 
-                print!("\n");
+        match proc.mode {
+            Mode::Kernel => {
+                print!(" K");
+            }
+            Mode::User => {
+                print!(" U");
+            }
+            _ => {
+                print!(" ?");
             }
         }
+
+        // Base priority / current feedback queue level
+        print!(" ");
+        printn!(proc.priority as u64);
+        print!("/");
+        printn!(proc.level as u64);
+
+        print!(" (");
+        print!(proc.status.as_str());
+        print!(")\n");
     }
 
     fn get_next_pid(&mut self) -> usize {
+        if self.next_free_pid == RESERVED_PID {
+            self.next_free_pid += 1;
+        }
+
         self.next_free_pid += 1;
         self.next_free_pid - 1
     }
 
     pub fn get_current_pid(&self) -> usize {
-        self.current_pid
+        match self.processes.get(self.current) {
+            Some(Some(proc)) => proc.id,
+            _ => RESERVED_PID,
+        }
     }
 
-    pub unsafe fn new_process(
-        &mut self,
-        name: [u8; 16],
-        mode: Mode,
-        entry: u64,
-        stack_top: u64,
-        cr3: u64,
-    ) -> usize {
+    pub unsafe fn new_process(&mut self, opts: SpawnOptions) -> usize {
+        let SpawnOptions {
+            mode,
+            entry,
+            stack_top,
+            ..
+        } = opts;
+
+        // Reuse a pooled kernel stack if one is free, else grow the pool.
+        let kernel_stack = match self.kstack_pool.pop() {
+            Some(stack) => stack,
+            None => match process::alloc_kernel_stack() {
+                Some(stack) => stack,
+                None => return RESERVED_PID,
+            },
+        };
+
         let pid: usize = self.get_next_pid();
+        let parent = self.get_current_pid();
+        let (stdin, stdout) = self.stdio();
+        let trace = self.trace();
+
+        // Take the first free slot, or append one when the table is full.
+        let pos = match self.processes.iter().position(|p| p.is_none()) {
+            Some(pos) => pos,
+            None => {
+                self.processes.push(None);
+                self.processes.len() - 1
+            }
+        };
 
-        let mut proc: Option<Process> = None;
-        let mut pos: usize = 0;
-        let mut found_slot = false;
+        let mut proc = Some(Process::new(pid, kernel_stack, parent, &opts));
 
-        for slot in self.processes.iter() {
-            if slot.is_some() {
-                pos += 1;
-                continue;
-            }
-            found_slot = true;
-            break;
+        if let Some(port) = proc.as_mut().and_then(|p| p.ports.first_mut()) {
+            port.id = self.next_port_id;
+            self.next_port_id += 1;
         }
 
-        if !found_slot || pos >= MAX_PROCESSES {
-            return 0xff;
+        // Standard input and output are inherited from the spawning process,
+        // and so is syscall tracing.
+        if let Some(p) = proc.as_mut() {
+            p.stdin = stdin;
+            p.stdout = stdout;
+            p.trace = trace;
         }
+        self.attach_stdio(stdin, stdout);
 
         {
-            // Process::new takes `slot` (not pid) so the kernel stack pool is
-            // indexed by slot position — each slot always owns the same stack
-            // entry regardless of how many times it has been recycled.
-            proc = Some(Process::new(pid, pos, name, mode, entry, stack_top, cr3));
-
             unsafe {
-                let kstack_top = proc.as_mut().unwrap().kernel_stack.as_ptr().add(32768) as u64;
+                let kstack_top = proc.as_mut().unwrap().kernel_stack.as_ptr().add(STACK_SIZE) as u64;
                 let mut sp = kstack_top;
 
                 sp &= !0xF;
@@ -329,8 +1793,8 @@ impl Scheduler {
 
                 match mode {
                     Mode::User => {
-                        code_segment = 0x1b;
-                        stack_segment = 0x23;
+                        code_segment = USER_CS;
+                        stack_segment = USER_SS;
                     }
                     _ => {
                         code_segment = 0x08;
@@ -386,6 +1850,52 @@ impl Scheduler {
 
         pid
     }
+
+    /// Start a thread of the running user process at `entry`, with `arg` in
+    /// RDI.  It shares the page tables of the process and runs on the first
+    /// free stack slot below the main thread's.  Returns the new PID, or None
+    /// if the caller is not a user process or all MAX_THREADS slots are taken.
+    pub unsafe fn new_thread(&mut self, entry: u64, arg: u64) -> Option<usize> {
+        let caller = self.processes.get(self.current)?.as_ref()?;
+
+        if !matches!(caller.mode, Mode::User) {
+            return None;
+        }
+
+        let (group, name, cr3, priority) = (caller.group, caller.name, caller.cr3, caller.priority);
+
+        let stack_top = (1..MAX_THREADS).map(|i| thread_stack(i).end).find(|&top| {
+            !self.processes.iter().flatten().any(|p| {
+                p.group == group && p.stack_top <= top && p.stack_top > top - THREAD_STACK_SIZE
+            })
+        })?;
+
+        // The entry function is entered as if called, with a null return
+        // address: a thread must end through the thread exit syscall.
+        let sp = stack_top - 8;
+        (sp as *mut u64).write(0);
+
+        let pid = self.new_process(SpawnOptions {
+            name,
+            mode: Mode::User,
+            entry,
+            stack_top: sp,
+            cr3,
+            priority,
+            image_start: 0,
+            image_end: 0,
+        });
+        if pid == RESERVED_PID {
+            return None;
+        }
+
+        let thread = self.get_mut(pid)?;
+        thread.group = group;
+        thread.parent = group;
+        (*(thread.last_rsp as *mut InterruptFrame)).rdi = arg;
+
+        Some(pid)
+    }
 }
 
 #[no_mangle]
@@ -398,21 +1908,125 @@ pub unsafe extern "C" fn scheduler_schedule(mut old: *mut u64) -> *mut u64 {
 
     if let Some(mut sch) = SCHEDULER.try_lock() {
         old = sch.schedule(old);
+        sch.deliver_signal(old as *mut InterruptFrame);
+    }
+
+    // A Ctrl-C from gdb stops the task about to run on its first instruction.
+    if crate::gdb::break_requested() {
+        (*(old as *mut InterruptFrame)).rflags |= crate::gdb::RFLAGS_TF;
     }
 
     crate::input::port::write(0x20, 0x20);
     old
 }
 
-pub unsafe fn kill(pid: usize) {
+pub unsafe fn exit(pid: usize, code: u64) {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        sch.exit(pid, code);
+    }
+}
+
+pub unsafe fn exit_process(pid: usize, code: u64) {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        sch.exit_process(pid, code);
+    }
+}
+
+/// Returns false if there is no such process or signal (or the scheduler is busy).
+pub unsafe fn signal(pid: usize, sig: u8) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.signal(pid, sig);
+    }
+
+    false
+}
+
+pub unsafe fn set_action(pid: usize, sig: u8, action: SigAction) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.set_action(pid, sig, action);
+    }
+
+    false
+}
+
+pub unsafe fn set_alarm(pid: usize, at: u64) -> u64 {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.set_alarm(pid, at);
+    }
+
+    0
+}
+
+/// Called by the syscall gate with its saved frame, after the syscall has
+/// run and interrupts are off again.
+pub unsafe extern "C" fn deliver_signal(frame: *mut InterruptFrame) {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        sch.deliver_signal(frame);
+    }
+}
+
+pub unsafe fn sigreturn(frame: *mut InterruptFrame) -> Option<u64> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.sigreturn(frame);
+    }
+
+    None
+}
+
+/// Block the running process until `child` (or any child, for None) exits.
+/// Returns the PID and exit code of the child, or None if the running process
+/// has no such child.
+pub unsafe fn wait(child: Option<usize>) -> Option<(usize, u64)> {
+    block_on(|sch| sch.try_wait(child)).ok()
+}
+
+/// Block the running thread until thread `tid` of the same process exits.
+/// Returns its exit code, or None if there is no such thread.
+pub unsafe fn join(tid: usize) -> Option<u64> {
+    block_on(|sch| sch.try_join(tid)).ok()
+}
+
+/// Block the running process until the futex at physical address `key` is
+/// woken, unless `word` no longer holds `expected`.  A non-zero `deadline`
+/// is the PIT tick at which to give up.
+pub unsafe fn futex_wait(key: u64, word: *const u32, expected: u32, deadline: u64) -> FutexWait {
+    let mut queued = false;
+
+    block_on(|sch| sch.try_futex_wait(key, word, expected, deadline, &mut queued))
+        .unwrap_or(FutexWait::Woken)
+}
+
+pub unsafe fn futex_wake(key: u64, count: usize) -> usize {
     if let Some(mut sch) = SCHEDULER.try_lock() {
-        sch.kill(pid);
+        return sch.futex_wake(key, count);
+    }
+
+    0
+}
+
+/// Retry `attempt` until it yields a result.  Ok(None) means it blocked the
+/// running process, which is then halted until something makes it Ready.
+unsafe fn block_on<T, E>(
+    mut attempt: impl FnMut(&mut Scheduler) -> Result<Option<T>, E>,
+) -> Result<T, E> {
+    loop {
+        if let Some(mut sch) = SCHEDULER.try_lock() {
+            match attempt(&mut sch) {
+                Ok(Some(result)) => return Ok(result),
+                Ok(None) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        // Blocked now: the next PIT tick switches away until this process is
+        // made Ready again.
+        core::arch::asm!("hlt");
     }
 }
 
-pub unsafe fn block(pid: usize, msg: Message) {
+pub unsafe fn block(pid: usize) {
     if let Some(mut sch) = SCHEDULER.try_lock() {
-        sch.block(pid, msg);
+        sch.block(pid);
     }
 }
 
@@ -427,8 +2041,7 @@ pub unsafe fn wake(pid: usize) {
 /// a TOCTOU race with PIT preemption between the two operations.
 pub unsafe fn sleep_current(until_tick: u64) {
     if let Some(mut sch) = SCHEDULER.try_lock() {
-        let slot = sch.current_pid;
-        sch.sleep_process(slot, until_tick);
+        sch.sleep_process(RESERVED_PID, until_tick);
     }
 }
 
@@ -438,12 +2051,6 @@ pub unsafe fn idle(pid: usize) {
     }
 }
 
-pub unsafe fn crash(pid: usize) {
-    if let Some(mut sch) = SCHEDULER.try_lock() {
-        sch.set_status(pid, Status::Crashed);
-    }
-}
-
 pub unsafe fn list_processes() {
     if let Some(sch) = SCHEDULER.try_lock() {
         sch.list_processes();
@@ -451,30 +2058,41 @@ pub unsafe fn list_processes() {
 }
 
 /// Serialise running tasks into a flat byte buffer for the ScListTasks syscall.
-/// Each entry is 20 bytes: id(1) mode(1) status(1) _pad(1) name(16).
+/// Each entry is 24 bytes: id(1) mode(1) status(1) prio(1) name(16) pid(4).
+/// id:     the PID if it fits in a byte below 0xff, else 0xff; pid is exact
 /// mode:   0=Kernel  1=User
-/// status: 0=Ready 1=Running 2=Idle 3=Blocked 4=Crashed 5=Dead
+/// status: 0=Ready 1=Running 2=Idle 3=Blocked 5=Dead
+/// prio:   base priority in bits 0-3, current feedback queue level in bits 4-7
+/// With `stats`, entries are 48 bytes: the same 24, then cpu_ticks(8)
+/// blocked_ticks(8) switches(8).
 /// Returns the number of entries written.
-pub fn list_tasks(buf: *mut u8, max: usize) -> usize {
+pub fn list_tasks(buf: *mut u8, max: usize, stats: bool) -> usize {
+    let size = if stats { 48 } else { 24 };
+
     if let Some(sch) = SCHEDULER.try_lock() {
         let mut count = 0usize;
         for process in sch.processes.iter() {
             if count >= max { break; }
             if let Some(p) = process {
-                let off = count * 20;
+                let off = count * size;
                 unsafe {
-                    *buf.add(off)     = p.id as u8;
+                    *buf.add(off)     = p.id.min(RESERVED_PID) as u8;
                     *buf.add(off + 1) = match p.mode { Mode::Kernel => 0, _ => 1 };
                     *buf.add(off + 2) = match p.status {
                         Status::Ready   => 0,
                         Status::Running => 1,
                         Status::Idle    => 2,
                         Status::Blocked => 3,
-                        Status::Crashed => 4,
                         Status::Dead    => 5,
                     };
-                    *buf.add(off + 3) = 0;
+                    *buf.add(off + 3) = p.priority | (p.level << 4);
                     for i in 0..16 { *buf.add(off + 4 + i) = p.name[i]; }
+                    (buf.add(off + 20) as *mut u32).write_unaligned(p.id as u32);
+                    if stats {
+                        (buf.add(off + 24) as *mut u64).write_unaligned(p.cpu_ticks);
+                        (buf.add(off + 32) as *mut u64).write_unaligned(p.blocked_ticks);
+                        (buf.add(off + 40) as *mut u64).write_unaligned(p.switches);
+                    }
                 }
                 count += 1;
             }
@@ -485,20 +2103,129 @@ pub fn list_tasks(buf: *mut u8, max: usize) -> usize {
     }
 }
 
-pub unsafe fn new_process(
-    name: [u8; 16],
-    mode: Mode,
-    entry: u64,
-    stack_top: u64,
-    cr3: u64,
-) -> usize {
+/// Snapshot the CPU accounting of every process.  Empty if the scheduler is busy.
+pub fn task_stats() -> Vec<TaskStats> {
+    let Some(sch) = SCHEDULER.try_lock() else {
+        return Vec::new();
+    };
+
+    sch.processes
+        .iter()
+        .flatten()
+        .map(|p| TaskStats {
+            pid: p.id,
+            name: p.name,
+            status: p.status,
+            cpu_ticks: p.cpu_ticks,
+            blocked_ticks: p.blocked_ticks,
+            switches: p.switches,
+        })
+        .collect()
+}
+
+/// Snapshot the layout of the running process.  None if the scheduler is busy.
+pub fn core_layout() -> Option<CoreLayout> {
+    let sch = SCHEDULER.try_lock()?;
+    let Some(Some(current)) = sch.processes.get(sch.current) else {
+        return None;
+    };
+    let leader = sch
+        .processes
+        .iter()
+        .flatten()
+        .find(|p| p.id == current.group)?;
+
+    Some(CoreLayout {
+        pid: current.id,
+        parent: current.parent,
+        name: leader.name,
+        image_start: leader.image_start,
+        image_end: leader.image_end,
+        stack_top: current.stack_top,
+    })
+}
+
+/// Snapshot the process table into `out`; returns how many entries were
+/// written.  0 if the scheduler is busy.
+pub fn debug_threads(out: &mut [DebugThread]) -> usize {
+    let Some(sch) = SCHEDULER.try_lock() else {
+        return 0;
+    };
+
+    let mut count = 0;
+
+    for (slot, p) in sch.processes.iter().enumerate() {
+        let Some(p) = p else {
+            continue;
+        };
+        let Some(entry) = out.get_mut(count) else {
+            break;
+        };
+
+        *entry = DebugThread {
+            pid: p.id,
+            name: p.name,
+            status: p.status,
+            cr3: p.cr3,
+            last_rsp: p.last_rsp,
+            running: slot == sch.current,
+        };
+        count += 1;
+    }
+
+    count
+}
+
+pub unsafe fn new_process(opts: SpawnOptions) -> usize {
     if let Some(mut sch) = SCHEDULER.try_lock() {
-        return sch.new_process(name, mode, entry, stack_top, cr3);
+        return sch.new_process(opts);
     }
 
     0xff
 }
 
+/// Returns None if the caller cannot start a thread (or the scheduler is busy).
+pub unsafe fn new_thread(entry: u64, arg: u64) -> Option<usize> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.new_thread(entry, arg);
+    }
+
+    None
+}
+
+/// Returns false if `pid` does not exist (or the scheduler is busy).
+pub unsafe fn set_priority(pid: usize, priority: u8) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.set_priority(pid, priority);
+    }
+
+    false
+}
+
+pub unsafe fn trace() -> Option<Trace> {
+    if let Some(sch) = SCHEDULER.try_lock() {
+        return sch.trace();
+    }
+
+    None
+}
+
+pub unsafe fn set_trace(pid: usize, trace: Option<Trace>) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.set_trace(pid, trace);
+    }
+
+    false
+}
+
+pub unsafe fn get_priority(pid: usize) -> Option<u8> {
+    if let Some(sch) = SCHEDULER.try_lock() {
+        return sch.get_priority(pid);
+    }
+
+    None
+}
+
 pub unsafe fn get_current_pid() -> usize {
     if let Some(sch) = SCHEDULER.try_lock() {
         return sch.get_current_pid();
@@ -511,10 +2238,6 @@ pub unsafe fn set_shell_pid(pid: usize) {
     SHELL_PID = pid;
 }
 
-pub unsafe fn get_shell_pid() -> usize {
-    SHELL_PID
-}
-
 pub unsafe fn push_msg(pid: usize, msg: Message) {
     if let Some(mut sch) = SCHEDULER.try_lock() {
         sch.push_msg(pid, msg)
@@ -528,3 +2251,175 @@ pub unsafe fn pop_msg(pid: usize) -> Option<Message> {
 
     None
 }
+
+pub unsafe fn port_create(pid: usize) -> Option<usize> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.port_create(pid);
+    }
+
+    None
+}
+
+pub unsafe fn port_destroy(pid: usize, port: usize) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.port_destroy(pid, port);
+    }
+
+    false
+}
+
+pub unsafe fn send(port: usize, msg: Message) -> Result<(), IpcError> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.send(port, msg);
+    }
+
+    Err(IpcError::QueueFull)
+}
+
+/// Block the running thread until a message arrives on `port`.
+pub unsafe fn receive(port: usize) -> Result<Message, IpcError> {
+    block_on(|sch| sch.try_receive(port))
+}
+
+/// Send `request` to `port` and block the running thread until the reply.
+pub unsafe fn call(port: usize, request: Message) -> Result<Vec<u8>, IpcError> {
+    let mut request = Some(request);
+
+    block_on(|sch| sch.try_call(port, &mut request))
+}
+
+pub unsafe fn register_service(pid: usize, name: [u8; NAME_LEN], port: usize) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.register_service(pid, name, port);
+    }
+
+    false
+}
+
+pub unsafe fn unregister_service(pid: usize, name: [u8; NAME_LEN]) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.unregister_service(pid, name);
+    }
+
+    false
+}
+
+/// Find the port registered for `name`.  With `wait` set, the running process
+/// is blocked until the name appears, a signal arrives, or the PIT tick
+/// `deadline` (if non-zero) has passed.
+pub unsafe fn lookup_service(name: [u8; NAME_LEN], wait: bool, deadline: u64) -> Option<usize> {
+    let mut queued = false;
+
+    block_on(|sch| sch.try_lookup(name, wait, deadline, &mut queued)).ok()
+}
+
+/// Wait until one of `sources` is ready, as syscall 0x5C.  Returns how many
+/// are; 0 if `wait` is clear and none is, or if the PIT tick `deadline` (if
+/// non-zero) passes first.
+pub unsafe fn poll(
+    sources: &mut [PollSource],
+    wait: bool,
+    deadline: u64,
+) -> Result<usize, IpcError> {
+    let mut queued = false;
+
+    block_on(|sch| sch.try_poll(sources, wait, deadline, &mut queued))
+}
+
+/// Wake processes polling a source of the kinds in `mask`.  Called by IRQ
+/// handlers: if the scheduler is locked by the code they interrupted, the
+/// wakeup is left to the next PIT tick.
+pub fn wake_pollers(mask: u64) {
+    match SCHEDULER.try_lock() {
+        Some(mut sch) => sch.wake_pollers(mask),
+        None => {
+            POLL_WAKE.fetch_or(mask, Ordering::Release);
+        }
+    }
+}
+
+pub unsafe fn pipe_open(pid: usize) -> Option<u64> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.pipe_open(pid);
+    }
+
+    None
+}
+
+pub unsafe fn set_stdio(pid: usize, stdin: Option<u64>, stdout: Option<u64>) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.set_stdio(pid, stdin, stdout);
+    }
+
+    false
+}
+
+pub unsafe fn fd_install(pid: usize, file: File) -> Option<usize> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.fd_install(pid, file);
+    }
+
+    None
+}
+
+pub unsafe fn fd_get(pid: usize, fd: usize) -> Option<File> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.fd_get(pid, fd);
+    }
+
+    None
+}
+
+pub unsafe fn fd_update(pid: usize, fd: usize, file: File) {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        sch.fd_update(pid, fd, file);
+    }
+}
+
+pub unsafe fn fd_close(pid: usize, fd: usize) -> bool {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.fd_close(pid, fd);
+    }
+
+    false
+}
+
+/// Standard input and output of the running process; None is the console.
+pub fn stdio() -> (Option<u64>, Option<u64>) {
+    let Some(sch) = SCHEDULER.try_lock() else {
+        return (None, None);
+    };
+
+    sch.stdio()
+}
+
+/// Block the running process until its standard input pipe has data, and
+/// read it into `buf`.  Returns 0 at EOF.
+pub unsafe fn pipe_read(buf: &mut [u8]) -> Result<usize, IpcError> {
+    block_on(|sch| sch.try_pipe_read(buf))
+}
+
+/// Write all of `data` to the standard output pipe of the running process,
+/// blocking while the pipe is full.
+pub unsafe fn pipe_write(data: &[u8]) -> Result<(), IpcError> {
+    let mut written = 0;
+
+    block_on(|sch| sch.try_pipe_write(data, &mut written))
+}
+
+/// Snapshot of the name service registry, in registration order.
+pub fn services() -> Vec<Service> {
+    let Some(sch) = SCHEDULER.try_lock() else {
+        return Vec::new();
+    };
+
+    sch.services.clone()
+}
+
+pub unsafe fn reply(pid: usize, token: u64, data: Vec<u8>) -> Result<(), IpcError> {
+    if let Some(mut sch) = SCHEDULER.try_lock() {
+        return sch.reply(pid, token, data);
+    }
+
+    Err(IpcError::NoPort)
+}
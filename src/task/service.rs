@@ -0,0 +1,34 @@
+//! Name service.
+//!
+//! A process registers a well-known name ("net", "fs", "gui") under one of
+//! its IPC ports, so clients can find it without knowing its PID or the order
+//! services were started in.  The registry lives in the scheduler, which drops
+//! a name as soon as its port is destroyed or its process exits.
+
+/// Longest service name, in bytes.
+pub const NAME_LEN: usize = 16;
+
+/// Names the registry holds at most.
+pub const MAX_SERVICES: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Service {
+    /// NUL-padded name.
+    pub name: [u8; NAME_LEN],
+    pub port: usize,
+    /// PID of the process owning the port.
+    pub pid: usize,
+}
+
+/// Check a service name and NUL-pad it.  Names are 1 to NAME_LEN printable
+/// ASCII characters without spaces.
+pub fn parse_name(name: &[u8]) -> Option<[u8; NAME_LEN]> {
+    if name.is_empty() || name.len() > NAME_LEN || !name.iter().all(|b| b.is_ascii_graphic()) {
+        return None;
+    }
+
+    let mut padded = [0; NAME_LEN];
+    padded[..name.len()].copy_from_slice(name);
+
+    Some(padded)
+}
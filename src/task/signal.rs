@@ -0,0 +1,116 @@
+//! POSIX-style signals.
+//!
+//! A signal sent to a process either runs its default action right away
+//! (terminate, or nothing for SIGCHLD), is dropped when ignored, or is left
+//! pending until the process next returns to ring 3.  Delivery then pushes a
+//! `SignalFrame` on the user stack and enters the handler; the handler returns
+//! into the restorer stub, which calls sigreturn to resume the interrupted code.
+use super::context::InterruptFrame;
+
+pub const SIGINT: u8 = 2;
+/// Raised by an invalid opcode in user code.
+pub const SIGILL: u8 = 4;
+/// Raised by a breakpoint or single step in user code with no debugger attached.
+pub const SIGTRAP: u8 = 5;
+pub const SIGKILL: u8 = 9;
+pub const SIGUSR1: u8 = 10;
+/// Raised by a page fault or protection fault in user code.
+pub const SIGSEGV: u8 = 11;
+pub const SIGUSR2: u8 = 12;
+pub const SIGALRM: u8 = 14;
+pub const SIGTERM: u8 = 15;
+pub const SIGCHLD: u8 = 17;
+
+/// Size of the per-process action table; pending and masked signals are bits
+/// of a u32 indexed by signal number.
+pub const NSIG: usize = 32;
+
+/// Handler value selecting the default action.
+pub const SIG_DFL: u64 = 0;
+
+/// Handler value discarding the signal.
+pub const SIG_IGN: u64 = 1;
+
+/// Signal names without the SIG prefix, as accepted by `kill -NAME`.
+const NAMES: [(&[u8], u8); 7] = [
+    (b"INT", SIGINT),
+    (b"KILL", SIGKILL),
+    (b"USR1", SIGUSR1),
+    (b"USR2", SIGUSR2),
+    (b"ALRM", SIGALRM),
+    (b"TERM", SIGTERM),
+    (b"CHLD", SIGCHLD),
+];
+
+/// What a process does when it receives a signal.  `restorer` is a user stub
+/// that calls sigreturn; the handler returns into it.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SigAction {
+    pub handler: u64,
+    pub restorer: u64,
+}
+
+impl SigAction {
+    pub const DEFAULT: SigAction = SigAction {
+        handler: SIG_DFL,
+        restorer: 0,
+    };
+}
+
+/// Pushed on the user stack when a handler is entered.  The handler is called
+/// with RSP pointing at `restorer`, as if `restorer` had called it.
+#[repr(C)]
+pub struct SignalFrame {
+    pub restorer: u64,
+    pub signo: u64,
+    /// Signal mask to restore on sigreturn.
+    pub mask: u64,
+    pub regs: InterruptFrame,
+}
+
+pub fn is_valid(sig: u8) -> bool {
+    NAMES.iter().any(|&(_, s)| s == sig)
+}
+
+/// True if the default action ends the process.  Only SIGCHLD is ignored.
+pub fn default_terminates(sig: u8) -> bool {
+    sig != SIGCHLD
+}
+
+/// Exit code of a process ended by `sig`, as a POSIX shell reports it.
+pub fn exit_code(sig: u8) -> u64 {
+    128 + sig as u64
+}
+
+pub fn bit(sig: u8) -> u32 {
+    1 << sig
+}
+
+/// Parse a signal given as a number, a name (`TERM`) or a prefixed name
+/// (`SIGTERM`), in any case.
+pub fn parse(input: &[u8]) -> Option<u8> {
+    let name = match input.get(..3) {
+        Some(prefix) if prefix.eq_ignore_ascii_case(b"SIG") => &input[3..],
+        _ => input,
+    };
+
+    if let Some(&(_, sig)) = NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        return Some(sig);
+    }
+
+    let mut value: u8 = 0;
+
+    for &b in input {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        value = value.checked_mul(10)?.checked_add(b - b'0')?;
+    }
+
+    if is_valid(value) {
+        Some(value)
+    } else {
+        None
+    }
+}
@@ -1,4 +1,5 @@
 use crate::input::port;
+use alloc::vec::Vec;
 use core::fmt::{self, Write};
 use core::sync::atomic::{AtomicBool, Ordering};
 use spin::mutex::Mutex;
@@ -39,6 +40,22 @@ pub fn get_writer() -> Option<spin::MutexGuard<'static, Writer>> {
     }
 }
 
+/// Collects what process `pid` prints from now on instead of drawing it, until `end_capture`.
+/// The shell uses it to feed the output of a built-in command into a pipe.
+pub fn start_capture(pid: usize) {
+    if let Some(mut writer) = get_writer() {
+        writer.capture = Some((pid, Vec::new()));
+    }
+}
+
+/// Stops collecting and returns what was printed since `start_capture`.
+pub fn end_capture() -> Vec<u8> {
+    get_writer()
+        .and_then(|mut writer| writer.capture.take())
+        .map(|(_, output)| output)
+        .unwrap_or_default()
+}
+
 /// VGA text mode colors (16 colors).
 #[allow(dead_code)]
 #[derive(Copy, Clone)]
@@ -101,6 +118,8 @@ pub struct Writer {
     row_pos: usize,
     color_code: ColorCode,
     buffer: *mut Buffer,
+    /// PID whose output is collected instead of drawn, and the output so far.
+    capture: Option<(usize, Vec<u8>)>,
 }
 
 impl Write for Writer {
@@ -119,6 +138,7 @@ impl Writer {
             row_pos: 0,
             color_code: ColorCode::new(Color::White, Color::Black),
             buffer: BUFFER_ADDRESS as *mut _,
+            capture: None,
         }
     }
 
@@ -152,6 +172,13 @@ impl Writer {
     }
     /// Write one (1) byte to the display.
     pub fn write_byte(&mut self, byte: u8) {
+        if let Some((pid, output)) = self.capture.as_mut() {
+            if *pid == unsafe { crate::task::scheduler::get_current_pid() } {
+                output.push(byte);
+                return;
+            }
+        }
+
         match byte {
             b'\n' => self.new_line(),
             (0x08 | b'\r') => {
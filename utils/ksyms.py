@@ -0,0 +1,101 @@
+#!/usr/bin/env python3
+# Embed the kernel's function symbols into its .ksyms section, so that panics
+# and kernel faults print backtraces with function names (src/backtrace.rs).
+#
+# Usage: ksyms.py <kernel.elf>    (set NM to use another nm, e.g. llvm-nm)
+
+import os
+import re
+import struct
+import subprocess
+import sys
+
+# Rust legacy mangling leaves a hash after the demangled path.
+HASH = re.compile(r"::h[0-9a-f]{16}$")
+
+
+def read_symbols(path):
+    nm = os.environ.get("NM", "nm")
+    out = subprocess.run(
+        [nm, "-n", "-S", "-C", "--defined-only", path],
+        check=True, capture_output=True, text=True,
+    ).stdout
+
+    symbols = []
+    for line in out.splitlines():
+        # "addr [size] kind name"; demangled names may contain spaces.
+        fields = line.split(None, 2)
+        if len(fields) < 3:
+            continue
+        if len(fields[1]) == 1:
+            (addr, kind, name), size = fields, "0"
+        else:
+            addr, size, rest = fields
+            kind, _, name = rest.partition(" ")
+
+        if kind not in "tT":
+            continue
+
+        name = HASH.sub("", name).encode()[:255]
+        symbols.append((int(addr, 16), int(size, 16), name))
+
+    return symbols
+
+
+def find_section(data, name):
+    # ELF64 little endian: section header table offset, entry size, count and
+    # the index of the section name table.
+    shoff, = struct.unpack_from("<Q", data, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", data, 0x3A)
+
+    def header(i):
+        return struct.unpack_from("<IIQQQQ", data, shoff + i * shentsize)
+
+    strtab = header(shstrndx)[4]
+    for i in range(shnum):
+        sh_name, _, _, _, sh_offset, sh_size = header(i)
+        end = data.index(b"\0", strtab + sh_name)
+        if data[strtab + sh_name:end] == name:
+            return sh_offset, sh_size
+
+    return None
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit("usage: ksyms.py <kernel.elf>")
+
+    path = sys.argv[1]
+    with open(path, "rb") as f:
+        data = bytearray(f.read())
+
+    section = find_section(data, b".ksyms")
+    if section is None:
+        sys.exit(f"{path}: no .ksyms section")
+    offset, size = section
+
+    table = bytearray()
+    count = 0
+    symbols = read_symbols(path)
+
+    for addr, sym_size, name in symbols:
+        record = struct.pack("<QIB", addr, sym_size, len(name)) + name
+        if 8 + len(table) + len(record) > size:
+            print(f"ksyms: .ksyms full, {len(symbols) - count} symbols left out "
+                  f"(raise KSYMS_SIZE)", file=sys.stderr)
+            break
+
+        table += record
+        count += 1
+
+    blob = b"KSYM" + struct.pack("<I", count) + table
+    data[offset:offset + size] = blob.ljust(size, b"\0")
+
+    with open(path, "wb") as f:
+        f.write(data)
+
+    print(f"ksyms: {count} symbols, {len(blob)} of {size} bytes")
+
+
+if __name__ == "__main__":
+    main()